@@ -5,6 +5,7 @@
 
 pub mod adapter;
 pub mod flash;
+pub mod reset;
 pub mod shared_bus;
 
 /// Set the configuration of a peripheral driver.
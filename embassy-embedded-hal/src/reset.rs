@@ -0,0 +1,80 @@
+//! Hardware reset of external devices through a GPIO.
+
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital::OutputPin;
+
+/// Reset a device, such as an Ethernet PHY, through its active-low RST# pin.
+///
+/// Drives `rst` low for `assert_us`, releases it and waits `settle_us` before returning. A hardware
+/// reset also recovers a PHY that no longer answers on its management interface, which a soft reset
+/// through its registers can't. Datasheets typically ask for 100us to 10ms of reset assertion and
+/// up to a few milliseconds before the registers are accessible; check the device's.
+///
+/// A hardware reset restores all registers to their strapped defaults, so run it before the
+/// driver initializes the device, e.g. before `phy_init` of an Ethernet PHY.
+pub fn hardware_reset<P: OutputPin, D: DelayNs>(
+    rst: &mut P,
+    delay: &mut D,
+    assert_us: u32,
+    settle_us: u32,
+) -> Result<(), P::Error> {
+    rst.set_low()?;
+    delay.delay_us(assert_us);
+    rst.set_high()?;
+    delay.delay_us(settle_us);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Pin {
+        levels: Vec<bool>,
+    }
+
+    impl embedded_hal_1::digital::ErrorType for Pin {
+        type Error = core::convert::Infallible;
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(true);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Delay {
+        delays_us: Vec<u32>,
+    }
+
+    impl DelayNs for Delay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delays_us.push(ns / 1000);
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            self.delays_us.push(us);
+        }
+    }
+
+    #[test]
+    fn hardware_reset_sequence() {
+        let mut rst = Pin::default();
+        let mut delay = Delay::default();
+        hardware_reset(&mut rst, &mut delay, 500, 2000).unwrap();
+        assert_eq!(rst.levels, [false, true]);
+        assert_eq!(delay.delays_us, [500, 2000]);
+    }
+}
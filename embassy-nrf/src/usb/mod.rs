@@ -280,21 +280,7 @@ impl<'d, T: Instance, V: VbusDetect> driver::Bus for Bus<'d, T, V> {
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
-        let regs = T::regs();
-        if ep_addr.index() == 0 {
-            if stalled {
-                regs.tasks_ep0stall().write_value(1);
-            }
-        } else {
-            regs.epstall().write(|w| {
-                w.set_ep(ep_addr.index() as u8 & 0b111);
-                w.set_io(match ep_addr.direction() {
-                    Direction::In => vals::Io::IN,
-                    Direction::Out => vals::Io::OUT,
-                });
-                w.set_stall(stalled);
-            });
-        }
+        set_stalled::<T>(ep_addr, stalled);
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
@@ -440,6 +426,24 @@ impl EndpointDir for Out {
     }
 }
 
+fn set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    let regs = T::regs();
+    if ep_addr.index() == 0 {
+        if stalled {
+            regs.tasks_ep0stall().write_value(1);
+        }
+    } else {
+        regs.epstall().write(|w| {
+            w.set_ep(ep_addr.index() as u8 & 0b111);
+            w.set_io(match ep_addr.direction() {
+                Direction::In => vals::Io::IN,
+                Direction::Out => vals::Io::OUT,
+            });
+            w.set_stall(stalled);
+        });
+    }
+}
+
 /// USB endpoint.
 pub struct Endpoint<'d, T: Instance, Dir> {
     _phantom: PhantomData<(&'d mut T, Dir)>,
@@ -463,6 +467,11 @@ impl<'d, T: Instance, Dir: EndpointDir> driver::Endpoint for Endpoint<'d, T, Dir
     async fn wait_enabled(&mut self) {
         self.wait_enabled_state(true).await
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled::<T>(self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 #[allow(private_bounds)]
@@ -0,0 +1,416 @@
+//! PIO backed MDIO (clause 22) management interface for Ethernet PHYs
+//!
+//! The `StationManagementAsync` trait and the PHY drivers built on it live in the `eth` module of
+//! `embassy-stm32`, which only builds for STM32 targets, so [`PioMdio`] can't implement it from
+//! this crate. Its [`read`](PioMdio::read) and [`write`](PioMdio::write) have the same shape
+//! though, so a bus trait of a portable PHY driver is implemented with a thin wrapper:
+//!
+//! ```rust,ignore
+//! struct Bus<'d>(PioMdio<'d, PIO0, 0>);
+//!
+//! impl phy_driver::MdioBus for Bus<'_> {
+//!     async fn read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+//!         self.0.read(phy_addr, reg).await
+//!     }
+//!
+//!     async fn write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+//!         self.0.write(phy_addr, reg, val).await
+//!     }
+//! }
+//! ```
+
+use crate::gpio::{Level, Pull};
+use crate::pio::{
+    Common, Config, Direction, Instance, LoadedProgram, PioPin, ShiftConfig, ShiftDirection, StateMachine,
+};
+use crate::pio_programs::clock_divider::calculate_pio_clock_divider;
+use crate::Peri;
+
+/// This struct represents an MDIO program loaded into pio instruction memory.
+pub struct PioMdioProgram<'a, PIO: Instance> {
+    prg: LoadedProgram<'a, PIO>,
+}
+
+impl<'a, PIO: Instance> PioMdioProgram<'a, PIO> {
+    /// Load the program into the given pio
+    pub fn new(common: &mut Common<'a, PIO>) -> Self {
+        Self {
+            prg: common.load_program(&program()),
+        }
+    }
+}
+
+// The assembled MDIO program.
+fn program() -> pio::Program<32> {
+    pio::pio_asm!(
+        r#"
+            ; Side-set pin 0 is MDC, SET/OUT/IN pin 0 is MDIO.
+            ; Every MDC period is 4 cycles: 2 low, 2 high.
+            ; The PHY samples MDIO on the rising edge of MDC and drives read data after it.
+            .side_set 1
+
+            ; Each transaction is two words: a read flag (0 = write) and the frame, MSB first.
+            .wrap_target
+            start:
+                pull block          side 0
+                mov y, osr          side 0
+                pull block          side 0
+                set pindirs, 1      side 0
+                set pins, 1         side 0
+                set x, 31           side 0
+
+            ; 32 bits of preamble
+            preamble:
+                nop                 side 0 [1]
+                jmp x-- preamble    side 1 [1]
+
+            ; ST, OP, PHYAD and REGAD
+                set x, 13           side 0
+            header:
+                out pins, 1         side 0 [1]
+                jmp x-- header      side 1 [1]
+                jmp !y write        side 0
+
+            ; Release MDIO for the turnaround, then sample 16 data bits
+                set pindirs, 0      side 0
+                nop                 side 1 [1]
+                set x, 15           side 0 [1]
+                nop                 side 1 [1]
+            read_bit:
+                nop                 side 0 [1]
+                in pins, 1          side 1
+                jmp x-- read_bit    side 1
+                push block          side 0
+                jmp start           side 0
+
+            ; Drive the turnaround and 16 data bits, then release MDIO
+            write:
+                set x, 17           side 0
+            write_bit:
+                out pins, 1         side 0 [1]
+                jmp x-- write_bit   side 1 [1]
+                set pindirs, 0      side 0
+            .wrap
+        "#
+    )
+    .program
+}
+
+/// Pio backed MDIO bus master.
+///
+/// Drives clause 22 frames to the PHYs on the bus. MDIO floats during the turnaround of a read,
+/// so the pin's pull-up is enabled.
+pub struct PioMdio<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    // Replies of reads that were dropped while waiting for them, still to arrive in the RX FIFO.
+    stale_replies: u8,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioMdio<'d, PIO, SM> {
+    /// Configure a pio state machine to use the loaded MDIO program.
+    ///
+    /// `mdc_hz` is the MDC clock frequency, 802.3 allows up to 2.5 MHz.
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        mdc_pin: Peri<'d, impl PioPin>,
+        mdio_pin: Peri<'d, impl PioPin>,
+        program: &PioMdioProgram<'d, PIO>,
+        mdc_hz: u32,
+    ) -> Self {
+        let mdc = common.make_pio_pin(mdc_pin);
+        let mut mdio = common.make_pio_pin(mdio_pin);
+        mdio.set_pull(Pull::Up);
+
+        sm.set_pins(Level::Low, &[&mdc]);
+        sm.set_pins(Level::High, &[&mdio]);
+        sm.set_pin_dirs(Direction::Out, &[&mdc]);
+        sm.set_pin_dirs(Direction::In, &[&mdio]);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&program.prg, &[&mdc]);
+        cfg.set_set_pins(&[&mdio]);
+        cfg.set_out_pins(&[&mdio]);
+        cfg.set_in_pins(&[&mdio]);
+
+        let shift_cfg = ShiftConfig {
+            auto_fill: false,
+            direction: ShiftDirection::Left,
+            threshold: 32,
+        };
+        cfg.shift_in = shift_cfg;
+        cfg.shift_out = shift_cfg;
+
+        cfg.clock_divider = calculate_pio_clock_divider(4 * mdc_hz);
+
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+
+        Self { sm, stale_replies: 0 }
+    }
+
+    /// Read a clause 22 register.
+    ///
+    /// Dropping the future while it waits for the reply is safe: the next read discards the reply
+    /// first. Dropping it while it waits for room in the TX FIFO, i.e. while earlier writes are
+    /// still queued, is not, and desynchronizes the state machine from the frames.
+    pub async fn read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let (rx, tx) = self.sm.rx_tx();
+        while self.stale_replies > 0 {
+            rx.wait_pull().await;
+            self.stale_replies -= 1;
+        }
+        for word in read_command(phy_addr, reg) {
+            tx.wait_push(word).await;
+        }
+        self.stale_replies += 1;
+        let val = rx.wait_pull().await as u16;
+        self.stale_replies -= 1;
+        val
+    }
+
+    /// Write a clause 22 register.
+    ///
+    /// Returns once the frame is queued, not when it was sent. Like [`read`](Self::read), dropping
+    /// the future while it waits for room in the TX FIFO desynchronizes the state machine.
+    pub async fn write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        let tx = self.sm.tx();
+        for word in write_command(phy_addr, reg, val) {
+            tx.wait_push(word).await;
+        }
+    }
+}
+
+/// TX FIFO words of a read: the read flag and the frame.
+const fn read_command(phy_addr: u8, reg: u8) -> [u32; 2] {
+    [1, frame(0b10, phy_addr, reg, 0)]
+}
+
+/// TX FIFO words of a write: the write flag and the frame.
+const fn write_command(phy_addr: u8, reg: u8, val: u16) -> [u32; 2] {
+    [0, frame(0b01, phy_addr, reg, val)]
+}
+
+/// Clause 22 frame after the preamble: ST, OP, PHYAD, REGAD, TA and DATA.
+const fn frame(op: u32, phy_addr: u8, reg: u8, val: u16) -> u32 {
+    0b01 << 30 | op << 28 | (phy_addr as u32 & 0x1f) << 23 | (reg as u32 & 0x1f) << 18 | 0b10 << 16 | val as u32
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use pio::{
+        InSource, Instruction, InstructionOperands, JmpCondition, MovDestination, MovSource, OutDestination,
+        SetDestination,
+    };
+
+    use super::*;
+
+    #[test]
+    fn frames() {
+        // ST 01, OP 10, PHYAD 1, REGAD 2, TA 10
+        assert_eq!(frame(0b10, 1, 2, 0), 0b01_10_00001_00010_10 << 16);
+        // ST 01, OP 01, PHYAD 31, REGAD 31, TA 10, DATA 0xabcd
+        assert_eq!(frame(0b01, 31, 31, 0xabcd), 0b01_01_11111_11111_10 << 16 | 0xabcd);
+        // Out of range addresses are masked instead of corrupting the other fields
+        assert_eq!(frame(0b10, 0x21, 0x22, 0), frame(0b10, 1, 2, 0));
+    }
+
+    #[derive(Clone, Copy)]
+    enum PhyState {
+        // Counting the ones of the preamble.
+        Idle { ones: u32 },
+        // Receiving ST, OP, PHYAD and REGAD.
+        Header { bits: u32, count: u32 },
+        // Waiting for the first turnaround bit of a read, which nobody drives.
+        Turnaround { reg: usize },
+        // Driving the second turnaround bit and `count` data bits so far.
+        ReadData { val: u16, count: u32 },
+        // Receiving the turnaround and data bits of a write.
+        WriteData { reg: usize, bits: u32, count: u32 },
+    }
+
+    // Clause 22 PHY that samples MDIO on the rising edge of MDC and drives read data after it.
+    struct FakePhy {
+        addr: u32,
+        regs: [u16; 32],
+        state: PhyState,
+        // Level driven onto MDIO, if any.
+        out: Option<bool>,
+    }
+
+    impl FakePhy {
+        fn rising_edge(&mut self, mdio: bool) {
+            self.state = match self.state {
+                PhyState::Idle { ones } if mdio => PhyState::Idle { ones: ones + 1 },
+                PhyState::Idle { ones } if ones >= 32 => PhyState::Header { bits: 0, count: 1 },
+                PhyState::Idle { .. } => PhyState::Idle { ones: 0 },
+                PhyState::Header { bits, count } if count < 13 => PhyState::Header {
+                    bits: bits << 1 | mdio as u32,
+                    count: count + 1,
+                },
+                PhyState::Header { bits, .. } => {
+                    let bits = bits << 1 | mdio as u32;
+                    let reg = (bits & 0x1f) as usize;
+                    assert_eq!(bits >> 12, 0b01, "start of frame");
+                    match (bits >> 10 & 0b11, bits >> 5 & 0x1f) {
+                        (_, addr) if addr != self.addr => PhyState::Idle { ones: 0 },
+                        (0b10, _) => PhyState::Turnaround { reg },
+                        (0b01, _) => PhyState::WriteData { reg, bits: 0, count: 0 },
+                        (op, _) => panic!("invalid opcode {op:#b}"),
+                    }
+                }
+                PhyState::Turnaround { reg } => {
+                    self.out = Some(false);
+                    PhyState::ReadData {
+                        val: self.regs[reg],
+                        count: 0,
+                    }
+                }
+                PhyState::ReadData { val, count } if count < 16 => {
+                    self.out = Some(val >> (15 - count) & 1 != 0);
+                    PhyState::ReadData { val, count: count + 1 }
+                }
+                PhyState::ReadData { .. } => {
+                    self.out = None;
+                    PhyState::Idle { ones: 0 }
+                }
+                PhyState::WriteData { reg, bits, count } if count < 17 => PhyState::WriteData {
+                    reg,
+                    bits: bits << 1 | mdio as u32,
+                    count: count + 1,
+                },
+                PhyState::WriteData { reg, bits, .. } => {
+                    let bits = bits << 1 | mdio as u32;
+                    assert_eq!(bits >> 16, 0b10, "write turnaround");
+                    self.regs[reg] = bits as u16;
+                    PhyState::Idle { ones: 0 }
+                }
+            };
+        }
+    }
+
+    // Level of MDIO, driven by the state machine if its pin is an output, else by the PHY or the
+    // pull-up.
+    fn mdio_level(driven: bool, out: bool, phy: Option<bool>) -> bool {
+        match (driven, phy) {
+            (true, Some(_)) => panic!("both ends drive MDIO"),
+            (true, None) => out,
+            (false, level) => level.unwrap_or(true),
+        }
+    }
+
+    // Runs the assembled program on a state machine configured like `PioMdio::new`, with MDC on
+    // the side-set pin and MDIO pulled up, until it waits for the next command.
+    fn run(phy: &mut FakePhy, commands: &[[u32; 2]]) -> Vec<u32> {
+        let program = program();
+        let mut tx: VecDeque<u32> = commands.iter().flatten().copied().collect();
+        let mut rx = Vec::new();
+        let (mut pc, mut x, mut y, mut osr, mut isr) = (0u8, 0u32, 0u32, 0u32, 0u32);
+        let (mut mdc, mut mdio_out, mut mdio_driven) = (false, true, false);
+
+        loop {
+            let mdio = mdio_level(mdio_driven, mdio_out, phy.out);
+            let insn = Instruction::decode(program.code[pc as usize], program.side_set).unwrap();
+            let mut jump = None;
+            match insn.operands {
+                InstructionOperands::PULL { .. } => match tx.pop_front() {
+                    Some(word) => osr = word,
+                    None => return rx,
+                },
+                InstructionOperands::PUSH { .. } => rx.push(core::mem::take(&mut isr)),
+                InstructionOperands::MOV {
+                    destination: MovDestination::Y,
+                    source,
+                    ..
+                } => {
+                    y = match source {
+                        MovSource::OSR => osr,
+                        MovSource::Y => y,
+                        _ => unimplemented!(),
+                    }
+                }
+                InstructionOperands::SET { destination, data } => match destination {
+                    SetDestination::PINS => mdio_out = data & 1 != 0,
+                    SetDestination::PINDIRS => mdio_driven = data & 1 != 0,
+                    SetDestination::X => x = data as u32,
+                    SetDestination::Y => y = data as u32,
+                },
+                InstructionOperands::OUT {
+                    destination: OutDestination::PINS,
+                    bit_count: 1,
+                } => {
+                    mdio_out = osr >> 31 != 0;
+                    osr <<= 1;
+                }
+                InstructionOperands::IN {
+                    source: InSource::PINS,
+                    bit_count: 1,
+                } => isr = isr << 1 | mdio as u32,
+                InstructionOperands::JMP { condition, address } => {
+                    let taken = match condition {
+                        JmpCondition::Always => true,
+                        JmpCondition::YIsZero => y == 0,
+                        JmpCondition::XDecNonZero => {
+                            x = x.wrapping_sub(1);
+                            x != u32::MAX
+                        }
+                        _ => unimplemented!(),
+                    };
+                    jump = taken.then_some(address);
+                }
+                operands => unimplemented!("{operands:?}"),
+            }
+
+            // The PHY samples the level after this instruction's pin writes and only changes its
+            // output after the edge, so `in` above still saw the previous level.
+            let side = insn.side_set.unwrap() != 0;
+            if side && !mdc {
+                phy.rising_edge(mdio_level(mdio_driven, mdio_out, phy.out));
+            }
+            mdc = side;
+
+            pc = match jump {
+                Some(address) => address,
+                None if pc == program.wrap.source => program.wrap.target,
+                None => pc + 1,
+            };
+        }
+    }
+
+    fn phy(addr: u32) -> FakePhy {
+        FakePhy {
+            addr,
+            regs: [0; 32],
+            state: PhyState::Idle { ones: 0 },
+            out: None,
+        }
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut phy = phy(1);
+        phy.regs[2] = 0x0007;
+
+        let rx = run(
+            &mut phy,
+            &[write_command(1, 4, 0x01e1), read_command(1, 4), read_command(1, 2)],
+        );
+        assert_eq!(phy.regs[4], 0x01e1);
+        assert_eq!(rx, [0x01e1, 0x0007]);
+    }
+
+    #[test]
+    fn read_without_phy() {
+        // Nobody drives MDIO during the read, so the pull-up makes it read as all ones.
+        let mut phy = phy(1);
+        phy.regs[2] = 0x0007;
+
+        let rx = run(&mut phy, &[read_command(2, 2), read_command(1, 2)]);
+        assert_eq!(rx, [0xffff, 0x0007]);
+    }
+}
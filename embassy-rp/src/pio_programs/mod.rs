@@ -3,6 +3,7 @@
 pub mod clock_divider;
 pub mod hd44780;
 pub mod i2s;
+pub mod mdio;
 pub mod onewire;
 pub mod pwm;
 pub mod rotary_encoder;
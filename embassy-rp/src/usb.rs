@@ -429,28 +429,7 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
-        let n = ep_addr.index();
-
-        if n == 0 {
-            T::regs().ep_stall_arm().modify(|w| {
-                if ep_addr.is_in() {
-                    w.set_ep0_in(stalled);
-                } else {
-                    w.set_ep0_out(stalled);
-                }
-            });
-        }
-
-        let ctrl = if ep_addr.is_in() {
-            T::dpram().ep_in_buffer_control(n)
-        } else {
-            T::dpram().ep_out_buffer_control(n)
-        };
-
-        ctrl.modify(|w| w.set_stall(stalled));
-
-        let wakers = if ep_addr.is_in() { &EP_IN_WAKERS } else { &EP_OUT_WAKERS };
-        wakers[n].wake();
+        set_stalled::<T>(ep_addr, stalled);
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
@@ -527,6 +506,31 @@ impl Dir for Out {
     }
 }
 
+fn set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    let n = ep_addr.index();
+
+    if n == 0 {
+        T::regs().ep_stall_arm().modify(|w| {
+            if ep_addr.is_in() {
+                w.set_ep0_in(stalled);
+            } else {
+                w.set_ep0_out(stalled);
+            }
+        });
+    }
+
+    let ctrl = if ep_addr.is_in() {
+        T::dpram().ep_in_buffer_control(n)
+    } else {
+        T::dpram().ep_out_buffer_control(n)
+    };
+
+    ctrl.modify(|w| w.set_stall(stalled));
+
+    let wakers = if ep_addr.is_in() { &EP_IN_WAKERS } else { &EP_OUT_WAKERS };
+    wakers[n].wake();
+}
+
 /// Endpoint for RP USB driver.
 pub struct Endpoint<'d, T: Instance, D> {
     _phantom: PhantomData<(&'d mut T, D)>,
@@ -554,6 +558,11 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
         .await;
         trace!("wait_enabled IN OK");
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled::<T>(self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
@@ -576,6 +585,11 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
         .await;
         trace!("wait_enabled OUT OK");
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled::<T>(self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
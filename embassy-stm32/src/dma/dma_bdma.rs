@@ -896,6 +896,11 @@ impl<'a, W: Word> ReadableRingBuffer<'a, W> {
         self.ringbuf.cap()
     }
 
+    /// The index in the buffer of the next element the DMA will transfer.
+    pub fn dma_index(&self) -> usize {
+        (self.capacity() - self.channel.get_remaining_transfers() as usize) % self.capacity()
+    }
+
     /// Set a waker to be woken when at least one byte is received.
     pub fn set_waker(&mut self, waker: &Waker) {
         DmaCtrlImpl(self.channel.reborrow()).set_waker(waker);
@@ -1048,6 +1053,11 @@ impl<'a, W: Word> WritableRingBuffer<'a, W> {
         self.ringbuf.cap()
     }
 
+    /// The index in the buffer of the next element the DMA will transfer.
+    pub fn dma_index(&self) -> usize {
+        (self.capacity() - self.channel.get_remaining_transfers() as usize) % self.capacity()
+    }
+
     /// Set a waker to be woken when at least one byte is received.
     pub fn set_waker(&mut self, waker: &Waker) {
         DmaCtrlImpl(self.channel.reborrow()).set_waker(waker);
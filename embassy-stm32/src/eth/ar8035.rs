@@ -0,0 +1,113 @@
+//! Qualcomm Atheros AR8035 gigabit PHY
+
+use core::task::Context;
+
+use super::regs::C22;
+use super::{Downshift, GenericPhy, Phy, PhyError, StationManagement};
+
+// Smart Speed register, which controls downshift.
+const SMART_SPEED: C22 = C22::vendor_specific::<0x14>();
+const SMART_SPEED_ENABLE: u16 = 1 << 5;
+// Link attempts before downshifting, minus 2.
+const SMART_SPEED_RETRY_LIMIT_SHIFT: u16 = 2;
+const SMART_SPEED_RETRY_LIMIT_MASK: u16 = 0b111 << SMART_SPEED_RETRY_LIMIT_SHIFT;
+// Downshift right after the last failed attempt instead of waiting for the Smart Speed timer.
+const SMART_SPEED_BYPASS_TIMER: u16 = 1 << 1;
+const DOWNSHIFT_RETRIES: core::ops::RangeInclusive<u8> = 2..=9;
+
+/// Qualcomm Atheros AR8035 gigabit PHY.
+///
+/// Resets, initializes and polls the link like [`GenericPhy`]. The AR8031 and AR8033 have the same
+/// registers and work with this driver too. Downshift, which Atheros calls Smart Speed, is
+/// configured with [`Downshift`].
+pub struct Ar8035 {
+    inner: GenericPhy,
+}
+
+impl Ar8035 {
+    /// Construct the PHY at address `phy_addr`.
+    ///
+    /// # Panics
+    /// `phy_addr` must be in range `0..32`
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            inner: GenericPhy::new(phy_addr),
+        }
+    }
+
+    /// Construct the PHY, probing all addresses from 0 to 31 during initialization.
+    ///
+    /// # Panics
+    /// Initialization panics if the PHY didn't respond on any address
+    pub fn new_auto() -> Self {
+        Self {
+            inner: GenericPhy::new_auto(),
+        }
+    }
+}
+
+impl Phy for Ar8035 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_reset(sm);
+    }
+
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.inner.try_phy_reset(sm)
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_init(sm);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        self.inner.poll_link(sm, cx)
+    }
+}
+
+/// Programs the Smart Speed register, for 2 to 9 link attempts. The PHY keeps the setting across
+/// soft resets but only applies it on the next one, so reset the PHY with [`Phy::phy_reset`]
+/// afterwards. With [`Ar8035::new_auto`], the PHY address is only known after the first reset.
+impl Downshift for Ar8035 {
+    fn set_downshift<S: StationManagement>(&mut self, sm: &mut S, retries: Option<u8>) {
+        let phy_addr = self.inner.smi_addr();
+        sm.smi_modify(phy_addr, SMART_SPEED, |reg| match retries {
+            Some(retries) => {
+                let limit =
+                    retries.clamp(*DOWNSHIFT_RETRIES.start(), *DOWNSHIFT_RETRIES.end()) - DOWNSHIFT_RETRIES.start();
+                (reg & !SMART_SPEED_RETRY_LIMIT_MASK)
+                    | SMART_SPEED_ENABLE
+                    | SMART_SPEED_BYPASS_TIMER
+                    | (limit as u16) << SMART_SPEED_RETRY_LIMIT_SHIFT
+            }
+            None => reg & !(SMART_SPEED_ENABLE | SMART_SPEED_BYPASS_TIMER),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::RecordingMdioBus;
+
+    #[test]
+    fn downshift() {
+        const RESERVED: u16 = 1 << 11;
+
+        let mut sm = RecordingMdioBus::new();
+        // Enabled after 5 attempts
+        sm.set(0, SMART_SPEED, RESERVED | SMART_SPEED_ENABLE | 0b011 << 2);
+        let mut phy = Ar8035::new(0);
+
+        phy.set_downshift(&mut sm, Some(3));
+        assert_eq!(sm.get(0, SMART_SPEED), RESERVED | 0x0026);
+
+        phy.set_downshift(&mut sm, None);
+        assert_eq!(sm.get(0, SMART_SPEED), RESERVED | 0x0004);
+
+        // Clamped to the supported range
+        phy.set_downshift(&mut sm, Some(20));
+        assert_eq!(sm.get(0, SMART_SPEED), RESERVED | 0x003e);
+        phy.set_downshift(&mut sm, Some(0));
+        assert_eq!(sm.get(0, SMART_SPEED), RESERVED | 0x0022);
+    }
+}
@@ -0,0 +1,302 @@
+//! Optional PHY capabilities
+//!
+//! Not every PHY supports these features, so they are separate from the [`Phy`](super::Phy) trait.
+
+use super::{LinkStatus, PhyError, StationManagement, StationManagementAsync};
+
+/// PHY that can wake the system on a magic packet.
+pub trait WakeOnLan {
+    /// Enable magic packet detection for the MAC address `mac`.
+    fn configure_wol<S: StationManagement>(&mut self, sm: &mut S, mac: [u8; 6]);
+    /// Check whether a magic packet was received since the last call, clearing the event.
+    fn wol_triggered<S: StationManagement>(&mut self, sm: &mut S) -> bool;
+}
+
+/// PHY supporting Energy-Efficient Ethernet (IEEE 802.3az).
+pub trait Eee {
+    /// Enable or disable advertising EEE, which allows the link to enter low power idle.
+    ///
+    /// Takes effect on the next auto-negotiation.
+    fn eee_enable<S: StationManagement>(&mut self, sm: &mut S, enable: bool);
+    /// Check whether the link partner advertised EEE.
+    fn eee_link_partner_capable<S: StationManagement>(&mut self, sm: &mut S) -> bool;
+}
+
+/// PHY supporting near-end loopback.
+pub trait Loopback {
+    /// Enable or disable loopback of transmitted data back to the receive path.
+    fn set_loopback<S: StationManagement>(&mut self, sm: &mut S, enable: bool);
+}
+
+bitflags::bitflags! {
+    /// PHY interrupt sources.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct InterruptFlags: u8 {
+        /// The link came up.
+        const LINK_UP = 1 << 0;
+        /// The link went down.
+        const LINK_DOWN = 1 << 1;
+        /// Auto-negotiation completed.
+        const AN_COMPLETE = 1 << 2;
+        /// The link partner signalled a remote fault.
+        const REMOTE_FAULT = 1 << 3;
+    }
+}
+
+/// PHY that can assert an interrupt pin on link events.
+///
+/// Clause 22 does not define interrupt registers, so their layout is vendor-specific.
+pub trait PhyInterrupts {
+    /// Read the latched interrupt sources.
+    ///
+    /// The PHY clears the latched sources on read, so call this once per interrupt.
+    fn interrupt_source<S: StationManagement>(&mut self, sm: &mut S) -> InterruptFlags;
+    /// Enable the interrupt sources in `mask` and disable all others.
+    fn enable_interrupts<S: StationManagement>(&mut self, sm: &mut S, mask: InterruptFlags);
+}
+
+/// PHY that reports the speed and duplex mode of its link, e.g. to monitor it with `run_phy`.
+pub trait PhyLinkStatus {
+    /// Read the current link status over an asynchronous SMI.
+    async fn link_status_async<S: StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus;
+}
+
+/// PHY that can be powered down without losing its configuration.
+pub trait PowerDown {
+    /// Power the PHY down or back up.
+    ///
+    /// A powered down PHY still responds to management accesses. After powering back up, the link
+    /// may only come back once auto-negotiation is restarted.
+    fn set_powered<S: StationManagement>(&mut self, sm: &mut S, on: bool);
+}
+
+/// PHY that can isolate its data path from the MII, for boards sharing one MII between several PHYs.
+pub trait Isolate {
+    /// Isolate the PHY from the MII or reconnect it.
+    ///
+    /// An isolated PHY tri-states its MII data outputs but still responds to management accesses.
+    fn set_isolated<S: StationManagement>(&mut self, sm: &mut S, isolated: bool);
+}
+
+/// MASTER-SLAVE role of a PHY, which decides which end of the link provides the clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MasterSlave {
+    /// Clock master.
+    Master,
+    /// Clock slave, recovering the clock from the link partner.
+    Slave,
+}
+
+/// PHY whose MASTER-SLAVE role can be forced.
+///
+/// Single-pair PHYs (100BASE-T1, 1000BASE-T1) don't negotiate the role, so exactly one end of
+/// the link must be configured as master.
+pub trait MasterSlaveConfig {
+    /// Force the MASTER-SLAVE role.
+    ///
+    /// Must be set before link training starts, i.e. before the link is brought up or
+    /// auto-negotiation is restarted.
+    fn set_master_slave<S: StationManagement>(&mut self, sm: &mut S, role: MasterSlave);
+}
+
+/// Gigabit PHY that can downshift to a lower speed when 1000BASE-T training keeps failing.
+///
+/// Links over cables with fewer than four working pairs, or very long ones, never train at
+/// 1000 Mbit/s; with downshift enabled they come up at 100 Mbit/s instead of staying down. The
+/// downshift control bits are in vendor-specific registers, see [`Ar8035`](super::Ar8035).
+pub trait Downshift {
+    /// Enable downshift after `retries` failed link attempts, or disable it with `None`.
+    ///
+    /// PHYs only support a limited range of retry counts; `retries` is clamped to that range.
+    /// Most PHYs only apply the setting on their next soft reset, so call this before
+    /// [`Phy::phy_reset`](super::Phy::phy_reset).
+    fn set_downshift<S: StationManagement>(&mut self, sm: &mut S, retries: Option<u8>);
+}
+
+/// Interface between the MAC and the PHY.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InterfaceMode {
+    /// Media Independent Interface.
+    Mii,
+    /// Reduced Media Independent Interface.
+    Rmii,
+    /// Reduced Gigabit Media Independent Interface.
+    Rgmii {
+        /// Delay RXC by about 2ns in the PHY, so the MAC samples RXD in the middle of the data eye.
+        rx_delay: bool,
+        /// Delay TXC by about 2ns in the PHY, for a MAC that transmits clock and data aligned.
+        tx_delay: bool,
+    },
+    /// Serial Gigabit Media Independent Interface.
+    Sgmii,
+}
+
+/// PHY whose MAC interface can be configured at runtime.
+///
+/// RGMII needs a 2ns skew between clock and data in each direction, added either by the PCB
+/// traces, the MAC or the PHY. The PHY delays are set in vendor-specific registers, so they
+/// need a concrete PHY driver.
+pub trait InterfaceModeConfig {
+    /// Select the MAC interface.
+    ///
+    /// Fails with [`PhyError::Unsupported`] if the PHY doesn't support `mode`, or can only select it
+    /// with strap pins.
+    fn set_interface_mode<S: StationManagement>(&mut self, sm: &mut S, mode: InterfaceMode) -> Result<(), PhyError>;
+}
+
+/// LED number, as printed in the PHY datasheet (e.g. `LedIndex(1)` for LED1).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LedIndex(pub u8);
+
+/// What a PHY LED shows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedFunction {
+    /// On while the link is up.
+    LinkOnly,
+    /// Blinks on transmit and receive activity.
+    Activity,
+    /// On while the link is up, blinking on activity.
+    LinkActivity,
+    /// On at the highest supported speed.
+    Speed,
+    /// Always off.
+    Off,
+}
+
+/// PHY whose LED functions can be configured.
+///
+/// The LED control registers are vendor-specific, and most PHYs only support some functions on
+/// some LEDs.
+pub trait LedConfig {
+    /// Set the function of `led`.
+    ///
+    /// Fails with [`PhyError::Unsupported`] if the PHY has no such LED or it can't show `function`.
+    fn set_led_mode<S: StationManagement>(
+        &mut self,
+        sm: &mut S,
+        led: LedIndex,
+        function: LedFunction,
+    ) -> Result<(), PhyError>;
+}
+
+/// Crossover mode of the twisted pair interface.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mdix {
+    /// Detect crossover automatically (Auto-MDIX).
+    Auto,
+    /// Force MDI, the pinout of an end station.
+    Mdi,
+    /// Force MDI-X, the crossed pinout of a hub or switch port.
+    Mdix,
+}
+
+/// PHY whose crossover detection can be forced.
+///
+/// Two ports that both detect crossover can fail to agree on rare occasions; forcing one end
+/// resolves it. A forced mode needs the matching cable, and some PHYs disable downshift while
+/// the mode is forced.
+pub trait MdixConfig {
+    /// Select the crossover mode.
+    fn set_mdix<S: StationManagement>(&mut self, sm: &mut S, mode: Mdix);
+}
+
+/// Source of the 50MHz RMII reference clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RmiiClock {
+    /// The PHY generates the reference clock from its crystal and drives it to the MAC.
+    Internal,
+    /// The PHY receives the reference clock from an external oscillator or the MCU.
+    External,
+}
+
+/// PHY whose RMII reference clock mode, normally set by a strap pin, can be overridden.
+///
+/// A wrong strap leaves the MAC and PHY without a common clock, so no link comes up.
+pub trait RmiiClockConfig {
+    /// Select the RMII reference clock source.
+    ///
+    /// Some PHYs only sample the clock mode at reset; see the implementation for whether a
+    /// reset or power cycle is needed.
+    fn set_rmii_clock_source<S: StationManagement>(&mut self, sm: &mut S, source: RmiiClock);
+}
+
+/// Error counters read from a PHY.
+///
+/// Counters the PHY doesn't have read as 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PhyStats {
+    /// Frames received with an error.
+    pub rx_errors: u32,
+    /// Invalid symbols received.
+    pub symbol_errors: u32,
+    /// False carrier events, i.e. carrier detected without a valid start of frame.
+    pub false_carrier: u32,
+}
+
+/// PHY with error counters, for link quality telemetry without MAC-side counters.
+///
+/// The counters are in vendor-specific registers. Whether reading clears them, and whether they
+/// saturate or wrap, depends on the PHY; see the implementation.
+pub trait PhyCounters {
+    /// Read the error counters.
+    fn read_counters<S: StationManagement>(&mut self, sm: &mut S) -> PhyStats;
+}
+
+/// State of a cable pair reported by a cable test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PairState {
+    /// Correctly terminated pair.
+    Ok,
+    /// Open pair.
+    Open,
+    /// Shorted pair.
+    Short,
+    /// Impedance mismatch along the pair.
+    ImpedanceMismatch,
+    /// The PHY does not test the pair.
+    NotTested,
+}
+
+/// Result of a cable test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CableReport {
+    /// State of each pair. Pairs the PHY does not test are reported as [`PairState::NotTested`].
+    pub pair_status: [PairState; 4],
+    /// Distance to the fault on each pair in centimeters, if known.
+    pub distance_cm: [Option<u16>; 4],
+}
+
+/// Cable test error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CableTestError {
+    /// The PHY does not support cable diagnostics.
+    Unsupported,
+    /// The cable test did not complete in time.
+    Timeout,
+    /// The PHY could not determine the cable state.
+    Failed,
+}
+
+/// PHY supporting cable diagnostics through time-domain reflectometry (TDR).
+///
+/// The TDR registers are vendor-specific, so the default implementation reports
+/// [`CableTestError::Unsupported`]. [`Ksz8081`](super::Ksz8081) implements it with LinkMD.
+pub trait CableDiagnostics {
+    /// Run a cable test and report the state of each pair.
+    ///
+    /// The link is interrupted while the test runs.
+    fn run_cable_test<S: StationManagement>(&mut self, sm: &mut S) -> Result<CableReport, CableTestError> {
+        let _ = sm;
+        Err(CableTestError::Unsupported)
+    }
+}
@@ -0,0 +1,116 @@
+//! PHY wrapper that filters link flaps
+
+use core::task::Context;
+
+use super::generic_phy::LinkDebounce;
+use super::{Phy, PhyError, StationManagement};
+
+/// [`Phy`] wrapper that only reports a link change once it has been stable.
+///
+/// A marginal cable or a PHY still settling after autonegotiation can make the link status bounce
+/// between polls. The wrapped PHY's [`Phy::poll_link`] result has to differ from the reported state
+/// on `stable_count` consecutive polls before the new state is reported; a single poll agreeing with
+/// the reported state starts the count over.
+///
+/// Reset and initialization are passed through to the wrapped PHY unchanged. The link is reported
+/// down until it has been up for `stable_count` polls.
+pub struct DebouncedPhy<P> {
+    inner: P,
+    debounce: LinkDebounce<bool>,
+}
+
+impl<P> DebouncedPhy<P> {
+    /// Create a new `DebouncedPhy` that needs `stable_count` consecutive identical polls of `inner`
+    /// to report a link change.
+    ///
+    /// A `stable_count` of 0 or 1 reports every change right away.
+    pub fn new(inner: P, stable_count: u8) -> Self {
+        Self {
+            inner,
+            debounce: LinkDebounce::new(false, stable_count),
+        }
+    }
+
+    /// Set the number of consecutive identical polls needed to report a link change.
+    pub fn set_stable_count(&mut self, stable_count: u8) {
+        self.debounce.set_stable_count(stable_count);
+    }
+
+    /// Get a mutable reference to the wrapped PHY.
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+
+    /// Return the wrapped PHY.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P: Phy> Phy for DebouncedPhy<P> {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        self.debounce.reset(false);
+        self.inner.phy_reset(sm)
+    }
+
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.debounce.reset(false);
+        self.inner.try_phy_reset(sm)
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_init(sm)
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        let link_up = self.inner.poll_link(sm, cx);
+        self.debounce.update(link_up);
+        self.debounce.reported()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::RecordingMdioBus;
+
+    struct ScriptedPhy {
+        polls: &'static [bool],
+        pos: usize,
+    }
+
+    impl Phy for ScriptedPhy {
+        fn phy_reset<S: StationManagement>(&mut self, _sm: &mut S) {}
+
+        fn phy_init<S: StationManagement>(&mut self, _sm: &mut S) {}
+
+        fn poll_link<S: StationManagement>(&mut self, _sm: &mut S, _cx: &mut Context) -> bool {
+            let link_up = self.polls[self.pos];
+            self.pos += 1;
+            link_up
+        }
+    }
+
+    #[test]
+    fn link_change_needs_stable_polls() {
+        const POLLS: &[bool] = &[
+            true, false, true, false, // flapping, never stable
+            true, true, true, // up for 3 polls
+            false, true, // single down glitch
+            false, false, false, // down for 3 polls
+        ];
+        const REPORTED: &[bool] = &[
+            false, false, false, false, //
+            false, false, true, //
+            true, true, //
+            true, true, false, //
+        ];
+
+        let mut phy = DebouncedPhy::new(ScriptedPhy { polls: POLLS, pos: 0 }, 3);
+        let mut sm = RecordingMdioBus::new();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        let reported: [bool; POLLS.len()] = core::array::from_fn(|_| phy.poll_link(&mut sm, &mut cx));
+        assert_eq!(reported, REPORTED);
+    }
+}
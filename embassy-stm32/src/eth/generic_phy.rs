@@ -1,16 +1,28 @@
 //! Generic SMI Ethernet PHY
 
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::Context;
 
+use embassy_embedded_hal::reset::hardware_reset;
+use embassy_sync::waitqueue::AtomicWaker;
 #[cfg(feature = "time")]
 use embassy_time::{Duration, Timer};
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital::OutputPin;
 #[cfg(feature = "time")]
 use futures_util::FutureExt;
 
-use super::{Phy, StationManagement};
+use super::regs::{Bmsr, Mmd, MsControl, MsStatus, PcsStat1, PhyId, PmaCtrl1, PmaSpeedAbility, C22, C45};
+use super::{
+    CableDiagnostics, DuplexMode, Eee, InterfaceMode, InterfaceModeConfig, Isolate, LinkStatus, Loopback, MasterSlave,
+    MasterSlaveConfig, Phy, PhyError, PhyLinkStatus, PowerDown, Speed, StationManagement,
+};
 
 #[allow(dead_code)]
-mod phy_consts {
+pub(crate) mod phy_consts {
+    use super::{Mmd, C45};
+
     pub const PHY_REG_BCR: u8 = 0x00;
     pub const PHY_REG_BSR: u8 = 0x01;
     pub const PHY_REG_ID1: u8 = 0x02;
@@ -23,7 +35,12 @@ mod phy_consts {
     pub const PHY_REG_CTL: u8 = 0x0D; // Ethernet PHY Register Control
     pub const PHY_REG_ADDAR: u8 = 0x0E; // Ethernet PHY Address or Data
 
-    pub const PHY_REG_WUCSR: u16 = 0x8010;
+    pub const PHY_REG_WUCSR: C45 = C45::new(Mmd::PCS, 0x8010); // Wakeup Control and Status (LAN87xx)
+    pub const PHY_REG_EEE_ADV: C45 = C45::AN_EEE_ADV;
+    pub const PHY_REG_EEE_LPABLE: C45 = C45::AN_EEE_LPABLE;
+
+    pub const PHY_REG_EEE_100TX: u16 = 1 << 1;
+    pub const PHY_REG_EEE_1000T: u16 = 1 << 2;
 
     pub const PHY_REG_BCR_COLTEST: u16 = 1 << 7;
     pub const PHY_REG_BCR_FD: u16 = 1 << 8;
@@ -39,14 +56,171 @@ mod phy_consts {
     pub const PHY_REG_BSR_UP: u16 = 1 << 2;
     pub const PHY_REG_BSR_FAULT: u16 = 1 << 4;
     pub const PHY_REG_BSR_ANDONE: u16 = 1 << 5;
+    pub const PHY_REG_BSR_ESTATUS: u16 = 1 << 8;
+
+    pub const PHY_REG_AN_10HD: u16 = 1 << 5;
+    pub const PHY_REG_AN_10FD: u16 = 1 << 6;
+    pub const PHY_REG_AN_100HD: u16 = 1 << 7;
+    pub const PHY_REG_AN_100FD: u16 = 1 << 8;
+    pub const PHY_REG_AN_PAUSE: u16 = 1 << 10;
+    pub const PHY_REG_AN_ASYM_PAUSE: u16 = 1 << 11;
+    pub const PHY_REG_AN_SELECTOR_802_3: u16 = 0x0001;
 }
 use self::phy_consts::*;
 
+const DEFAULT_POST_RESET_DELAY_US: u32 = 2000;
+const DEFAULT_RESET_PULSE_US: u32 = 10_000;
+const DEFAULT_PROBE_ATTEMPTS: u8 = 10;
+const DEFAULT_PROBE_DELAY_US: u32 = 10000;
+const DEFAULT_RESET_TIMEOUT_US: u32 = 500_000;
+const DEFAULT_RESET_SETTLE_US: u32 = 1000;
+const RESET_POLL_US: u32 = 1000;
+
+/// Error returned when a [`Speed`] can't be forced through the clause 22 registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UnsupportedSpeed(pub Speed);
+
+/// Error returned when a PHY address is outside the range `0..32`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidPhyAddr(pub u8);
+
+bitflags::bitflags! {
+    /// Abilities advertised during auto-negotiation.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Advertised: u8 {
+        /// 10BASE-T half duplex.
+        const BASE10T_HALF = 1 << 0;
+        /// 10BASE-T full duplex.
+        const BASE10T_FULL = 1 << 1;
+        /// 100BASE-TX half duplex.
+        const BASE100TX_HALF = 1 << 2;
+        /// 100BASE-TX full duplex.
+        const BASE100TX_FULL = 1 << 3;
+        /// 1000BASE-T half duplex.
+        const BASE1000T_HALF = 1 << 4;
+        /// 1000BASE-T full duplex.
+        const BASE1000T_FULL = 1 << 5;
+        /// Symmetric pause.
+        const PAUSE = 1 << 6;
+        /// Asymmetric pause.
+        const ASYM_PAUSE = 1 << 7;
+    }
+}
+
+impl Advertised {
+    // Value of the clause 22 ADVERTISE register.
+    fn anar(self) -> u16 {
+        let mut anar = PHY_REG_AN_SELECTOR_802_3;
+        for (flag, bit) in [
+            (Advertised::BASE10T_HALF, PHY_REG_AN_10HD),
+            (Advertised::BASE10T_FULL, PHY_REG_AN_10FD),
+            (Advertised::BASE100TX_HALF, PHY_REG_AN_100HD),
+            (Advertised::BASE100TX_FULL, PHY_REG_AN_100FD),
+            (Advertised::PAUSE, PHY_REG_AN_PAUSE),
+            (Advertised::ASYM_PAUSE, PHY_REG_AN_ASYM_PAUSE),
+        ] {
+            if self.contains(flag) {
+                anar |= bit;
+            }
+        }
+        anar
+    }
+
+    // Update the 1000BASE-T abilities in `ctrl`, keeping the master/slave configuration.
+    fn ms_control(self, ctrl: MsControl) -> MsControl {
+        ctrl.with_adv_1000_half(self.contains(Advertised::BASE1000T_HALF))
+            .with_adv_1000_full(self.contains(Advertised::BASE1000T_FULL))
+    }
+}
+
+/// All speed and duplex abilities, without PAUSE and ASYM_PAUSE.
+///
+/// The MAC doesn't act on PAUSE frames, so advertising flow control would let the link partner
+/// throttle frames that are then ignored. The 10/100 abilities match the ADVERTISE reset value of
+/// common PHYs.
+impl Default for Advertised {
+    fn default() -> Self {
+        Advertised::all().difference(Advertised::PAUSE | Advertised::ASYM_PAUSE)
+    }
+}
+
+/// Flow control resolved from the PAUSE abilities of both link partners.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlowControl {
+    /// The MAC may transmit PAUSE frames.
+    pub tx_pause: bool,
+    /// The MAC should honor received PAUSE frames.
+    pub rx_pause: bool,
+}
+
+/// Fault conditions reported in BMSR.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkFaults {
+    /// Jabber condition detected (10BASE-T only).
+    pub jabber: bool,
+    /// The link partner signalled a remote fault.
+    pub remote_fault: bool,
+}
+
+/// Link change event from the PHY interrupt line, see [`GenericPhy::poll_link_on_irq`].
+///
+/// Call [`signal`](Self::signal) from the interrupt handler (e.g. an EXTI handler or task) of the
+/// GPIO wired to the PHY's interrupt output.
+pub struct PhyIrq {
+    pending: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl PhyIrq {
+    /// Create a new `PhyIrq`.
+    ///
+    /// It starts out pending, so the first poll reads the link state.
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(true),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Report an interrupt of the PHY, waking the task polling the link.
+    pub fn signal(&self) {
+        self.pending.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl Default for PhyIrq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Generic SMI Ethernet PHY implementation
 pub struct GenericPhy {
     phy_addr: u8,
+    post_reset_delay_us: u32,
+    reset_pulse_us: u32,
+    probe_attempts: u8,
+    probe_delay_us: u32,
+    probe_addrs: RangeInclusive<u8>,
+    reset_timeout_us: u32,
+    reset_settle_us: u32,
+    #[cfg(feature = "time")]
+    last_link_status: LinkStatus,
+    forced_link: Option<(Speed, DuplexMode)>,
+    fiber_mode: bool,
+    advertised: Advertised,
+    latched_faults: LinkFaults,
+    link_irq: Option<&'static PhyIrq>,
+    irq_link_up: bool,
     #[cfg(feature = "time")]
     poll_interval: Duration,
+    #[cfg(feature = "time")]
+    link_timeout: Duration,
 }
 
 impl GenericPhy {
@@ -55,29 +229,83 @@ impl GenericPhy {
     /// # Panics
     /// `phy_addr` must be in range `0..32`
     pub fn new(phy_addr: u8) -> Self {
-        assert!(phy_addr < 32);
-        Self {
+        Self::try_new(phy_addr).unwrap()
+    }
+
+    /// Construct the PHY like [`GenericPhy::new`], returning an error if `phy_addr` is not in range `0..32`.
+    pub fn try_new(phy_addr: u8) -> Result<Self, InvalidPhyAddr> {
+        if phy_addr >= 32 {
+            return Err(InvalidPhyAddr(phy_addr));
+        }
+        Ok(Self {
             phy_addr,
+            post_reset_delay_us: DEFAULT_POST_RESET_DELAY_US,
+            reset_pulse_us: DEFAULT_RESET_PULSE_US,
+            probe_attempts: DEFAULT_PROBE_ATTEMPTS,
+            probe_delay_us: DEFAULT_PROBE_DELAY_US,
+            probe_addrs: 0..=31,
+            reset_timeout_us: DEFAULT_RESET_TIMEOUT_US,
+            reset_settle_us: DEFAULT_RESET_SETTLE_US,
+            #[cfg(feature = "time")]
+            last_link_status: LinkStatus::Down,
+            forced_link: None,
+            fiber_mode: false,
+            advertised: Advertised::default(),
+            latched_faults: LinkFaults::default(),
+            link_irq: None,
+            irq_link_up: false,
             #[cfg(feature = "time")]
             poll_interval: Duration::from_millis(500),
-        }
+            #[cfg(feature = "time")]
+            link_timeout: Duration::from_secs(2),
+        })
     }
 
     /// Construct the PHY. Try to probe all addresses from 0 to 31 during initialization
     ///
     /// # Panics
-    /// Initialization panics if PHY didn't respond on any address
+    /// Initialization panics if PHY didn't respond on any address. Use [`GenericPhy::try_reset`]
+    /// to handle a missing PHY instead.
     pub fn new_auto() -> Self {
+        Self::new_auto_in(0..=31)
+    }
+
+    /// Construct the PHY like [`GenericPhy::new_auto`], probing only the addresses in `addrs`.
+    ///
+    /// Probing a missing address takes 100ms by default, so restricting the probe to the addresses
+    /// the PHY can be strapped to speeds up detection.
+    ///
+    /// # Panics
+    /// `addrs` must be a non-empty range within `0..=31`. Initialization panics if PHY didn't
+    /// respond on any address.
+    pub fn new_auto_in(addrs: RangeInclusive<u8>) -> Self {
+        assert!(*addrs.end() < 32, "PHY addresses are 0..=31");
+        assert!(!addrs.is_empty(), "no PHY address to probe");
         Self {
             phy_addr: 0xFF,
-            #[cfg(feature = "time")]
-            poll_interval: Duration::from_millis(500),
+            probe_addrs: addrs,
+            ..Self::new(0)
+        }
+    }
+
+    /// Construct the PHY with auto-detection like [`GenericPhy::new_auto`], using a custom probe timing.
+    ///
+    /// After resetting each address, the reset bit is checked up to `attempts` times with
+    /// `delay_us` microseconds in between. [`GenericPhy::new_auto`] uses 10 attempts of 10ms each.
+    ///
+    /// # Panics
+    /// Initialization panics if PHY didn't respond on any address
+    pub fn new_auto_with(attempts: u8, delay_us: u32) -> Self {
+        Self {
+            probe_attempts: attempts,
+            probe_delay_us: delay_us,
+            ..Self::new_auto()
         }
     }
 }
 
 // TODO: Factor out to shared functionality
-fn blocking_delay_us(us: u32) {
+pub(crate) fn blocking_delay_us(us: u32) {
     #[cfg(feature = "time")]
     embassy_time::block_for(Duration::from_micros(us as u64));
     #[cfg(not(feature = "time"))]
@@ -89,62 +317,475 @@ fn blocking_delay_us(us: u32) {
     }
 }
 
+// Panic for the reset methods of the `Phy` traits, which can't return an error.
+fn reset_failed(err: PhyError) -> ! {
+    match err {
+        PhyError::ResetTimeout => panic!("PHY did not come out of reset in time"),
+        _ => panic!("PHY did not respond"),
+    }
+}
+
 impl Phy for GenericPhy {
     fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
-        // Detect SMI address
-        if self.phy_addr == 0xFF {
-            for addr in 0..32 {
-                sm.smi_write(addr, PHY_REG_BCR, PHY_REG_BCR_RESET);
-                for _ in 0..10 {
-                    if sm.smi_read(addr, PHY_REG_BCR) & PHY_REG_BCR_RESET != PHY_REG_BCR_RESET {
-                        trace!("Found ETH PHY on address {}", addr);
-                        self.phy_addr = addr;
-                        return;
-                    }
-                    // Give PHY a total of 100ms to respond
-                    blocking_delay_us(10000);
-                }
-            }
-            panic!("PHY did not respond");
+        if let Err(err) = self.reset(sm, blocking_delay_us) {
+            reset_failed(err);
         }
+    }
 
-        sm.smi_write(self.phy_addr, PHY_REG_BCR, PHY_REG_BCR_RESET);
-        while sm.smi_read(self.phy_addr, PHY_REG_BCR) & PHY_REG_BCR_RESET == PHY_REG_BCR_RESET {}
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.try_reset(sm)
     }
 
     fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
-        // Clear WU CSR
-        self.smi_write_ext(sm, PHY_REG_WUCSR, 0);
-
-        // Enable auto-negotiation
-        sm.smi_write(
-            self.phy_addr,
-            PHY_REG_BCR,
-            PHY_REG_BCR_AN | PHY_REG_BCR_ANRST | PHY_REG_BCR_100M,
-        );
+        self.init(sm, 0);
     }
 
     fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        if let Some(irq) = self.link_irq {
+            return self.poll_link_on_irq(sm, cx, irq);
+        }
+
         #[cfg(not(feature = "time"))]
         cx.waker().wake_by_ref();
 
         #[cfg(feature = "time")]
         let _ = Timer::after(self.poll_interval).poll_unpin(cx);
 
-        let bsr = sm.smi_read(self.phy_addr, PHY_REG_BSR);
+        let bsr = Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR));
+        self.bsr_link_up(bsr)
+    }
+}
+
+impl super::PhyAsync for GenericPhy {
+    async fn phy_reset<S: super::StationManagementAsync>(&mut self, sm: &mut S) {
+        if let Err(err) = self.try_reset_async(sm).await {
+            reset_failed(err);
+        }
+    }
+
+    async fn try_phy_reset<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.try_reset_async(sm).await
+    }
+
+    async fn phy_init<S: super::StationManagementAsync>(&mut self, sm: &mut S) {
+        self.init_regs(sm, 0).await;
+    }
+
+    async fn poll_link<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> bool {
+        self.bsr_link_up(Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR).await))
+    }
+}
+
+/// EEE through the standard auto-negotiation MMD registers 7.60 and 7.61.
+impl Eee for GenericPhy {
+    fn eee_enable<S: StationManagement>(&mut self, sm: &mut S, enable: bool) {
+        let adv = if enable {
+            PHY_REG_EEE_100TX | PHY_REG_EEE_1000T
+        } else {
+            0
+        };
+        sm.smi_write_mmd(self.phy_addr, PHY_REG_EEE_ADV, adv);
+    }
+
+    fn eee_link_partner_capable<S: StationManagement>(&mut self, sm: &mut S) -> bool {
+        sm.smi_read_mmd(self.phy_addr, PHY_REG_EEE_LPABLE) & (PHY_REG_EEE_100TX | PHY_REG_EEE_1000T) != 0
+    }
+}
+
+impl Loopback for GenericPhy {
+    fn set_loopback<S: StationManagement>(&mut self, sm: &mut S, enable: bool) {
+        sm.smi_modify(self.phy_addr, C22::BMCR, |bcr| match enable {
+            true => bcr | PHY_REG_BCR_LOOPBACK,
+            false => bcr & !PHY_REG_BCR_LOOPBACK,
+        });
+    }
+}
+
+impl Isolate for GenericPhy {
+    fn set_isolated<S: StationManagement>(&mut self, sm: &mut S, isolated: bool) {
+        sm.smi_modify(self.phy_addr, C22::BMCR, |bcr| match isolated {
+            true => bcr | PHY_REG_BCR_ISOLATE,
+            false => bcr & !PHY_REG_BCR_ISOLATE,
+        });
+    }
+}
+
+/// Uses the manual configuration bits of the 1000BASE-T control register.
+impl MasterSlaveConfig for GenericPhy {
+    fn set_master_slave<S: StationManagement>(&mut self, sm: &mut S, role: MasterSlave) {
+        sm.smi_modify(self.phy_addr, C22::MASTER_SLAVE_CONTROL, |ctrl| {
+            MsControl(ctrl)
+                .with_manual_config(true)
+                .with_master(role == MasterSlave::Master)
+                .0
+        });
+    }
+}
+
+/// The interface is selected by strap pins on most PHYs, so MII and RMII are accepted without
+/// register writes. RGMII delays and SGMII need vendor-specific registers and are
+/// [`PhyError::Unsupported`]; use a concrete PHY driver like [`Rtl8211f`](super::Rtl8211f).
+impl InterfaceModeConfig for GenericPhy {
+    fn set_interface_mode<S: StationManagement>(&mut self, _sm: &mut S, mode: InterfaceMode) -> Result<(), PhyError> {
+        match mode {
+            InterfaceMode::Mii | InterfaceMode::Rmii => Ok(()),
+            InterfaceMode::Rgmii { .. } | InterfaceMode::Sgmii => Err(PhyError::Unsupported),
+        }
+    }
+}
+
+impl PowerDown for GenericPhy {
+    fn set_powered<S: StationManagement>(&mut self, sm: &mut S, on: bool) {
+        sm.smi_modify(self.phy_addr, C22::BMCR, |bcr| match on {
+            true => bcr & !PHY_REG_BCR_POWERDN,
+            false => bcr | PHY_REG_BCR_POWERDN,
+        });
+    }
+}
+
+/// TDR registers are vendor-specific, so cable tests are unsupported.
+impl CableDiagnostics for GenericPhy {}
+
+// `DelayNs` forwarding to a microsecond delay function.
+struct DelayUs<F: FnMut(u32)>(F);
+
+impl<F: FnMut(u32)> DelayNs for DelayUs<F> {
+    fn delay_ns(&mut self, ns: u32) {
+        (self.0)(ns.div_ceil(1000))
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        (self.0)(us)
+    }
+}
+
+// Wait without blocking the executor when a time driver is available.
+async fn delay_us(us: u32) {
+    #[cfg(feature = "time")]
+    Timer::after_micros(us as u64).await;
+    #[cfg(not(feature = "time"))]
+    blocking_delay_us(us);
+}
+
+impl GenericPhy {
+    // `Phy::phy_init`, writing `wucsr` to the wake-up control register instead of clearing it.
+    pub(crate) fn init<S: StationManagement>(&mut self, sm: &mut S, wucsr: u16) {
+        // The `StationManagementAsync` impl of a blocking bus never yields, so this doesn't spin.
+        embassy_futures::block_on(self.init_regs(sm, wucsr))
+    }
+
+    // Register sequence of `phy_init`, shared by `Phy` and `PhyAsync`.
+    async fn init_regs<S: super::StationManagementAsync>(&mut self, sm: &mut S, wucsr: u16) {
+        // Clear WU CSR, which keeps a wake-up configuration and status across resets
+        sm.smi_write_mmd(self.phy_addr, PHY_REG_WUCSR, wucsr).await;
+
+        if self.fixed_link().is_none() {
+            sm.smi_write(self.phy_addr, C22::ADVERTISE.0, self.advertised.anar())
+                .await;
+            let bsr = Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR).await);
+            self.latch_faults(bsr);
+            if bsr.extended_status() {
+                let ctrl = MsControl(sm.smi_read(self.phy_addr, MsControl::ADDR).await);
+                sm.smi_write(self.phy_addr, MsControl::ADDR, self.advertised.ms_control(ctrl).0)
+                    .await;
+            }
+        }
+
+        // Enable auto-negotiation
+        sm.smi_write(self.phy_addr, PHY_REG_BCR, self.init_bcr()).await;
+    }
+
+    // BCR value written by `phy_init`: either start auto-negotiation or force the configured link.
+    fn init_bcr(&self) -> u16 {
+        match self.fixed_link() {
+            None => PHY_REG_BCR_AN | PHY_REG_BCR_ANRST | PHY_REG_BCR_100M,
+            Some((speed, duplex)) => {
+                let mut bcr = 0;
+                if speed == Speed::_100 {
+                    bcr |= PHY_REG_BCR_100M;
+                }
+                if duplex == DuplexMode::Full {
+                    bcr |= PHY_REG_BCR_FD;
+                }
+                bcr
+            }
+        }
+    }
+
+    // Speed and duplex used instead of auto-negotiation, if any. Fiber is always 100M full duplex.
+    fn fixed_link(&self) -> Option<(Speed, DuplexMode)> {
+        if self.fiber_mode {
+            Some((Speed::_100, DuplexMode::Full))
+        } else {
+            self.forced_link
+        }
+    }
+
+    // SMI address of the PHY, or 0xFF before auto-detection.
+    pub(crate) fn smi_addr(&self) -> u8 {
+        self.phy_addr
+    }
 
-        // No link without autonegotiate
-        if bsr & PHY_REG_BSR_ANDONE == 0 {
+    // Keep the latching-high fault bits of a BMSR read for `link_faults`, since reading BMSR clears them.
+    fn latch_faults(&mut self, bsr: Bmsr) {
+        self.latched_faults.jabber |= bsr.jabber_detect();
+        self.latched_faults.remote_fault |= bsr.remote_fault();
+    }
+
+    fn bsr_link_up(&mut self, bsr: Bmsr) -> bool {
+        self.latch_faults(bsr);
+
+        // No link without autonegotiate, unless the link is forced
+        if self.fixed_link().is_none() && !bsr.autoneg_complete() {
             return false;
         }
         // No link if link is down
-        if bsr & PHY_REG_BSR_UP == 0 {
+        if !bsr.link_up() {
             return false;
         }
 
         // Got link
         true
     }
+
+    fn reset<S: StationManagement>(&mut self, sm: &mut S, mut delay_us: impl FnMut(u32)) -> Result<(), PhyError> {
+        embassy_futures::block_on(self.reset_with(sm, |us| {
+            delay_us(us);
+            core::future::ready(())
+        }))
+    }
+
+    // Reset sequence shared by `Phy` and `PhyAsync`, awaiting `delay(us)` to wait.
+    async fn reset_with<S: super::StationManagementAsync, F: core::future::Future<Output = ()>>(
+        &mut self,
+        sm: &mut S,
+        mut delay: impl FnMut(u32) -> F,
+    ) -> Result<(), PhyError> {
+        // Detect SMI address
+        if self.phy_addr == 0xFF {
+            self.phy_addr = 'detect: {
+                for addr in self.probe_addrs.clone() {
+                    sm.smi_write(addr, PHY_REG_BCR, PHY_REG_BCR_RESET).await;
+                    for _ in 0..self.probe_attempts {
+                        if reset_done(sm.smi_read(addr, PHY_REG_BCR).await) {
+                            trace!("Found ETH PHY on address {}", addr);
+                            break 'detect addr;
+                        }
+                        // By default give PHY a total of 100ms to respond
+                        delay(self.probe_delay_us).await;
+                    }
+                }
+                return Err(PhyError::NoPhyFound);
+            };
+        } else {
+            sm.smi_write(self.phy_addr, PHY_REG_BCR, PHY_REG_BCR_RESET).await;
+        }
+
+        // Wait once, for the PHY in use, instead of after resetting each probed address
+        delay(self.post_reset_delay_us).await;
+        let mut waited_us = 0;
+        while !reset_done(sm.smi_read(self.phy_addr, PHY_REG_BCR).await) {
+            if waited_us >= self.reset_timeout_us {
+                return Err(PhyError::ResetTimeout);
+            }
+            delay(RESET_POLL_US).await;
+            waited_us += RESET_POLL_US;
+        }
+        if self.reset_settle_us > 0 {
+            delay(self.reset_settle_us).await;
+        }
+        Ok(())
+    }
+}
+
+/// Read the identifier of the PHY at `phy_addr`.
+pub fn read_phy_id<S: StationManagement>(sm: &mut S, phy_addr: u8) -> PhyId {
+    let physid1 = sm.smi_read(phy_addr, C22::PHYSID1.0);
+    let physid2 = sm.smi_read(phy_addr, C22::PHYSID2.0);
+    PhyId::from_regs(physid1, physid2)
+}
+
+/// Read the identifier of the PHY at `phy_addr` over an asynchronous SMI.
+pub async fn read_phy_id_async<S: super::StationManagementAsync>(sm: &mut S, phy_addr: u8) -> PhyId {
+    let physid1 = sm.smi_read(phy_addr, C22::PHYSID1.0).await;
+    let physid2 = sm.smi_read(phy_addr, C22::PHYSID2.0).await;
+    PhyId::from_regs(physid1, physid2)
+}
+
+/// Probe every MDIO address and return the address and identifier of each responding PHY.
+///
+/// Addresses whose `PHYSID1`/`PHYSID2` read back as all ones or all zeros have no device
+/// attached and are skipped.
+pub fn scan_bus<S: StationManagement>(sm: &mut S) -> heapless::Vec<(u8, PhyId), 32> {
+    (0..32)
+        .filter_map(|phy_addr| {
+            let physid1 = sm.smi_read(phy_addr, C22::PHYSID1.0);
+            let physid2 = sm.smi_read(phy_addr, C22::PHYSID2.0);
+            match (physid1, physid2) {
+                (0xffff, 0xffff) | (0, 0) => None,
+                _ => Some((phy_addr, PhyId::from_regs(physid1, physid2))),
+            }
+        })
+        .collect()
+}
+
+/// Names of the IEEE 802.3 clause 22 base registers, indexed by register address.
+const C22_REG_NAMES: [&str; 16] = [
+    "BMCR",
+    "BMSR",
+    "PHYSID1",
+    "PHYSID2",
+    "ADVERTISE",
+    "LPA",
+    "EXPANSION",
+    "NPTX",
+    "NPRX",
+    "CTRL1000",
+    "STAT1000",
+    "PSECR",
+    "PSESR",
+    "MMD_CTRL",
+    "MMD_DATA",
+    "ESTATUS",
+];
+
+/// Read the 16 clause 22 base registers of the PHY at `phy_addr` and write them as a table to `w`.
+///
+/// Each register goes on its own line, e.g. `BMCR: 0x1140`. This works with any
+/// [`core::fmt::Write`] sink, such as a UART or semihosting writer.
+pub fn write_c22_dump<W: core::fmt::Write, S: StationManagement>(
+    sm: &mut S,
+    phy_addr: u8,
+    w: &mut W,
+) -> core::fmt::Result {
+    for (reg, name) in C22_REG_NAMES.iter().enumerate() {
+        let val = sm.smi_read(phy_addr, reg as u8);
+        writeln!(w, "{name}: {val:#06x}")?;
+    }
+    Ok(())
+}
+
+/// Log the decoded state of the PHY at `phy_addr` with `defmt`, to see why a link doesn't come up.
+///
+/// Reports whether the link is up, the auto-negotiation state, the 10/100 abilities advertised by
+/// both ends and the link they resolve to. BMSR is read twice: the first read shows a link loss,
+/// jabber or remote fault latched since the last read, the second one the current state. Reading
+/// BMSR clears its latched bits, so a later poll won't see those earlier events.
+#[cfg(feature = "defmt")]
+pub fn dump_phy_state<S: StationManagement>(sm: &mut S, phy_addr: u8) {
+    let bcr = sm.smi_read(phy_addr, PHY_REG_BCR);
+    // Read BMSR twice, the first read returns the latched state.
+    let latched = Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR));
+    let bsr = Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR));
+    let antx = sm.smi_read(phy_addr, PHY_REG_ANTX);
+    let anrx = sm.smi_read(phy_addr, PHY_REG_ANRX);
+
+    defmt::info!(
+        "PHY {=u8}: latched: link up: {=bool}, jabber: {=bool}, remote fault: {=bool}",
+        phy_addr,
+        latched.link_up(),
+        latched.jabber_detect(),
+        latched.remote_fault(),
+    );
+    defmt::info!(
+        "PHY {=u8}: link up: {=bool}, AN enabled: {=bool}, AN complete: {=bool}, remote fault: {=bool}",
+        phy_addr,
+        bsr.link_up(),
+        bcr & PHY_REG_BCR_AN != 0,
+        bsr.autoneg_complete(),
+        bsr.remote_fault(),
+    );
+    for (name, an) in [("advertised", antx), ("link partner", anrx)] {
+        defmt::info!(
+            "PHY {=u8}: {=str}: 10HD: {=bool}, 10FD: {=bool}, 100HD: {=bool}, 100FD: {=bool}",
+            phy_addr,
+            name,
+            an & PHY_REG_AN_10HD != 0,
+            an & PHY_REG_AN_10FD != 0,
+            an & PHY_REG_AN_100HD != 0,
+            an & PHY_REG_AN_100FD != 0,
+        );
+    }
+    let resolved = match bsr.link_up() {
+        true => resolve_an(antx, anrx),
+        false => LinkStatus::Down,
+    };
+    defmt::info!("PHY {=u8}: resolved link: {}", phy_addr, resolved);
+}
+
+fn reset_done(bcr: u16) -> bool {
+    bcr & PHY_REG_BCR_RESET != PHY_REG_BCR_RESET
+}
+
+// Resolve a 1000BASE-T link from our advertisement and the link partner's abilities.
+fn resolve_1000(ctrl: MsControl, status: MsStatus) -> Option<DuplexMode> {
+    if ctrl.adv_1000_full() && status.lp_1000_full() {
+        Some(DuplexMode::Full)
+    } else if ctrl.adv_1000_half() && status.lp_1000_half() {
+        Some(DuplexMode::Half)
+    } else {
+        None
+    }
+}
+
+// PMA/PMD control 1 speed selection (bits 13, 6 and 5:2) and the speed ability bit that must be
+// set for it, per 802.3 45.2.1.1.3.
+const C45_SPEEDS: [(u16, u16, Speed); 6] = [
+    (0x0000, 1 << 6, Speed::_10),
+    (0x2000, 1 << 5, Speed::_100),
+    (0x0040, 1 << 4, Speed::_1000),
+    (0x2040, 1 << 0, Speed::_10000),
+    (0x2058, 1 << 13, Speed::_2500),
+    (0x205c, 1 << 14, Speed::_5000),
+];
+
+// Decode the selected PMA/PMD speed, if the PMA/PMD is capable of it.
+fn resolve_c45(ctrl: PmaCtrl1, ability: PmaSpeedAbility) -> Option<Speed> {
+    // Bits 5:2 only count if bits 13 and 6 are both set
+    let sel = if ctrl.speed_sel_msb() && ctrl.speed_sel_lsb() {
+        ctrl.0 & 0x207c
+    } else {
+        ctrl.0 & 0x2040
+    };
+    C45_SPEEDS
+        .iter()
+        .find(|&&(bits, able, _)| sel == bits && ability.0 & able != 0)
+        .map(|&(_, _, speed)| speed)
+}
+
+// Resolve the highest common 10/100 ability from our advertisement and the link partner's.
+fn resolve_an(antx: u16, anrx: u16) -> LinkStatus {
+    let common = antx & anrx;
+    let (speed, duplex) = if common & PHY_REG_AN_100FD != 0 {
+        (Speed::_100, DuplexMode::Full)
+    } else if common & PHY_REG_AN_100HD != 0 {
+        (Speed::_100, DuplexMode::Half)
+    } else if common & PHY_REG_AN_10FD != 0 {
+        (Speed::_10, DuplexMode::Full)
+    } else {
+        (Speed::_10, DuplexMode::Half)
+    };
+    LinkStatus::Up { speed, duplex }
+}
+
+// Resolve flow control from the local and link partner abilities (IEEE 802.3 Annex 28B, table 28B-3).
+fn resolve_pause(antx: u16, anrx: u16) -> FlowControl {
+    let pause = |reg: u16| reg & PHY_REG_AN_PAUSE != 0;
+    let asym = |reg: u16| reg & PHY_REG_AN_ASYM_PAUSE != 0;
+
+    if pause(antx) && pause(anrx) {
+        FlowControl {
+            tx_pause: true,
+            rx_pause: true,
+        }
+    } else if asym(antx) && asym(anrx) {
+        FlowControl {
+            tx_pause: pause(anrx),
+            rx_pause: pause(antx),
+        }
+    } else {
+        FlowControl::default()
+    }
 }
 
 /// Public functions for the PHY
@@ -155,11 +796,1316 @@ impl GenericPhy {
         self.poll_interval = poll_interval
     }
 
-    // Writes a value to an extended PHY register in MMD address space
-    fn smi_write_ext<S: StationManagement>(&mut self, sm: &mut S, reg_addr: u16, reg_data: u16) {
-        sm.smi_write(self.phy_addr, PHY_REG_CTL, 0x0003); // set address
-        sm.smi_write(self.phy_addr, PHY_REG_ADDAR, reg_addr);
-        sm.smi_write(self.phy_addr, PHY_REG_CTL, 0x4003); // set data
-        sm.smi_write(self.phy_addr, PHY_REG_ADDAR, reg_data);
+    /// Set the timeout used by [`GenericPhy::poll_link_timeout`]. Defaults to 2s.
+    ///
+    /// Keep it comfortably larger than the poll interval set with [`GenericPhy::set_poll_interval`],
+    /// so a slow but present PHY is not reported as down.
+    #[cfg(feature = "time")]
+    pub fn set_link_timeout(&mut self, timeout: Duration) {
+        self.link_timeout = timeout
+    }
+
+    /// Force the link to `speed` and `duplex` instead of using auto-negotiation.
+    ///
+    /// Takes effect on the next [`Phy::phy_init`], which then disables auto-negotiation and programs
+    /// the speed and duplex bits in BMCR directly. Once the PHY reports the link up, the forced
+    /// configuration is reported as the link status.
+    ///
+    /// Only 10 and 100 Mbit/s can be forced through the clause 22 BMCR; other speeds return an error.
+    pub fn force_link(&mut self, speed: Speed, duplex: DuplexMode) -> Result<(), UnsupportedSpeed> {
+        if !matches!(speed, Speed::_10 | Speed::_100) {
+            return Err(UnsupportedSpeed(speed));
+        }
+        self.forced_link = Some((speed, duplex));
+        Ok(())
+    }
+
+    /// Run the PHY in 100BASE-FX fiber mode.
+    ///
+    /// 100BASE-FX has no auto-negotiation, so the ADVERTISE and LPA registers say nothing about a
+    /// fiber link. In fiber mode, [`Phy::phy_init`] disables auto-negotiation and sets 100M full
+    /// duplex in BMCR, and the link is reported up as 100M full duplex as soon as BMSR shows link,
+    /// which the PHY derives from the signal detect input of the fiber transceiver.
+    ///
+    /// Fiber is fixed-speed: this takes precedence over [`force_link`](Self::force_link). Selecting
+    /// the fiber interface itself is PHY specific, usually through a strap pin, and not done here.
+    pub fn set_fiber_mode(&mut self, enable: bool) {
+        self.fiber_mode = enable;
+    }
+
+    /// Reset the PHY like [`Phy::phy_reset`], returning an error instead of panicking if
+    /// auto-detection finds no PHY or the PHY doesn't come out of reset.
+    ///
+    /// Useful for hot-pluggable media where the PHY may be absent.
+    pub fn try_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.reset(sm, blocking_delay_us)
+    }
+
+    /// Pulse the RST# pin of the PHY, then reset it like [`GenericPhy::try_reset`].
+    ///
+    /// `rst` is driven low for the pulse width set with [`set_reset_pulse_us`](Self::set_reset_pulse_us).
+    /// The post-reset delay is applied once, after the soft reset that follows the pulse. A hardware
+    /// reset also recovers a PHY that no longer answers on SMI. Fails with [`PhyError::ResetPin`] if
+    /// `rst` can't be driven.
+    pub fn try_reset_with_pin<S: StationManagement, P: OutputPin>(
+        &mut self,
+        sm: &mut S,
+        rst: &mut P,
+    ) -> Result<(), PhyError> {
+        self.reset_with_pin(sm, rst, blocking_delay_us)
+    }
+
+    fn reset_with_pin<S: StationManagement, P: OutputPin>(
+        &mut self,
+        sm: &mut S,
+        rst: &mut P,
+        mut delay_us: impl FnMut(u32),
+    ) -> Result<(), PhyError> {
+        let mut delay = DelayUs(&mut delay_us);
+        // The soft reset below waits for the PHY, so don't wait after releasing the pin too
+        hardware_reset(rst, &mut delay, self.reset_pulse_us, 0).map_err(|_| PhyError::ResetPin)?;
+        self.reset(sm, delay_us)
+    }
+
+    /// Reset and initialize the PHY, bringing the link up from cold in one call.
+    ///
+    /// Runs [`GenericPhy::try_reset`] followed by [`Phy::phy_init`]. The address found by
+    /// auto-detection is kept, so calling this again to restart the PHY resets only that address
+    /// instead of probing all 32.
+    pub fn start<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.start_with(sm, blocking_delay_us)
+    }
+
+    fn start_with<S: StationManagement>(&mut self, sm: &mut S, delay_us: impl FnMut(u32)) -> Result<(), PhyError> {
+        self.reset(sm, delay_us)?;
+        self.phy_init(sm);
+        Ok(())
+    }
+
+    /// Reset the PHY like [`PhyAsync::phy_reset`](super::PhyAsync::phy_reset), returning an error
+    /// instead of panicking if auto-detection finds no PHY or the PHY doesn't come out of reset.
+    pub async fn try_reset_async<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.reset_with(sm, delay_us).await
+    }
+
+    /// Set the abilities advertised during auto-negotiation.
+    ///
+    /// Takes effect on the next [`Phy::phy_init`]. Defaults to [`Advertised::default`]. The 1000BASE-T
+    /// abilities are only written to PHYs that report extended status.
+    pub fn set_advertised(&mut self, abilities: Advertised) {
+        self.advertised = abilities;
+    }
+
+    /// Enable and restart auto-negotiation without resetting the PHY.
+    ///
+    /// Other BMCR bits are preserved. Returns immediately; [`Phy::poll_link`] reports the link once
+    /// the new negotiation completes. Clears any link configuration set with [`force_link`](Self::force_link).
+    pub fn restart_autoneg<S: StationManagement>(&mut self, sm: &mut S) {
+        self.forced_link = None;
+        sm.smi_modify(self.phy_addr, C22::BMCR, |bcr| bcr | PHY_REG_BCR_AN | PHY_REG_BCR_ANRST);
+    }
+
+    /// Poll the link only after the PHY signalled an interrupt, sleeping in between.
+    ///
+    /// Without the `time` feature, [`Phy::poll_link`] wakes the task again immediately and keeps
+    /// the CPU busy. This registers the waker with `irq` instead, and only reads the PHY when
+    /// [`PhyIrq::signal`] was called since the last poll; otherwise it returns the last link state.
+    ///
+    /// The PHY's interrupt output must be wired to a GPIO whose interrupt calls [`PhyIrq::signal`].
+    /// The interrupt source and mask registers are vendor-specific, so this doesn't enable or
+    /// acknowledge the PHY's interrupts. Use the [`PhyInterrupts`](super::PhyInterrupts)
+    /// implementation of a specific PHY such as [`Lan8742`](super::Lan8742), which acknowledges the
+    /// interrupt when polling, or acknowledge it before calling this.
+    pub fn poll_link_on_irq<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context, irq: &PhyIrq) -> bool {
+        self.poll_link_on_irq_with(sm, cx, irq, |_| {})
+    }
+
+    // Like `poll_link_on_irq`, calling `ack` to acknowledge the interrupt before reading the link.
+    pub(crate) fn poll_link_on_irq_with<S: StationManagement>(
+        &mut self,
+        sm: &mut S,
+        cx: &mut Context,
+        irq: &PhyIrq,
+        ack: impl FnOnce(&mut S),
+    ) -> bool {
+        irq.waker.register(cx.waker());
+        if irq.pending.load(Ordering::Acquire) {
+            // Cleared before reading, so an interrupt during the reads is handled on the next poll
+            irq.pending.store(false, Ordering::Relaxed);
+            ack(sm);
+            let bsr = Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR));
+            self.irq_link_up = self.bsr_link_up(bsr);
+        }
+        self.irq_link_up
+    }
+
+    /// Make [`Phy::poll_link`] use [`poll_link_on_irq`](Self::poll_link_on_irq) with `irq`, or go back
+    /// to polling with `None`.
+    pub fn set_link_irq(&mut self, irq: Option<&'static PhyIrq>) {
+        self.link_irq = irq;
+    }
+
+    pub(crate) fn link_irq(&self) -> Option<&'static PhyIrq> {
+        self.link_irq
+    }
+
+    /// SMI address of the PHY, or `None` if it hasn't been auto-detected yet.
+    ///
+    /// The detected address can be stored and passed to [`GenericPhy::new`] on the next boot to
+    /// skip probing.
+    pub fn phy_addr(&self) -> Option<u8> {
+        (self.phy_addr != 0xFF).then_some(self.phy_addr)
+    }
+
+    /// Set the SMI address of the PHY, returning an error if `phy_addr` is not in range `0..32`.
+    ///
+    /// This skips auto-detection on the next reset.
+    pub fn set_phy_addr(&mut self, phy_addr: u8) -> Result<(), InvalidPhyAddr> {
+        if phy_addr >= 32 {
+            return Err(InvalidPhyAddr(phy_addr));
+        }
+        self.phy_addr = phy_addr;
+        Ok(())
+    }
+
+    /// Set the delay applied after writing the reset bit, before the PHY registers are accessed again.
+    ///
+    /// Some PHYs return stale register values right after a soft reset. With auto-detection, it is
+    /// applied once, after a PHY answered on a probed address. Defaults to 2ms.
+    pub fn set_post_reset_delay_us(&mut self, us: u32) {
+        self.post_reset_delay_us = us
+    }
+
+    /// Set how long [`GenericPhy::try_reset_with_pin`] holds the RST# pin low.
+    ///
+    /// Defaults to 10ms, the longest minimum of common datasheets.
+    pub fn set_reset_pulse_us(&mut self, us: u32) {
+        self.reset_pulse_us = us
+    }
+
+    /// Set the delay applied after the reset bit cleared, before the PHY is accessed again.
+    ///
+    /// Several PHYs need a few more microseconds to milliseconds after the end of a soft reset
+    /// before MDIO writes take effect, so the first [`Phy::phy_init`] write can otherwise be lost.
+    /// Defaults to 1ms, which covers common datasheets; set it to 0 for PHYs that don't need it.
+    pub fn set_reset_settle_us(&mut self, us: u32) {
+        self.reset_settle_us = us
+    }
+
+    /// Set how long to wait for the reset bit to clear after a soft reset of a known PHY address.
+    ///
+    /// Resetting fails with [`PhyError::ResetTimeout`] if the PHY is still in reset afterwards, and
+    /// [`Phy::phy_reset`] panics with a message saying so.
+    /// Auto-detection is bounded by the probe timing of [`GenericPhy::new_auto_with`] instead.
+    /// Defaults to 500ms.
+    pub fn set_reset_timeout_us(&mut self, us: u32) {
+        self.reset_timeout_us = us
+    }
+
+    /// Read the current link status, including the negotiated speed and duplex mode.
+    pub fn link_status<S: StationManagement>(&mut self, sm: &mut S) -> LinkStatus {
+        embassy_futures::block_on(self.link_status_async(sm))
+    }
+
+    /// Read the link status of a multi-gigabit PHY through its clause 45 PMA/PMD and PCS registers.
+    ///
+    /// The link state comes from PCS status 1 (3.1) and the speed from the speed selection in
+    /// PMA/PMD control 1 (1.0), checked against the PMA/PMD speed ability (1.4). 2.5G, 5G and 10G
+    /// links are always full duplex. Slower links are resolved through the clause 22 registers
+    /// like [`link_status`](Self::link_status).
+    pub fn link_status_c45<S: StationManagement>(&mut self, sm: &mut S) -> LinkStatus {
+        let pcs = PcsStat1(sm.smi_read_mmd(self.phy_addr, PcsStat1::ADDR));
+        if !pcs.link_up() {
+            return LinkStatus::Down;
+        }
+
+        let ctrl = PmaCtrl1(sm.smi_read_mmd(self.phy_addr, PmaCtrl1::ADDR));
+        let ability = PmaSpeedAbility(sm.smi_read_mmd(self.phy_addr, PmaSpeedAbility::ADDR));
+        match resolve_c45(ctrl, ability) {
+            Some(speed) if speed >= Speed::_2500 => LinkStatus::Up {
+                speed,
+                duplex: DuplexMode::Full,
+            },
+            _ => self.link_status(sm),
+        }
+    }
+
+    /// Read the flow control negotiated with the link partner.
+    ///
+    /// Only meaningful once auto-negotiation completed on a full duplex link; the MAC flow control
+    /// registers can be programmed from the result.
+    pub fn flow_control_status<S: StationManagement>(&mut self, sm: &mut S) -> FlowControl {
+        let antx = sm.smi_read(self.phy_addr, PHY_REG_ANTX);
+        let anrx = sm.smi_read(self.phy_addr, PHY_REG_ANRX);
+        resolve_pause(antx, anrx)
+    }
+
+    /// Report the fault conditions seen since the last call.
+    ///
+    /// The jabber and remote fault bits in BMSR latch high until read. The link polling functions
+    /// also read BMSR, so the driver keeps every fault bit it reads until this function reports it.
+    pub fn link_faults<S: StationManagement>(&mut self, sm: &mut S) -> LinkFaults {
+        let bsr = Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR));
+        self.latch_faults(bsr);
+        core::mem::take(&mut self.latched_faults)
+    }
+
+    /// Read the current link status over an asynchronous SMI, like [`GenericPhy::link_status`].
+    pub async fn link_status_async<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let bsr = Bmsr(sm.smi_read(self.phy_addr, Bmsr::ADDR).await);
+        if !self.bsr_link_up(bsr) {
+            return LinkStatus::Down;
+        }
+        if let Some((speed, duplex)) = self.fixed_link() {
+            return LinkStatus::Up { speed, duplex };
+        }
+
+        // 1000BASE-T abilities are only present on PHYs with extended status
+        if bsr.extended_status() {
+            let ctrl = MsControl(sm.smi_read(self.phy_addr, MsControl::ADDR).await);
+            let status = MsStatus(sm.smi_read(self.phy_addr, MsStatus::ADDR).await);
+            if let Some(duplex) = resolve_1000(ctrl, status) {
+                return LinkStatus::Up {
+                    speed: Speed::_1000,
+                    duplex,
+                };
+            }
+        }
+
+        let antx = sm.smi_read(self.phy_addr, PHY_REG_ANTX).await;
+        let anrx = sm.smi_read(self.phy_addr, PHY_REG_ANRX).await;
+        resolve_an(antx, anrx)
+    }
+
+    /// Read the link status over an asynchronous SMI, reporting [`LinkStatus::Down`] if the SMI
+    /// doesn't answer within the link timeout.
+    ///
+    /// This keeps link monitoring from hanging when the PHY is removed while the SMI waits for it.
+    /// See [`GenericPhy::set_link_timeout`].
+    ///
+    /// The timeout can only fire while an SMI access is pending, so it only helps with buses that
+    /// wait asynchronously, such as [`SpiMdio`](super::SpiMdio). A blocking [`StationManagement`],
+    /// including the SMI of the Ethernet peripheral, is used through its
+    /// [`StationManagementAsync`](super::StationManagementAsync) impl, which completes every access
+    /// before returning. The timeout never preempts such a bus, however long an access takes.
+    #[cfg(feature = "time")]
+    pub async fn poll_link_timeout<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let timeout = self.link_timeout;
+        embassy_time::with_timeout(timeout, self.link_status_async(sm))
+            .await
+            .unwrap_or(LinkStatus::Down)
+    }
+
+    /// Wait until the link status differs from the one last reported by this function.
+    ///
+    /// The PHY is polled over the asynchronous SMI at the configured poll interval. The first call
+    /// compares against [`LinkStatus::Down`], so it returns as soon as the link is up.
+    ///
+    /// This future is cancel-safe: dropping it leaves the PHY untouched.
+    #[cfg(feature = "time")]
+    pub async fn wait_for_link_change<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let poll_interval = self.poll_interval;
+        self.wait_for_link_change_with(sm, || Timer::after(poll_interval)).await
+    }
+
+    // Poll the link until its status changes, awaiting `delay` between polls.
+    #[cfg(feature = "time")]
+    async fn wait_for_link_change_with<S: super::StationManagementAsync, F: core::future::Future<Output = ()>>(
+        &mut self,
+        sm: &mut S,
+        mut delay: impl FnMut() -> F,
+    ) -> LinkStatus {
+        loop {
+            let status = self.link_status_async(sm).await;
+            if self.observe_link_status(status) {
+                return status;
+            }
+            delay().await;
+        }
+    }
+
+    /// Wait until the link is up and return its speed and duplex mode.
+    ///
+    /// The PHY is polled over the asynchronous SMI at the configured poll interval.
+    ///
+    /// This future is cancel-safe: it only reads PHY registers, so dropping it leaves the PHY untouched.
+    #[cfg(feature = "time")]
+    pub async fn wait_link_up<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let poll_interval = self.poll_interval;
+        self.wait_link_state(sm, true, || Timer::after(poll_interval)).await
+    }
+
+    /// Wait until the link is down.
+    ///
+    /// The PHY is polled over the asynchronous SMI at the configured poll interval.
+    ///
+    /// This future is cancel-safe: it only reads PHY registers, so dropping it leaves the PHY untouched.
+    #[cfg(feature = "time")]
+    pub async fn wait_link_down<S: super::StationManagementAsync>(&mut self, sm: &mut S) {
+        let poll_interval = self.poll_interval;
+        self.wait_link_state(sm, false, || Timer::after(poll_interval)).await;
+    }
+
+    // Poll the link until it is up (or down, if `up` is false), awaiting `delay` between polls.
+    #[cfg(feature = "time")]
+    async fn wait_link_state<S: super::StationManagementAsync, F: core::future::Future<Output = ()>>(
+        &mut self,
+        sm: &mut S,
+        up: bool,
+        mut delay: impl FnMut() -> F,
+    ) -> LinkStatus {
+        loop {
+            let status = self.link_status_async(sm).await;
+            if status.is_up() == up {
+                return status;
+            }
+            delay().await;
+        }
+    }
+
+    // Record a link status, returning `true` if it differs from the previous one.
+    #[cfg(feature = "time")]
+    fn observe_link_status(&mut self, status: LinkStatus) -> bool {
+        let changed = status != self.last_link_status;
+        self.last_link_status = status;
+        changed
+    }
+
+    /// Read the link status without registering a waker, and report how long to wait until the next poll.
+    ///
+    /// This is intended for power-managed schedulers that want to sleep precisely until the next
+    /// poll instead of relying on the internal timer used by [`Phy::poll_link`].
+    ///
+    /// The returned duration is the configured poll interval. It is `None` if the PHY address is not
+    /// known yet, i.e. auto-detection in [`Phy::phy_reset`] has not run; the link is reported as down in that case.
+    #[cfg(feature = "time")]
+    pub fn poll_link_with_deadline<S: StationManagement>(&mut self, sm: &mut S) -> (LinkStatus, Option<Duration>) {
+        if self.phy_addr == 0xFF {
+            return (LinkStatus::Down, None);
+        }
+
+        (self.link_status(sm), Some(self.poll_interval))
+    }
+}
+
+impl PhyLinkStatus for GenericPhy {
+    async fn link_status_async<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        GenericPhy::link_status_async(self, sm).await
+    }
+}
+
+/// Monitor the link of `phy` and call `on_change` on every change of the link status.
+///
+/// The PHY is polled over the asynchronous SMI every `poll_interval`. A new status is
+/// only reported once two consecutive polls agree, so a short glitch of the link doesn't trigger
+/// the callback. The first report is the first stable status that differs from [`LinkStatus::Down`].
+///
+/// The [`Ethernet`](super::Ethernet) driver already reports the PHY link state to `embassy-net`
+/// through [`Driver::link_state`](embassy_net_driver::Driver::link_state). Use this function when the
+/// PHY is managed outside of that driver, e.g. to reprogram the MAC with
+/// [`LinkStatus::mac_config`] and forward the state to a network device built with
+/// `embassy-net-driver-channel`, whose `StateRunner::set_link_state` takes the place of the callback.
+#[cfg(feature = "embassy-net")]
+pub async fn run_phy<P: PhyLinkStatus, S: super::StationManagementAsync>(
+    phy: &mut P,
+    sm: &mut S,
+    poll_interval: Duration,
+    mut on_change: impl FnMut(LinkStatus),
+) -> ! {
+    let mut debounce = LinkDebounce::new(LinkStatus::Down, 2);
+    loop {
+        let status = phy.link_status_async(sm).await;
+        if let Some(status) = debounce.update(status) {
+            on_change(status);
+        }
+        Timer::after(poll_interval).await;
+    }
+}
+
+// Reports a link state once it was seen on `stable_count` consecutive polls and differs from the
+// last report. Shared by `run_phy` and `DebouncedPhy`.
+pub(crate) struct LinkDebounce<T> {
+    reported: T,
+    pending: Option<T>,
+    count: u8,
+    stable_count: u8,
+}
+
+impl<T: Copy + PartialEq> LinkDebounce<T> {
+    pub(crate) const fn new(initial: T, stable_count: u8) -> Self {
+        Self {
+            reported: initial,
+            pending: None,
+            count: 0,
+            stable_count,
+        }
+    }
+
+    pub(crate) fn set_stable_count(&mut self, stable_count: u8) {
+        self.stable_count = stable_count;
+    }
+
+    pub(crate) fn reported(&self) -> T {
+        self.reported
+    }
+
+    // Forget the polls so far and report `initial`.
+    pub(crate) fn reset(&mut self, initial: T) {
+        self.reported = initial;
+        self.pending = None;
+        self.count = 0;
+    }
+
+    pub(crate) fn update(&mut self, status: T) -> Option<T> {
+        if status == self.reported {
+            self.pending = None;
+            self.count = 0;
+            return None;
+        }
+        if self.pending != Some(status) {
+            self.pending = Some(status);
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        if self.count < self.stable_count {
+            return None;
+        }
+        self.reset(status);
+        Some(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::{Action, RecordingMdioBus, RecordingMdioBusAsync};
+    use crate::eth::regs::Reg13Op;
+    use crate::eth::CableTestError;
+
+    #[test]
+    fn phy_addr_after_detection() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new_auto();
+        assert_eq!(phy.phy_addr(), None);
+        phy.reset(&mut sm, |_| {}).unwrap();
+        assert_eq!(phy.phy_addr(), Some(0));
+        // Detection only resets and polls the PHY, logging doesn't add accesses
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Write(0, C22::BMCR, PHY_REG_BCR_RESET),
+                Action::Read(0, C22::BMCR),
+                Action::Read(0, C22::BMCR)
+            ]
+        );
+
+        assert_eq!(phy.set_phy_addr(32), Err(InvalidPhyAddr(32)));
+        assert_eq!(phy.phy_addr(), Some(0));
+        phy.set_phy_addr(31).unwrap();
+        assert_eq!(phy.phy_addr(), Some(31));
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_address() {
+        assert!(GenericPhy::try_new(31).is_ok());
+        assert!(matches!(GenericPhy::try_new(32), Err(InvalidPhyAddr(32))));
+    }
+
+    #[test]
+    fn reset_waits_post_reset_delay() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(3);
+        phy.set_post_reset_delay_us(5000);
+
+        let mut delays = Vec::new();
+        phy.reset(&mut sm, |us| delays.push(us)).unwrap();
+
+        assert_eq!(delays, [5000, DEFAULT_RESET_SETTLE_US]);
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Write(3, C22::BMCR, PHY_REG_BCR_RESET),
+                Action::Read(3, C22::BMCR)
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_settle_delay() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new_auto();
+        phy.set_reset_settle_us(300);
+
+        let mut delays = Vec::new();
+        phy.reset(&mut sm, |us| delays.push(us)).unwrap();
+        assert_eq!(delays, [DEFAULT_POST_RESET_DELAY_US, 300]);
+
+        phy.set_reset_settle_us(0);
+        delays.clear();
+        phy.reset(&mut sm, |us| delays.push(us)).unwrap();
+        assert_eq!(delays, [DEFAULT_POST_RESET_DELAY_US]);
+    }
+
+    // Addresses that a reset was written to, in order.
+    fn reset_writes(sm: &RecordingMdioBus) -> Vec<u8> {
+        sm.actions
+            .iter()
+            .filter_map(|action| match *action {
+                Action::Write(addr, C22::BMCR, PHY_REG_BCR_RESET) => Some(addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn auto_detect_respects_probe_attempts() {
+        // Absent PHYs read back all-ones, so the reset bit never clears.
+        let mut sm = RecordingMdioBus::with_phys([]);
+        let mut phy = GenericPhy::new_auto_with(3, 50_000);
+        let mut delays = Vec::new();
+        assert_eq!(phy.reset(&mut sm, |us| delays.push(us)), Err(PhyError::NoPhyFound));
+        let reads = sm.actions.iter().filter(|action| matches!(action, Action::Read(..)));
+        assert_eq!(reads.count(), 32 * 3);
+        // No post-reset delay after resetting each address
+        assert_eq!(delays, [50_000; 32 * 3]);
+    }
+
+    #[test]
+    fn post_reset_delay_once_after_detection() {
+        let mut sm = RecordingMdioBus::with_phys([2]);
+        let mut phy = GenericPhy::new_auto_with(1, 10);
+        let mut delays = Vec::new();
+        phy.reset(&mut sm, |us| delays.push(us)).unwrap();
+        assert_eq!(phy.phy_addr(), Some(2));
+        assert_eq!(delays, [10, 10, DEFAULT_POST_RESET_DELAY_US, DEFAULT_RESET_SETTLE_US]);
+    }
+
+    #[test]
+    fn reset_with_pin_pulses_then_resets() {
+        #[derive(Default)]
+        struct Pin {
+            levels: Vec<bool>,
+        }
+
+        impl embedded_hal_1::digital::ErrorType for Pin {
+            type Error = core::convert::Infallible;
+        }
+
+        impl OutputPin for Pin {
+            fn set_low(&mut self) -> Result<(), Self::Error> {
+                self.levels.push(false);
+                Ok(())
+            }
+
+            fn set_high(&mut self) -> Result<(), Self::Error> {
+                self.levels.push(true);
+                Ok(())
+            }
+        }
+
+        let mut sm = RecordingMdioBus::new();
+        let mut rst = Pin::default();
+        let mut phy = GenericPhy::new(3);
+        phy.set_reset_pulse_us(500);
+
+        let mut delays = Vec::new();
+        phy.reset_with_pin(&mut sm, &mut rst, |us| delays.push(us)).unwrap();
+        assert_eq!(rst.levels, [false, true]);
+        assert_eq!(delays, [500, 0, DEFAULT_POST_RESET_DELAY_US, DEFAULT_RESET_SETTLE_US]);
+        assert_eq!(sm.actions[0], Action::Write(3, C22::BMCR, PHY_REG_BCR_RESET));
+    }
+
+    #[test]
+    fn reset_times_out() {
+        let mut sm = RecordingMdioBus::new();
+        sm.stuck_in_reset = true;
+        let mut phy = GenericPhy::new(1);
+        phy.set_reset_timeout_us(3 * RESET_POLL_US);
+        let mut delays = Vec::new();
+        assert_eq!(phy.reset(&mut sm, |us| delays.push(us)), Err(PhyError::ResetTimeout));
+        assert_eq!(sm.actions[1..], [Action::Read(1, C22::BMCR); 4]);
+        assert_eq!(
+            delays,
+            [DEFAULT_POST_RESET_DELAY_US, RESET_POLL_US, RESET_POLL_US, RESET_POLL_US]
+        );
+    }
+
+    #[test]
+    fn start_probes_once() {
+        let mut sm = RecordingMdioBus::with_phys([5]);
+        let mut phy = GenericPhy::new_auto_with(1, 0);
+        phy.start_with(&mut sm, |_| {}).unwrap();
+        assert_eq!(reset_writes(&sm), [0, 1, 2, 3, 4, 5]);
+
+        sm.actions.clear();
+        phy.start_with(&mut sm, |_| {}).unwrap();
+        assert_eq!(reset_writes(&sm), [5]);
+    }
+
+    #[test]
+    fn auto_detect_probes_only_given_addresses() {
+        let mut sm = RecordingMdioBus::with_phys([]);
+        let mut phy = GenericPhy::new_auto_in(4..=7);
+        assert_eq!(phy.reset(&mut sm, |_| {}), Err(PhyError::NoPhyFound));
+        assert_eq!(reset_writes(&sm), [4, 5, 6, 7]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no PHY address to probe")]
+    fn auto_detect_rejects_empty_range() {
+        #[allow(clippy::reversed_empty_ranges)]
+        GenericPhy::new_auto_in(5..=2);
+    }
+
+    #[test]
+    fn c45_multi_gig_link_status() {
+        for (pma_ctrl1, speed) in [(0x2058, Speed::_2500), (0x205c, Speed::_5000), (0x2040, Speed::_10000)] {
+            // A multi-gig PHY with link up, capable of 2.5G, 5G and 10G.
+            let mut sm = RecordingMdioBus::new();
+            sm.set_mmd(0, C45::PCS_STAT1, PcsStat1::default().with_link_up(true).0);
+            sm.set_mmd(0, C45::PMA_CTRL1, pma_ctrl1);
+            let ability = PmaSpeedAbility::default()
+                .with_can_10g(true)
+                .with_can_2g5(true)
+                .with_can_5g(true);
+            sm.set_mmd(0, C45::PMA_SPEED, ability.0);
+            let mut phy = GenericPhy::new(0);
+            assert_eq!(
+                phy.link_status_c45(&mut sm),
+                LinkStatus::Up {
+                    speed,
+                    duplex: DuplexMode::Full
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn c45_speed_decode() {
+        let all = PmaSpeedAbility(0xffff);
+        assert_eq!(resolve_c45(PmaCtrl1(0x2058), all), Some(Speed::_2500));
+        assert_eq!(resolve_c45(PmaCtrl1(0x205c), all), Some(Speed::_5000));
+        assert_eq!(resolve_c45(PmaCtrl1(0x2040), all), Some(Speed::_10000));
+        assert_eq!(resolve_c45(PmaCtrl1(0x0040), all), Some(Speed::_1000));
+        // Bits 5:2 are ignored unless bits 13 and 6 are both set
+        assert_eq!(resolve_c45(PmaCtrl1(0x205c & !0x0040), all), Some(Speed::_100));
+        // Reserved selection
+        assert_eq!(resolve_c45(PmaCtrl1(0x2044), all), None);
+        // Selected speed the PMA/PMD isn't capable of
+        assert_eq!(
+            resolve_c45(PmaCtrl1(0x205c), PmaSpeedAbility(0).with_can_2g5(true)),
+            None
+        );
+    }
+
+    #[test]
+    fn poll_link_on_irq_reads_only_when_pending() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(
+            0,
+            C22::BMSR,
+            Bmsr::default().with_link_up(true).with_autoneg_complete(true).0,
+        );
+        let mut phy = GenericPhy::new(0);
+        let irq = PhyIrq::new();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        assert!(phy.poll_link_on_irq(&mut sm, &mut cx, &irq));
+        assert_eq!(sm.actions, [Action::Read(0, C22::BMSR)]);
+
+        sm.actions.clear();
+        sm.set(0, C22::BMSR, 0);
+        assert!(phy.poll_link_on_irq(&mut sm, &mut cx, &irq));
+        assert!(sm.actions.is_empty());
+
+        irq.signal();
+        assert!(!phy.poll_link_on_irq(&mut sm, &mut cx, &irq));
+        assert_eq!(sm.actions, [Action::Read(0, C22::BMSR)]);
+    }
+
+    #[test]
+    fn scan_bus_skips_empty_addresses() {
+        // Address 9 answers, but has no identifier.
+        let mut sm = RecordingMdioBus::with_phys([1, 5, 9]);
+        sm.set(1, C22::PHYSID1, 0x0007);
+        sm.set(1, C22::PHYSID2, 0xc131);
+        sm.set(5, C22::PHYSID1, 0x0022);
+        sm.set(5, C22::PHYSID2, 0x1561);
+        assert_eq!(
+            scan_bus(&mut sm),
+            [
+                (1, PhyId::from_regs(0x0007, 0xc131)),
+                (5, PhyId::from_regs(0x0022, 0x1561))
+            ]
+        );
+    }
+
+    #[test]
+    fn modify_reads_then_writes_once() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(4, C22::BMCR, 0x1100);
+
+        assert_eq!(sm.smi_modify(4, C22::BMCR, |bcr| bcr | 0x0040), 0x1140);
+        assert_eq!(
+            sm.actions,
+            [Action::Read(4, C22::BMCR), Action::Write(4, C22::BMCR, 0x1140)]
+        );
+    }
+
+    #[test]
+    fn mmd_burst_sets_up_address_once() {
+        let mut sm = RecordingMdioBus::new();
+        for (regnum, val) in (0x8061..).zip([0xbc9a, 0x7856, 0x3412]) {
+            sm.set_mmd(2, C45::new(Mmd::PCS, regnum), val);
+        }
+
+        let mut out = [0; 3];
+        sm.smi_read_mmd_burst(2, C45::new(Mmd::PCS, 0x8061), &mut out);
+        assert_eq!(out, [0xbc9a, 0x7856, 0x3412]);
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Write(2, C22::MMD_CONTROL, 0x0003),
+                Action::Write(2, C22::MMD_DATA, 0x8061),
+                Action::Write(2, C22::MMD_CONTROL, 0x8003),
+                Action::Read(2, C22::MMD_DATA),
+                Action::Read(2, C22::MMD_DATA),
+                Action::Read(2, C22::MMD_DATA),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_many_reads_in_order() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(4, C22::PHYSID1, 0x0007);
+        sm.set(4, C22::BMSR, 0x796d);
+
+        let mut out = [0; 3];
+        sm.smi_read_many(4, &[C22::PHYSID1, C22::BMSR, C22::BMCR], &mut out);
+
+        assert_eq!(out, [0x0007, 0x796d, 0]);
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Read(4, C22::PHYSID1),
+                Action::Read(4, C22::BMSR),
+                Action::Read(4, C22::BMCR)
+            ]
+        );
+    }
+
+    #[test]
+    fn eee_uses_indirect_mmd_access() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(2);
+
+        phy.eee_enable(&mut sm, true);
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Write(2, C22::MMD_CONTROL, 0x0007),
+                Action::Write(2, C22::MMD_DATA, 60),
+                Action::Write(2, C22::MMD_CONTROL, 0x4007),
+                Action::Write(2, C22::MMD_DATA, PHY_REG_EEE_100TX | PHY_REG_EEE_1000T),
+            ]
+        );
+        assert_eq!(sm.get_mmd(2, PHY_REG_EEE_ADV), PHY_REG_EEE_100TX | PHY_REG_EEE_1000T);
+
+        sm.actions.clear();
+        sm.set_mmd(2, PHY_REG_EEE_LPABLE, PHY_REG_EEE_100TX);
+        assert!(phy.eee_link_partner_capable(&mut sm));
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Write(2, C22::MMD_CONTROL, 0x0007),
+                Action::Write(2, C22::MMD_DATA, 61),
+                Action::Write(2, C22::MMD_CONTROL, 0x4007),
+                Action::Read(2, C22::MMD_DATA),
+            ]
+        );
+    }
+
+    #[test]
+    fn loopback_preserves_bcr() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        sm.set(0, C22::BMCR, PHY_REG_BCR_AN | PHY_REG_BCR_100M);
+
+        phy.set_loopback(&mut sm, true);
+        assert_eq!(
+            sm.get(0, C22::BMCR),
+            PHY_REG_BCR_AN | PHY_REG_BCR_100M | PHY_REG_BCR_LOOPBACK
+        );
+
+        phy.set_loopback(&mut sm, false);
+        assert_eq!(sm.get(0, C22::BMCR), PHY_REG_BCR_AN | PHY_REG_BCR_100M);
+    }
+
+    #[test]
+    fn isolate_preserves_bcr() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        sm.set(0, C22::BMCR, PHY_REG_BCR_AN | PHY_REG_BCR_FD);
+
+        phy.set_isolated(&mut sm, true);
+        assert_eq!(
+            sm.get(0, C22::BMCR),
+            PHY_REG_BCR_AN | PHY_REG_BCR_FD | PHY_REG_BCR_ISOLATE
+        );
+        phy.set_isolated(&mut sm, false);
+        assert_eq!(sm.get(0, C22::BMCR), PHY_REG_BCR_AN | PHY_REG_BCR_FD);
+    }
+
+    #[test]
+    fn master_slave_encoding() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        let adv = MsControl::default().with_adv_1000_full(true);
+        sm.set(0, C22::MASTER_SLAVE_CONTROL, adv.0);
+
+        phy.set_master_slave(&mut sm, MasterSlave::Master);
+        assert_eq!(sm.get(0, C22::MASTER_SLAVE_CONTROL), adv.0 | 1 << 12 | 1 << 11);
+        phy.set_master_slave(&mut sm, MasterSlave::Slave);
+        assert_eq!(sm.get(0, C22::MASTER_SLAVE_CONTROL), adv.0 | 1 << 12);
+    }
+
+    #[test]
+    fn power_down_preserves_bcr() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        sm.set(0, C22::BMCR, PHY_REG_BCR_AN | PHY_REG_BCR_100M);
+
+        phy.set_powered(&mut sm, false);
+        assert_eq!(
+            sm.get(0, C22::BMCR),
+            PHY_REG_BCR_AN | PHY_REG_BCR_100M | PHY_REG_BCR_POWERDN
+        );
+        phy.set_powered(&mut sm, true);
+        assert_eq!(sm.get(0, C22::BMCR), PHY_REG_BCR_AN | PHY_REG_BCR_100M);
+    }
+
+    #[test]
+    fn cable_test_unsupported() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        assert_eq!(phy.run_cable_test(&mut sm), Err(CableTestError::Unsupported));
+        assert!(sm.actions.is_empty());
+    }
+
+    #[test]
+    fn c22_dump_table() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(0, C22::BMCR, 0x1140);
+        sm.set(0, C22::BMSR, 0x796d);
+        sm.set(0, C22::PHYSID1, 0x0007);
+        sm.set(0, C22::PHYSID2, 0xc131);
+
+        let mut out = String::new();
+        write_c22_dump(&mut sm, 0, &mut out).unwrap();
+
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert_eq!(lines[0], "BMCR: 0x1140");
+        assert_eq!(lines[1], "BMSR: 0x796d");
+        assert_eq!(lines[2], "PHYSID1: 0x0007");
+        assert_eq!(lines[3], "PHYSID2: 0xc131");
+        assert_eq!(lines[15], "ESTATUS: 0x0000");
+        assert!((0..16).all(|reg| sm.actions[reg] == Action::Read(0, C22(reg as u8))));
+    }
+
+    #[test]
+    fn link_status_async_matches_blocking() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(1);
+        sm.set(1, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP);
+        sm.set(1, C22::ADVERTISE, PHY_REG_AN_100FD);
+        sm.set(1, C22::LPA, PHY_REG_AN_100FD);
+
+        let status = phy.link_status(&mut sm);
+        assert_eq!(embassy_futures::block_on(phy.link_status_async(&mut sm)), status);
+    }
+
+    #[test]
+    fn init_async_matches_blocking() {
+        let mut phy = GenericPhy::new(1);
+        let mut sm = RecordingMdioBus::new();
+        sm.set(1, C22::BMSR, PHY_REG_BSR_ESTATUS);
+        let mut sm_async = RecordingMdioBusAsync::default();
+        sm_async.0.set(1, C22::BMSR, PHY_REG_BSR_ESTATUS);
+
+        Phy::phy_init(&mut phy, &mut sm);
+        embassy_futures::block_on(crate::eth::PhyAsync::phy_init(&mut phy, &mut sm_async));
+        assert_eq!(sm_async.0.actions, sm.actions);
+    }
+
+    #[test]
+    fn link_status_resolves_10_100() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(1);
+
+        assert_eq!(phy.link_status(&mut sm), LinkStatus::Down);
+
+        sm.set(1, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP);
+        sm.set(
+            1,
+            C22::ADVERTISE,
+            PHY_REG_AN_10HD | PHY_REG_AN_10FD | PHY_REG_AN_100HD | PHY_REG_AN_100FD,
+        );
+        sm.set(1, C22::LPA, PHY_REG_AN_10FD | PHY_REG_AN_100HD);
+        assert_eq!(
+            phy.link_status(&mut sm),
+            LinkStatus::Up {
+                speed: Speed::_100,
+                duplex: DuplexMode::Half
+            }
+        );
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn link_change_is_reported_once() {
+        // The link is up or down on successive BMSR reads.
+        let mut sm = RecordingMdioBus::new();
+        let bmsr = |up: bool| if up { PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP } else { 0 };
+        sm.script(0, C22::BMSR, [false, false, false, true, true, false].map(bmsr));
+        sm.set(0, C22::ADVERTISE, PHY_REG_AN_100FD);
+        sm.set(0, C22::LPA, PHY_REG_AN_100FD);
+        let bmsr_reads =
+            |sm: &RecordingMdioBus| sm.actions.iter().filter(|a| **a == Action::Read(0, C22::BMSR)).count();
+        let mut phy = GenericPhy::new(0);
+        let mut delays = 0;
+
+        let status = embassy_futures::block_on(phy.wait_for_link_change_with(&mut sm, || {
+            delays += 1;
+            core::future::ready(())
+        }));
+        assert_eq!(
+            status,
+            LinkStatus::Up {
+                speed: Speed::_100,
+                duplex: DuplexMode::Full
+            }
+        );
+        assert_eq!((bmsr_reads(&sm), delays), (4, 3));
+
+        // The up status was reported, so only the link going down ends the next wait.
+        let status = embassy_futures::block_on(phy.wait_for_link_change_with(&mut sm, || {
+            delays += 1;
+            core::future::ready(())
+        }));
+        assert_eq!(status, LinkStatus::Down);
+        assert_eq!((bmsr_reads(&sm), delays), (6, 4));
+    }
+
+    #[test]
+    fn link_status_resolves_all_speeds() {
+        const ALL_10_100: u16 = PHY_REG_AN_10HD | PHY_REG_AN_10FD | PHY_REG_AN_100HD | PHY_REG_AN_100FD;
+        let ms_ctrl = MsControl::default().with_adv_1000_full(true).with_adv_1000_half(true);
+
+        let cases = [
+            (
+                MsStatus::default().with_lp_1000_full(true),
+                ALL_10_100,
+                Speed::_1000,
+                DuplexMode::Full,
+            ),
+            (
+                MsStatus::default().with_lp_1000_half(true),
+                ALL_10_100,
+                Speed::_1000,
+                DuplexMode::Half,
+            ),
+            (MsStatus::default(), ALL_10_100, Speed::_100, DuplexMode::Full),
+            (
+                MsStatus::default(),
+                PHY_REG_AN_100HD | PHY_REG_AN_10FD,
+                Speed::_100,
+                DuplexMode::Half,
+            ),
+            (MsStatus::default(), PHY_REG_AN_10FD, Speed::_10, DuplexMode::Full),
+            (MsStatus::default(), PHY_REG_AN_10HD, Speed::_10, DuplexMode::Half),
+        ];
+
+        for (ms_status, lpa, speed, duplex) in cases {
+            let mut sm = RecordingMdioBus::new();
+            sm.set(0, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP | PHY_REG_BSR_ESTATUS);
+            sm.set(0, C22::ADVERTISE, ALL_10_100);
+            sm.set(0, C22::LPA, lpa);
+            sm.set(0, C22::MASTER_SLAVE_CONTROL, ms_ctrl.0);
+            sm.set(0, C22::MASTER_SLAVE_STATUS, ms_status.0);
+
+            let mut phy = GenericPhy::new(0);
+            assert_eq!(phy.link_status(&mut sm), LinkStatus::Up { speed, duplex });
+        }
+    }
+
+    #[test]
+    fn advertised_default_has_no_pause() {
+        assert_eq!(Advertised::default().anar(), 0x01e1);
+        assert_eq!(Advertised::all().anar(), 0x0de1);
+    }
+
+    #[test]
+    fn advertised_encoding() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        sm.set(0, C22::BMSR, PHY_REG_BSR_ESTATUS);
+        sm.set(0, C22::MASTER_SLAVE_CONTROL, MsControl(0).with_master(true).0);
+
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get(0, C22::ADVERTISE), 0x01e1);
+        assert_eq!(
+            sm.get(0, C22::MASTER_SLAVE_CONTROL),
+            MsControl(0)
+                .with_master(true)
+                .with_adv_1000_half(true)
+                .with_adv_1000_full(true)
+                .0
+        );
+
+        phy.set_advertised(Advertised::BASE100TX_FULL | Advertised::BASE10T_FULL);
+        phy.phy_init(&mut sm);
+        assert_eq!(
+            sm.get(0, C22::ADVERTISE),
+            PHY_REG_AN_SELECTOR_802_3 | PHY_REG_AN_100FD | PHY_REG_AN_10FD
+        );
+        assert_eq!(sm.get(0, C22::MASTER_SLAVE_CONTROL), MsControl(0).with_master(true).0);
+    }
+
+    #[test]
+    fn flow_control_resolution() {
+        const P: u16 = PHY_REG_AN_PAUSE;
+        const A: u16 = PHY_REG_AN_ASYM_PAUSE;
+        let fc = |tx_pause, rx_pause| FlowControl { tx_pause, rx_pause };
+
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        for (local, partner, expected) in [
+            (P, P, fc(true, true)),
+            (P | A, P | A, fc(true, true)),
+            (A, P | A, fc(true, false)),
+            (P | A, A, fc(false, true)),
+            (A, A, fc(false, false)),
+            (P, A, fc(false, false)),
+            (0, P | A, fc(false, false)),
+        ] {
+            sm.set(0, C22::ADVERTISE, local);
+            sm.set(0, C22::LPA, partner);
+            assert_eq!(phy.flow_control_status(&mut sm), expected);
+        }
+    }
+
+    #[test]
+    fn link_faults_survive_polling() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+
+        // Polling the link reads BMSR first, which clears the latched bits in the PHY.
+        sm.set(0, C22::BMSR, PHY_REG_BSR_JABBER | PHY_REG_BSR_FAULT);
+        assert_eq!(phy.link_status(&mut sm), LinkStatus::Down);
+        sm.set(0, C22::BMSR, 0);
+
+        assert_eq!(
+            phy.link_faults(&mut sm),
+            LinkFaults {
+                jabber: true,
+                remote_fault: true
+            }
+        );
+        assert_eq!(phy.link_faults(&mut sm), LinkFaults::default());
+    }
+
+    #[test]
+    fn init_clears_wake_up_status() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+
+        phy.phy_init(&mut sm);
+        assert_eq!(
+            sm.actions[..4],
+            [
+                Action::Write(0, C22::MMD_CONTROL, Reg13Op::Address.control(Mmd::PCS)),
+                Action::Write(0, C22::MMD_DATA, PHY_REG_WUCSR.regnum),
+                Action::Write(0, C22::MMD_CONTROL, Reg13Op::Data.control(Mmd::PCS)),
+                Action::Write(0, C22::MMD_DATA, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn restart_autoneg_preserves_bcr() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        sm.set(0, C22::BMCR, PHY_REG_BCR_100M | PHY_REG_BCR_FD);
+
+        phy.restart_autoneg(&mut sm);
+        assert_eq!(
+            sm.actions.last(),
+            Some(&Action::Write(
+                0,
+                C22::BMCR,
+                PHY_REG_BCR_100M | PHY_REG_BCR_FD | PHY_REG_BCR_AN | PHY_REG_BCR_ANRST
+            ))
+        );
+    }
+
+    #[test]
+    fn forced_link() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+
+        assert_eq!(
+            phy.force_link(Speed::_1000, DuplexMode::Full),
+            Err(UnsupportedSpeed(Speed::_1000))
+        );
+        phy.force_link(Speed::_100, DuplexMode::Full).unwrap();
+        phy.phy_init(&mut sm);
+        assert_eq!(
+            sm.actions.last(),
+            Some(&Action::Write(0, C22::BMCR, PHY_REG_BCR_100M | PHY_REG_BCR_FD))
+        );
+
+        assert_eq!(phy.link_status(&mut sm), LinkStatus::Down);
+
+        // Auto-negotiation never completes on a forced link.
+        sm.set(0, C22::BMSR, PHY_REG_BSR_UP);
+        assert_eq!(
+            phy.link_status(&mut sm),
+            LinkStatus::Up {
+                speed: Speed::_100,
+                duplex: DuplexMode::Full
+            }
+        );
+    }
+
+    #[test]
+    fn fiber_mode_link() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(0);
+        phy.set_fiber_mode(true);
+
+        phy.phy_init(&mut sm);
+        assert_eq!(
+            sm.actions.last(),
+            Some(&Action::Write(0, C22::BMCR, PHY_REG_BCR_100M | PHY_REG_BCR_FD))
+        );
+
+        // The link partner abilities are meaningless on fiber and must be ignored.
+        sm.set(0, C22::ADVERTISE, 0x0021);
+        sm.set(0, C22::LPA, 0x0021);
+        assert_eq!(phy.link_status(&mut sm), LinkStatus::Down);
+
+        sm.set(0, C22::BMSR, PHY_REG_BSR_UP);
+        assert_eq!(
+            phy.link_status(&mut sm),
+            LinkStatus::Up {
+                speed: Speed::_100,
+                duplex: DuplexMode::Full
+            }
+        );
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn poll_link_with_deadline_reports_poll_interval() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(0, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP);
+        sm.set(0, C22::ADVERTISE, PHY_REG_AN_100FD);
+        sm.set(0, C22::LPA, PHY_REG_AN_100FD);
+
+        let mut phy = GenericPhy::new(0);
+        phy.set_poll_interval(Duration::from_millis(250));
+
+        let (status, next) = phy.poll_link_with_deadline(&mut sm);
+        assert!(status.is_up());
+        assert_eq!(next, Some(Duration::from_millis(250)));
+
+        let mut phy = GenericPhy::new_auto();
+        assert_eq!(phy.poll_link_with_deadline(&mut sm), (LinkStatus::Down, None));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn wait_link_returns_when_already_in_state() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(1);
+
+        // Count the poll intervals instead of waiting on a timer, which needs a time driver.
+        let mut delays = 0;
+        let mut delay = || {
+            delays += 1;
+            core::future::ready(())
+        };
+
+        // The link is down, so no poll interval elapses.
+        assert_eq!(
+            embassy_futures::block_on(phy.wait_link_state(&mut sm, false, &mut delay)),
+            LinkStatus::Down
+        );
+
+        sm.set(1, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP);
+        sm.set(1, C22::ADVERTISE, PHY_REG_AN_10FD);
+        sm.set(1, C22::LPA, PHY_REG_AN_10FD);
+        assert_eq!(
+            embassy_futures::block_on(phy.wait_link_state(&mut sm, true, &mut delay)),
+            LinkStatus::Up {
+                speed: Speed::_10,
+                duplex: DuplexMode::Full
+            }
+        );
+        assert_eq!(delays, 0);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn wait_link_returns_after_transition() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = GenericPhy::new(1);
+        sm.set(1, C22::BMSR, PHY_REG_BSR_ANDONE | PHY_REG_BSR_UP);
+        sm.set(1, C22::ADVERTISE, PHY_REG_AN_100FD);
+        sm.set(1, C22::LPA, PHY_REG_AN_100FD);
+        // The link comes up on the fourth poll.
+        sm.script(1, C22::BMSR, [0; 3]);
+
+        let mut delays = 0;
+        let mut delay = || {
+            delays += 1;
+            core::future::ready(())
+        };
+        assert_eq!(
+            embassy_futures::block_on(phy.wait_link_state(&mut sm, true, &mut delay)),
+            LinkStatus::Up {
+                speed: Speed::_100,
+                duplex: DuplexMode::Full
+            }
+        );
+        assert_eq!(delays, 3);
+        let bmsr_reads = sm.actions.iter().filter(|a| **a == Action::Read(1, C22::BMSR));
+        assert_eq!(bmsr_reads.count(), 4);
+    }
+
+    #[test]
+    fn link_debounce_ignores_glitches() {
+        let up = LinkStatus::Up {
+            speed: Speed::_100,
+            duplex: DuplexMode::Full,
+        };
+        let mut debounce = LinkDebounce::new(LinkStatus::Down, 2);
+
+        let script = [
+            up,
+            LinkStatus::Down,
+            up,
+            up,
+            up,
+            LinkStatus::Down,
+            up,
+            LinkStatus::Down,
+            LinkStatus::Down,
+        ];
+        let changes: Vec<_> = script.iter().filter_map(|&status| debounce.update(status)).collect();
+
+        assert_eq!(changes, [up, LinkStatus::Down]);
     }
 }
@@ -0,0 +1,415 @@
+//! Microchip (Micrel) KSZ8081 PHY
+
+use core::task::Context;
+
+use super::generic_phy::blocking_delay_us;
+use super::generic_phy::phy_consts::{PHY_REG_BCR_100M, PHY_REG_BCR_AN, PHY_REG_BCR_ANRST, PHY_REG_BCR_FD};
+use super::regs::{Bmsr, C22};
+use super::{
+    CableDiagnostics, CableReport, CableTestError, DuplexMode, GenericPhy, LinkStatus, Mdix, MdixConfig, PairState,
+    Phy, PhyCounters, PhyError, PhyLinkStatus, PhyStats, RmiiClock, RmiiClockConfig, Speed, StationManagement,
+};
+
+// RXER Counter register, cleared on read.
+const RXER_COUNTER: C22 = C22::vendor_specific::<0x15>();
+
+// LinkMD Control/Status register.
+const LINKMD: C22 = C22::vendor_specific::<0x1d>();
+const LINKMD_ENABLE: u16 = 1 << 15;
+const LINKMD_RESULT_SHIFT: u16 = 13;
+const LINKMD_RESULT_MASK: u16 = 0b11;
+const LINKMD_RESULT_NORMAL: u16 = 0b00;
+const LINKMD_RESULT_OPEN: u16 = 0b01;
+const LINKMD_RESULT_SHORT: u16 = 0b10;
+const LINKMD_FAULT_COUNT_MASK: u16 = 0x1ff;
+// Each step of the fault counter is about 0.38m of cable.
+const LINKMD_CM_PER_COUNT: u16 = 38;
+const LINKMD_POLL_US: u32 = 1000;
+const LINKMD_TIMEOUT_US: u32 = 100_000;
+
+// PHY Control 1 register.
+const PHYCTRL1: C22 = C22::vendor_specific::<0x1e>();
+const PHYCTRL1_OP_MODE_MASK: u16 = 0b111;
+const PHYCTRL1_OP_MODE_10HD: u16 = 0b001;
+const PHYCTRL1_OP_MODE_100HD: u16 = 0b010;
+const PHYCTRL1_OP_MODE_10FD: u16 = 0b101;
+const PHYCTRL1_OP_MODE_100FD: u16 = 0b110;
+
+// PHY Control 2 register.
+const PHYCTRL2: C22 = C22::vendor_specific::<0x1f>();
+const PHYCTRL2_RMII_REF_CLK_SEL: u16 = 1 << 7;
+const PHYCTRL2_MDI_SELECT: u16 = 1 << 14;
+const PHYCTRL2_PAIR_SWAP_DISABLE: u16 = 1 << 13;
+
+/// Microchip (Micrel) KSZ8081 PHY.
+///
+/// Resets, initializes and polls the link like [`GenericPhy`], but resolves the negotiated speed and
+/// duplex mode from the operation mode indication in the vendor PHY Control 1 register.
+pub struct Ksz8081 {
+    inner: GenericPhy,
+    rmii_clock_50mhz: Option<bool>,
+    mdix: Mdix,
+}
+
+impl Ksz8081 {
+    /// Construct the PHY at address `phy_addr`.
+    ///
+    /// # Panics
+    /// `phy_addr` must be in range `0..32`
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            inner: GenericPhy::new(phy_addr),
+            rmii_clock_50mhz: None,
+            mdix: Mdix::Auto,
+        }
+    }
+
+    /// Construct the PHY, probing all addresses from 0 to 31 during initialization.
+    ///
+    /// # Panics
+    /// Initialization panics if the PHY didn't respond on any address
+    pub fn new_auto() -> Self {
+        Self {
+            inner: GenericPhy::new_auto(),
+            rmii_clock_50mhz: None,
+            mdix: Mdix::Auto,
+        }
+    }
+
+    /// Select the RMII reference clock input.
+    ///
+    /// `true` expects a 50MHz reference clock on the XI pin, as used by the KSZ8081RND. `false` selects
+    /// a 25MHz crystal or clock, from which the KSZ8081RNA generates the 50MHz RMII clock itself. Takes
+    /// effect on the next [`Phy::phy_init`]. Until this is called, [`Phy::phy_init`] keeps the clock
+    /// selected by the board strap.
+    pub fn set_rmii_clock_50mhz(&mut self, enable: bool) {
+        self.rmii_clock_50mhz = Some(enable);
+    }
+
+    /// Read the current link status, including the negotiated speed and duplex mode.
+    pub fn link_status<S: StationManagement>(&mut self, sm: &mut S) -> LinkStatus {
+        let phy_addr = self.inner.smi_addr();
+        if !Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR)).link_up() {
+            return LinkStatus::Down;
+        }
+        resolve_op_mode(sm.smi_read(phy_addr, PHYCTRL1.0))
+    }
+}
+
+impl Phy for Ksz8081 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_reset(sm);
+    }
+
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.inner.try_phy_reset(sm)
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        let phy_addr = self.inner.smi_addr();
+        let clock_50mhz = self.rmii_clock_50mhz;
+        let mdix = self.mdix;
+        sm.smi_modify(phy_addr, PHYCTRL2, |ctrl2| {
+            mdix_bits(ref_clk_bits(ctrl2, clock_50mhz), mdix)
+        });
+
+        self.inner.phy_init(sm);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        self.inner.poll_link(sm, cx)
+    }
+}
+
+/// The clock mode takes effect immediately and is kept by later calls of [`Phy::phy_init`].
+///
+/// [`RmiiClock::Internal`] selects a 25MHz crystal, from which the KSZ8081RNA generates the RMII
+/// clock; [`RmiiClock::External`] expects a 50MHz clock on the XI pin. This is the same setting as
+/// [`Ksz8081::set_rmii_clock_50mhz`].
+impl PhyLinkStatus for Ksz8081 {
+    async fn link_status_async<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let phy_addr = self.inner.smi_addr();
+        if !Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR).await).link_up() {
+            return LinkStatus::Down;
+        }
+        resolve_op_mode(sm.smi_read(phy_addr, PHYCTRL1.0).await)
+    }
+}
+
+impl RmiiClockConfig for Ksz8081 {
+    fn set_rmii_clock_source<S: StationManagement>(&mut self, sm: &mut S, source: RmiiClock) {
+        self.rmii_clock_50mhz = Some(source == RmiiClock::External);
+        let clock_50mhz = self.rmii_clock_50mhz;
+        sm.smi_modify(self.inner.smi_addr(), PHYCTRL2, |ctrl2| {
+            ref_clk_bits(ctrl2, clock_50mhz)
+        });
+    }
+}
+
+/// The RXER counter (register 0x15) counts received frames with symbol errors and is reported as
+/// both [`PhyStats::rx_errors`] and [`PhyStats::symbol_errors`]. It is 16 bits wide and cleared
+/// on read, so every call returns the errors since the previous one. There is no false carrier
+/// counter.
+impl PhyCounters for Ksz8081 {
+    fn read_counters<S: StationManagement>(&mut self, sm: &mut S) -> PhyStats {
+        let rxer = sm.smi_read(self.inner.smi_addr(), RXER_COUNTER.0) as u32;
+        PhyStats {
+            rx_errors: rxer,
+            symbol_errors: rxer,
+            false_carrier: 0,
+        }
+    }
+}
+
+/// Uses the MDI/MDI-X select and pair swap disable bits of PHY Control 2. The mode takes effect
+/// immediately and is kept by later calls of [`Phy::phy_init`].
+impl MdixConfig for Ksz8081 {
+    fn set_mdix<S: StationManagement>(&mut self, sm: &mut S, mode: Mdix) {
+        self.mdix = mode;
+        sm.smi_modify(self.inner.smi_addr(), PHYCTRL2, |ctrl2| mdix_bits(ctrl2, mode));
+    }
+}
+
+/// LinkMD cable test through register 0x1d. It tests the pair the PHY receives on in MDI mode,
+/// reported as the first pair; the other pairs are reported as [`PairState::NotTested`].
+///
+/// As required by LinkMD, auto-negotiation and auto MDI/MDI-X are disabled while the test runs.
+/// Both are restored afterwards, which restarts auto-negotiation.
+impl CableDiagnostics for Ksz8081 {
+    fn run_cable_test<S: StationManagement>(&mut self, sm: &mut S) -> Result<CableReport, CableTestError> {
+        self.run_cable_test_with(sm, blocking_delay_us)
+    }
+}
+
+impl Ksz8081 {
+    fn run_cable_test_with<S: StationManagement>(
+        &mut self,
+        sm: &mut S,
+        mut delay_us: impl FnMut(u32),
+    ) -> Result<CableReport, CableTestError> {
+        let phy_addr = self.inner.smi_addr();
+        let bmcr = sm.smi_read(phy_addr, C22::BMCR.0);
+        let ctrl2 = sm.smi_read(phy_addr, PHYCTRL2.0);
+
+        // 100Mbit/s full duplex without auto-negotiation, forced to MDI.
+        sm.smi_write(phy_addr, C22::BMCR.0, PHY_REG_BCR_100M | PHY_REG_BCR_FD);
+        sm.smi_write(phy_addr, PHYCTRL2.0, mdix_bits(ctrl2, Mdix::Mdi));
+        sm.smi_write(phy_addr, LINKMD.0, LINKMD_ENABLE);
+
+        let mut waited_us = 0;
+        let result = loop {
+            let linkmd = sm.smi_read(phy_addr, LINKMD.0);
+            if linkmd & LINKMD_ENABLE == 0 {
+                break decode_linkmd(linkmd);
+            }
+            if waited_us >= LINKMD_TIMEOUT_US {
+                break Err(CableTestError::Timeout);
+            }
+            delay_us(LINKMD_POLL_US);
+            waited_us += LINKMD_POLL_US;
+        };
+
+        sm.smi_write(phy_addr, PHYCTRL2.0, ctrl2);
+        let restart = match bmcr & PHY_REG_BCR_AN {
+            0 => 0,
+            _ => PHY_REG_BCR_ANRST,
+        };
+        sm.smi_write(phy_addr, C22::BMCR.0, bmcr | restart);
+        result
+    }
+}
+
+// Decode a completed LinkMD test.
+fn decode_linkmd(linkmd: u16) -> Result<CableReport, CableTestError> {
+    let distance_cm = Some((linkmd & LINKMD_FAULT_COUNT_MASK) * LINKMD_CM_PER_COUNT);
+    let (state, distance_cm) = match (linkmd >> LINKMD_RESULT_SHIFT) & LINKMD_RESULT_MASK {
+        LINKMD_RESULT_NORMAL => (PairState::Ok, None),
+        LINKMD_RESULT_OPEN => (PairState::Open, distance_cm),
+        LINKMD_RESULT_SHORT => (PairState::Short, distance_cm),
+        _ => return Err(CableTestError::Failed),
+    };
+
+    let mut report = CableReport {
+        pair_status: [PairState::NotTested; 4],
+        distance_cm: [None; 4],
+    };
+    report.pair_status[0] = state;
+    report.distance_cm[0] = distance_cm;
+    Ok(report)
+}
+
+// Set the RMII reference clock select bit of PHY Control 2, keeping the strapped value for `None`.
+fn ref_clk_bits(ctrl2: u16, clock_50mhz: Option<bool>) -> u16 {
+    match clock_50mhz {
+        Some(true) => ctrl2 | PHYCTRL2_RMII_REF_CLK_SEL,
+        Some(false) => ctrl2 & !PHYCTRL2_RMII_REF_CLK_SEL,
+        None => ctrl2,
+    }
+}
+
+// Set the crossover bits of PHY Control 2 for `mode`.
+fn mdix_bits(ctrl2: u16, mode: Mdix) -> u16 {
+    let ctrl2 = ctrl2 & !(PHYCTRL2_MDI_SELECT | PHYCTRL2_PAIR_SWAP_DISABLE);
+    match mode {
+        Mdix::Auto => ctrl2,
+        Mdix::Mdi => ctrl2 | PHYCTRL2_PAIR_SWAP_DISABLE | PHYCTRL2_MDI_SELECT,
+        Mdix::Mdix => ctrl2 | PHYCTRL2_PAIR_SWAP_DISABLE,
+    }
+}
+
+// Resolve the link from the operation mode indication in PHY Control 1.
+fn resolve_op_mode(ctrl1: u16) -> LinkStatus {
+    let (speed, duplex) = match ctrl1 & PHYCTRL1_OP_MODE_MASK {
+        PHYCTRL1_OP_MODE_10HD => (Speed::_10, DuplexMode::Half),
+        PHYCTRL1_OP_MODE_100HD => (Speed::_100, DuplexMode::Half),
+        PHYCTRL1_OP_MODE_10FD => (Speed::_10, DuplexMode::Full),
+        PHYCTRL1_OP_MODE_100FD => (Speed::_100, DuplexMode::Full),
+        // Still auto-negotiating
+        _ => return LinkStatus::Down,
+    };
+    LinkStatus::Up { speed, duplex }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::RecordingMdioBus;
+
+    #[test]
+    fn op_mode_decode() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Ksz8081::new(0);
+        sm.set(0, C22::BMSR, Bmsr(0).with_link_up(true).0);
+
+        let up = |speed, duplex| LinkStatus::Up { speed, duplex };
+        for (op_mode, status) in [
+            (0b000, LinkStatus::Down),
+            (0b001, up(Speed::_10, DuplexMode::Half)),
+            (0b010, up(Speed::_100, DuplexMode::Half)),
+            (0b101, up(Speed::_10, DuplexMode::Full)),
+            (0b110, up(Speed::_100, DuplexMode::Full)),
+        ] {
+            sm.set(0, PHYCTRL1, op_mode);
+            assert_eq!(phy.link_status(&mut sm), status);
+            assert_eq!(embassy_futures::block_on(phy.link_status_async(&mut sm)), status);
+        }
+
+        sm.set(0, C22::BMSR, 0);
+        assert_eq!(phy.link_status(&mut sm), LinkStatus::Down);
+    }
+
+    #[test]
+    fn counters() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Ksz8081::new(0);
+        sm.set(0, RXER_COUNTER, 7);
+
+        assert_eq!(
+            phy.read_counters(&mut sm),
+            PhyStats {
+                rx_errors: 7,
+                symbol_errors: 7,
+                false_carrier: 0
+            }
+        );
+    }
+
+    #[test]
+    fn mdix_mode() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Ksz8081::new(0);
+        sm.set(0, PHYCTRL2, 0x8000 | PHYCTRL2_RMII_REF_CLK_SEL);
+
+        phy.set_mdix(&mut sm, Mdix::Mdi);
+        assert_eq!(sm.get(0, PHYCTRL2), 0xe000 | PHYCTRL2_RMII_REF_CLK_SEL);
+        phy.set_mdix(&mut sm, Mdix::Mdix);
+        assert_eq!(sm.get(0, PHYCTRL2), 0xa000 | PHYCTRL2_RMII_REF_CLK_SEL);
+
+        // Re-initializing after a reset keeps the forced mode.
+        sm.set(0, PHYCTRL2, 0x8000);
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get(0, PHYCTRL2), 0xa000);
+
+        phy.set_mdix(&mut sm, Mdix::Auto);
+        assert_eq!(sm.get(0, PHYCTRL2), 0x8000);
+    }
+
+    #[test]
+    fn cable_test() {
+        let mut phy = Ksz8081::new(0);
+        let mut sm = RecordingMdioBus::new();
+        sm.set(0, C22::BMCR, PHY_REG_BCR_AN);
+        sm.set(0, PHYCTRL2, 0x8000);
+
+        // LinkMD completes on the first poll.
+        sm.script(0, LINKMD, [LINKMD_RESULT_OPEN << LINKMD_RESULT_SHIFT | 10]);
+        let report = phy.run_cable_test_with(&mut sm, |_| {}).unwrap();
+        assert_eq!(report.pair_status[0], PairState::Open);
+        assert_eq!(report.distance_cm[0], Some(380));
+        assert_eq!(report.pair_status[1..], [PairState::NotTested; 3]);
+
+        // Auto MDI/MDI-X and auto-negotiation are restored.
+        assert_eq!(sm.get(0, PHYCTRL2), 0x8000);
+        assert_eq!(sm.get(0, C22::BMCR), PHY_REG_BCR_AN | PHY_REG_BCR_ANRST);
+
+        sm.script(0, LINKMD, [LINKMD_RESULT_NORMAL << LINKMD_RESULT_SHIFT | 10]);
+        let report = phy.run_cable_test_with(&mut sm, |_| {}).unwrap();
+        assert_eq!(report.pair_status[0], PairState::Ok);
+        assert_eq!(report.pair_status[1..], [PairState::NotTested; 3]);
+        assert_eq!(report.distance_cm, [None; 4]);
+
+        sm.script(0, LINKMD, [0b11 << LINKMD_RESULT_SHIFT]);
+        assert_eq!(phy.run_cable_test_with(&mut sm, |_| {}), Err(CableTestError::Failed));
+
+        // The enable bit never clears.
+        let mut waited_us = 0;
+        assert_eq!(
+            phy.run_cable_test_with(&mut sm, |us| waited_us += us),
+            Err(CableTestError::Timeout)
+        );
+        assert_eq!(waited_us, LINKMD_TIMEOUT_US);
+    }
+
+    #[test]
+    fn init_keeps_strapped_rmii_clock() {
+        for strap in [0, PHYCTRL2_RMII_REF_CLK_SEL] {
+            let mut sm = RecordingMdioBus::new();
+            let mut phy = Ksz8081::new(0);
+            sm.set(0, PHYCTRL2, 0x8000 | strap);
+
+            phy.phy_init(&mut sm);
+            assert_eq!(sm.get(0, PHYCTRL2), 0x8000 | strap);
+        }
+    }
+
+    #[test]
+    fn init_selects_rmii_clock() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Ksz8081::new(0);
+
+        phy.set_rmii_clock_50mhz(true);
+        phy.phy_init(&mut sm);
+        assert_ne!(sm.get(0, PHYCTRL2) & PHYCTRL2_RMII_REF_CLK_SEL, 0);
+
+        phy.set_rmii_clock_50mhz(false);
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get(0, PHYCTRL2) & PHYCTRL2_RMII_REF_CLK_SEL, 0);
+    }
+
+    #[test]
+    fn rmii_clock_source() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Ksz8081::new(0);
+        sm.set(0, PHYCTRL2, 0x8000 | PHYCTRL2_RMII_REF_CLK_SEL);
+
+        phy.set_rmii_clock_source(&mut sm, RmiiClock::Internal);
+        assert_eq!(sm.get(0, PHYCTRL2), 0x8000);
+
+        // Re-initializing keeps the selected clock.
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get(0, PHYCTRL2) & PHYCTRL2_RMII_REF_CLK_SEL, 0);
+
+        phy.set_rmii_clock_source(&mut sm, RmiiClock::External);
+        assert_eq!(sm.get(0, PHYCTRL2), 0x8000 | PHYCTRL2_RMII_REF_CLK_SEL);
+    }
+}
@@ -0,0 +1,395 @@
+//! Microchip LAN8742A PHY
+
+use core::task::Context;
+
+use super::regs::{Bmsr, Mmd, C22, C45};
+use super::{
+    DuplexMode, GenericPhy, InterruptFlags, LedConfig, LedFunction, LedIndex, LinkStatus, Phy, PhyError, PhyInterrupts,
+    PhyIrq, PhyLinkStatus, Speed, StationManagement, WakeOnLan,
+};
+
+// Mode Control/Status register.
+const MCSR: C22 = C22::vendor_specific::<17>();
+const MCSR_EDPWRDOWN: u16 = 1 << 13;
+
+// Interrupt Source and Interrupt Mask registers, with the same bit layout.
+const ISR: C22 = C22::vendor_specific::<29>();
+const IMR: C22 = C22::vendor_specific::<30>();
+const INT_LINK_DOWN: u16 = 1 << 4;
+const INT_REMOTE_FAULT: u16 = 1 << 5;
+const INT_AN_COMPLETE: u16 = 1 << 6;
+
+// PHY Special Control/Status register.
+const PSCSR: C22 = C22::vendor_specific::<31>();
+const PSCSR_AUTODONE: u16 = 1 << 12;
+const PSCSR_SPEED_MASK: u16 = 0b111 << 2;
+const PSCSR_10HD: u16 = 0b001 << 2;
+const PSCSR_10FD: u16 = 0b101 << 2;
+const PSCSR_100HD: u16 = 0b010 << 2;
+const PSCSR_100FD: u16 = 0b110 << 2;
+
+// Wakeup Control and Status register, which also selects the LED functions.
+const WUCSR: C45 = C45::new(Mmd::PCS, 0x8010);
+const WUCSR_MPEN: u16 = 1 << 1;
+const WUCSR_MPR: u16 = 1 << 5;
+const WUCSR_WOL_CONFIGURED: u16 = 1 << 8;
+const WUCSR_LED1_SHIFT: u16 = 13;
+const WUCSR_LED2_SHIFT: u16 = 11;
+const WUCSR_LED_MASK: u16 = 0b1111 << WUCSR_LED2_SHIFT;
+// The LED function encodings differ per LED; both LEDs show their default function at 0b00.
+const LED1_SEL_LINK_ACTIVITY: u16 = 0b00;
+const LED1_SEL_SPEED: u16 = 0b11;
+const LED2_SEL_SPEED: u16 = 0b00;
+const LED2_SEL_LINK_ACTIVITY: u16 = 0b11;
+
+// MAC receive address registers for magic packet detection, 2 bytes each.
+const RX_ADDRA: C45 = C45::new(Mmd::PCS, 0x8061);
+const RX_ADDRB: C45 = C45::new(Mmd::PCS, 0x8062);
+const RX_ADDRC: C45 = C45::new(Mmd::PCS, 0x8063);
+
+/// Microchip LAN8742A PHY, as found on the Nucleo-144 boards (e.g. Nucleo-F746ZG).
+///
+/// Resets, initializes and polls the link like [`GenericPhy`], but resolves the negotiated speed and
+/// duplex mode from the vendor Special Control/Status register instead of comparing the advertised
+/// abilities.
+///
+/// The LEDs show their reset functions until [`LedConfig::set_led_mode`] selects others. The strap
+/// pins shared with the LED outputs still decide the LED polarity, and whether LED2 is replaced by
+/// the nINT interrupt output.
+pub struct Lan8742 {
+    inner: GenericPhy,
+    energy_detect_power_down: bool,
+    // LED function select bits of WUCSR, `None` while the LEDs keep their reset functions
+    led_sel: Option<u16>,
+}
+
+impl Lan8742 {
+    /// Construct the PHY at address `phy_addr`.
+    ///
+    /// # Panics
+    /// `phy_addr` must be in range `0..32`
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            inner: GenericPhy::new(phy_addr),
+            energy_detect_power_down: false,
+            led_sel: None,
+        }
+    }
+
+    /// Construct the PHY, probing all addresses from 0 to 31 during initialization.
+    ///
+    /// # Panics
+    /// Initialization panics if the PHY didn't respond on any address
+    pub fn new_auto() -> Self {
+        Self {
+            inner: GenericPhy::new_auto(),
+            energy_detect_power_down: false,
+            led_sel: None,
+        }
+    }
+
+    /// Enable the energy detect power-down mode, which powers down the receiver while no cable is attached.
+    ///
+    /// Takes effect on the next [`Phy::phy_init`]. Disabled by default, because link detection is
+    /// slower in this mode.
+    pub fn set_energy_detect_power_down(&mut self, enable: bool) {
+        self.energy_detect_power_down = enable;
+    }
+
+    /// Read the current link status, including the negotiated speed and duplex mode.
+    pub fn link_status<S: StationManagement>(&mut self, sm: &mut S) -> LinkStatus {
+        let phy_addr = self.inner.smi_addr();
+        if !Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR)).link_up() {
+            return LinkStatus::Down;
+        }
+        resolve_pscsr(sm.smi_read(phy_addr, PSCSR.0))
+    }
+
+    /// Poll the link only after the PHY signalled an interrupt, like
+    /// [`GenericPhy::poll_link_on_irq`], reading the interrupt source to release the interrupt line
+    /// for the next event.
+    ///
+    /// The link interrupts must be enabled with [`PhyInterrupts::enable_interrupts`].
+    pub fn poll_link_on_irq<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context, irq: &PhyIrq) -> bool {
+        let phy_addr = self.inner.smi_addr();
+        self.inner.poll_link_on_irq_with(sm, cx, irq, |sm| {
+            sm.smi_read(phy_addr, ISR.0);
+        })
+    }
+
+    /// Make [`Phy::poll_link`] use [`poll_link_on_irq`](Self::poll_link_on_irq) with `irq`, or go back
+    /// to polling with `None`.
+    pub fn set_link_irq(&mut self, irq: Option<&'static PhyIrq>) {
+        self.inner.set_link_irq(irq);
+    }
+
+    fn write_led_sel<S: StationManagement>(&mut self, sm: &mut S) {
+        let Some(led_sel) = self.led_sel else {
+            return;
+        };
+        let phy_addr = self.inner.smi_addr();
+        let wucsr = sm.smi_read_mmd(phy_addr, WUCSR);
+        sm.smi_write_mmd(phy_addr, WUCSR, wucsr & !WUCSR_LED_MASK | led_sel);
+    }
+}
+
+impl Phy for Lan8742 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_reset(sm);
+    }
+
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.inner.try_phy_reset(sm)
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        let phy_addr = self.inner.smi_addr();
+        let enable = self.energy_detect_power_down;
+        sm.smi_modify(phy_addr, MCSR, |mcsr| match enable {
+            true => mcsr | MCSR_EDPWRDOWN,
+            false => mcsr & !MCSR_EDPWRDOWN,
+        });
+
+        // Clear the wake-up configuration and status, keeping the LED functions.
+        let wucsr = sm.smi_read_mmd(phy_addr, WUCSR);
+        let led_sel = self.led_sel.unwrap_or(wucsr & WUCSR_LED_MASK);
+        self.inner.init(sm, led_sel);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        match self.inner.link_irq() {
+            Some(irq) => self.poll_link_on_irq(sm, cx, irq),
+            None => self.inner.poll_link(sm, cx),
+        }
+    }
+}
+
+/// Magic packet detection. The LED function select bits sharing WUCSR are kept.
+impl PhyLinkStatus for Lan8742 {
+    async fn link_status_async<S: super::StationManagementAsync>(&mut self, sm: &mut S) -> LinkStatus {
+        let phy_addr = self.inner.smi_addr();
+        if !Bmsr(sm.smi_read(phy_addr, Bmsr::ADDR).await).link_up() {
+            return LinkStatus::Down;
+        }
+        resolve_pscsr(sm.smi_read(phy_addr, PSCSR.0).await)
+    }
+}
+
+impl WakeOnLan for Lan8742 {
+    fn configure_wol<S: StationManagement>(&mut self, sm: &mut S, mac: [u8; 6]) {
+        let phy_addr = self.inner.smi_addr();
+        sm.smi_write_mmd(phy_addr, RX_ADDRA, u16::from_le_bytes([mac[4], mac[5]]));
+        sm.smi_write_mmd(phy_addr, RX_ADDRB, u16::from_le_bytes([mac[2], mac[3]]));
+        sm.smi_write_mmd(phy_addr, RX_ADDRC, u16::from_le_bytes([mac[0], mac[1]]));
+        let wucsr = sm.smi_read_mmd(phy_addr, WUCSR);
+        sm.smi_write_mmd(
+            phy_addr,
+            WUCSR,
+            wucsr & WUCSR_LED_MASK | WUCSR_WOL_CONFIGURED | WUCSR_MPEN,
+        );
+    }
+
+    fn wol_triggered<S: StationManagement>(&mut self, sm: &mut S) -> bool {
+        let phy_addr = self.inner.smi_addr();
+        let wucsr = sm.smi_read_mmd(phy_addr, WUCSR);
+        if wucsr & WUCSR_MPR == 0 {
+            return false;
+        }
+        // The status bits are write-one-to-clear
+        sm.smi_write_mmd(phy_addr, WUCSR, wucsr);
+        true
+    }
+}
+
+/// The PHY has no dedicated link up source; a link coming up is reported through the
+/// auto-negotiation complete source, so [`InterruptFlags::LINK_UP`] and
+/// [`InterruptFlags::AN_COMPLETE`] share it.
+impl PhyInterrupts for Lan8742 {
+    fn interrupt_source<S: StationManagement>(&mut self, sm: &mut S) -> InterruptFlags {
+        let isr = sm.smi_read(self.inner.smi_addr(), ISR.0);
+        let mut flags = InterruptFlags::empty();
+        if isr & INT_AN_COMPLETE != 0 {
+            flags |= InterruptFlags::LINK_UP | InterruptFlags::AN_COMPLETE;
+        }
+        if isr & INT_LINK_DOWN != 0 {
+            flags |= InterruptFlags::LINK_DOWN;
+        }
+        if isr & INT_REMOTE_FAULT != 0 {
+            flags |= InterruptFlags::REMOTE_FAULT;
+        }
+        flags
+    }
+
+    fn enable_interrupts<S: StationManagement>(&mut self, sm: &mut S, mask: InterruptFlags) {
+        let mut imr = 0;
+        if mask.intersects(InterruptFlags::LINK_UP | InterruptFlags::AN_COMPLETE) {
+            imr |= INT_AN_COMPLETE;
+        }
+        if mask.contains(InterruptFlags::LINK_DOWN) {
+            imr |= INT_LINK_DOWN;
+        }
+        if mask.contains(InterruptFlags::REMOTE_FAULT) {
+            imr |= INT_REMOTE_FAULT;
+        }
+        sm.smi_write(self.inner.smi_addr(), IMR.0, imr);
+    }
+}
+
+/// LED1 can show [`LedFunction::LinkActivity`] (the default) or [`LedFunction::Speed`], and so
+/// can LED2, which defaults to [`LedFunction::Speed`]. The selection takes effect immediately and is
+/// kept by later calls of [`Phy::phy_init`]. Until it is first called, the LED function select
+/// bits are left alone.
+impl LedConfig for Lan8742 {
+    fn set_led_mode<S: StationManagement>(
+        &mut self,
+        sm: &mut S,
+        led: LedIndex,
+        function: LedFunction,
+    ) -> Result<(), PhyError> {
+        let (shift, sel) = match (led, function) {
+            (LedIndex(1), LedFunction::LinkActivity) => (WUCSR_LED1_SHIFT, LED1_SEL_LINK_ACTIVITY),
+            (LedIndex(1), LedFunction::Speed) => (WUCSR_LED1_SHIFT, LED1_SEL_SPEED),
+            (LedIndex(2), LedFunction::Speed) => (WUCSR_LED2_SHIFT, LED2_SEL_SPEED),
+            (LedIndex(2), LedFunction::LinkActivity) => (WUCSR_LED2_SHIFT, LED2_SEL_LINK_ACTIVITY),
+            _ => return Err(PhyError::Unsupported),
+        };
+        // WUCSR resets to 0, selecting the default function of both LEDs.
+        let led_sel = self.led_sel.unwrap_or(0);
+        self.led_sel = Some(led_sel & !(0b11 << shift) | sel << shift);
+        self.write_led_sel(sm);
+        Ok(())
+    }
+}
+
+// Resolve the link from the Special Control/Status register of a PHY whose link is up.
+fn resolve_pscsr(pscsr: u16) -> LinkStatus {
+    if pscsr & PSCSR_AUTODONE == 0 {
+        return LinkStatus::Down;
+    }
+    let (speed, duplex) = match pscsr & PSCSR_SPEED_MASK {
+        PSCSR_10HD => (Speed::_10, DuplexMode::Half),
+        PSCSR_10FD => (Speed::_10, DuplexMode::Full),
+        PSCSR_100HD => (Speed::_100, DuplexMode::Half),
+        PSCSR_100FD => (Speed::_100, DuplexMode::Full),
+        _ => return LinkStatus::Down,
+    };
+    LinkStatus::Up { speed, duplex }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::{Action, RecordingMdioBus};
+
+    #[test]
+    fn wol_keeps_led_functions() {
+        const LED1_SPEED: u16 = 0b11 << 13;
+
+        let mut sm = RecordingMdioBus::new();
+        sm.set_mmd(0, WUCSR, LED1_SPEED | WUCSR_MPEN | WUCSR_MPR);
+        let mut phy = Lan8742::new(0);
+
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get_mmd(0, WUCSR), LED1_SPEED);
+
+        phy.configure_wol(&mut sm, [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        assert_eq!(sm.get_mmd(0, RX_ADDRA), 0xbc9a);
+        assert_eq!(sm.get_mmd(0, RX_ADDRB), 0x7856);
+        assert_eq!(sm.get_mmd(0, RX_ADDRC), 0x3412);
+        assert_eq!(sm.get_mmd(0, WUCSR), LED1_SPEED | WUCSR_WOL_CONFIGURED | WUCSR_MPEN);
+
+        assert!(!phy.wol_triggered(&mut sm));
+        sm.set_mmd(0, WUCSR, sm.get_mmd(0, WUCSR) | WUCSR_MPR);
+        assert!(phy.wol_triggered(&mut sm));
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut sm = RecordingMdioBus::new();
+        let mut phy = Lan8742::new(1);
+
+        phy.enable_interrupts(&mut sm, InterruptFlags::LINK_DOWN | InterruptFlags::AN_COMPLETE);
+        assert_eq!(sm.get(1, IMR), INT_LINK_DOWN | INT_AN_COMPLETE);
+
+        sm.set(1, ISR, INT_LINK_DOWN | INT_REMOTE_FAULT);
+        sm.actions.clear();
+        assert_eq!(
+            phy.interrupt_source(&mut sm),
+            InterruptFlags::LINK_DOWN | InterruptFlags::REMOTE_FAULT
+        );
+        assert_eq!(sm.actions, [Action::Read(1, ISR)]);
+    }
+
+    #[test]
+    fn poll_link_on_irq_acknowledges() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(
+            0,
+            C22::BMSR,
+            Bmsr::default().with_link_up(true).with_autoneg_complete(true).0,
+        );
+        let mut phy = Lan8742::new(0);
+        let irq = PhyIrq::new();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        assert!(phy.poll_link_on_irq(&mut sm, &mut cx, &irq));
+        assert_eq!(sm.actions, [Action::Read(0, ISR), Action::Read(0, C22::BMSR)]);
+
+        sm.actions.clear();
+        assert!(phy.poll_link_on_irq(&mut sm, &mut cx, &irq));
+        assert!(sm.actions.is_empty());
+    }
+
+    #[test]
+    fn led_mode() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set_mmd(0, WUCSR, 1 << 8);
+        let mut phy = Lan8742::new(0);
+
+        // The LED functions are only written once selected.
+        phy.write_led_sel(&mut sm);
+        assert!(sm.actions.is_empty());
+
+        phy.set_led_mode(&mut sm, LedIndex(1), LedFunction::Speed).unwrap();
+        assert_eq!(sm.get_mmd(0, WUCSR), 1 << 8 | 0b11 << 13);
+        phy.set_led_mode(&mut sm, LedIndex(2), LedFunction::LinkActivity)
+            .unwrap();
+        assert_eq!(sm.get_mmd(0, WUCSR), 1 << 8 | 0b11 << 13 | 0b11 << 11);
+        phy.set_led_mode(&mut sm, LedIndex(2), LedFunction::Speed).unwrap();
+        assert_eq!(sm.get_mmd(0, WUCSR), 1 << 8 | 0b11 << 13);
+
+        assert_eq!(
+            phy.set_led_mode(&mut sm, LedIndex(1), LedFunction::Off),
+            Err(PhyError::Unsupported)
+        );
+        assert_eq!(
+            phy.set_led_mode(&mut sm, LedIndex(3), LedFunction::Speed),
+            Err(PhyError::Unsupported)
+        );
+        assert_eq!(sm.get_mmd(0, WUCSR), 1 << 8 | 0b11 << 13);
+    }
+
+    #[test]
+    fn pscsr_decode() {
+        let up = |speed, duplex| LinkStatus::Up { speed, duplex };
+
+        assert_eq!(resolve_pscsr(PSCSR_100FD), LinkStatus::Down);
+        assert_eq!(
+            resolve_pscsr(PSCSR_AUTODONE | PSCSR_10HD),
+            up(Speed::_10, DuplexMode::Half)
+        );
+        assert_eq!(
+            resolve_pscsr(PSCSR_AUTODONE | PSCSR_10FD),
+            up(Speed::_10, DuplexMode::Full)
+        );
+        assert_eq!(
+            resolve_pscsr(PSCSR_AUTODONE | PSCSR_100HD),
+            up(Speed::_100, DuplexMode::Half)
+        );
+        assert_eq!(
+            resolve_pscsr(PSCSR_AUTODONE | PSCSR_100FD),
+            up(Speed::_100, DuplexMode::Full)
+        );
+        assert_eq!(resolve_pscsr(PSCSR_AUTODONE), LinkStatus::Down);
+    }
+}
@@ -0,0 +1,315 @@
+//! Mock SMI bus for unit testing PHY drivers without hardware.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use super::regs::{Mmd, C22, C45, DEV_MASK};
+use super::{StationManagement, StationManagementC45};
+
+/// SMI access recorded by [`RecordingMdioBus`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Register read at a PHY address.
+    Read(u8, C22),
+    /// Register write of a value at a PHY address.
+    Write(u8, C22, u16),
+    /// Clause 45 frame read at a PHY address.
+    ReadMmd(u8, C45),
+    /// Clause 45 frame write of a value at a PHY address.
+    WriteMmd(u8, C45, u16),
+}
+
+/// SMI bus that answers reads from a programmable register map and records every access.
+///
+/// Writes update the register map, so a later read of the same register returns the written value.
+/// Registers that were never set read as 0. The reset bit of `BMCR` clears itself, like on a PHY that
+/// finishes a soft reset immediately, unless [`stuck_in_reset`](Self::stuck_in_reset) is set.
+///
+/// By default there is a PHY at every address. On a bus created with
+/// [`with_phys`](Self::with_phys), other addresses behave like an address without a PHY: reads
+/// return `0xffff` and writes are dropped. Accesses to them are still recorded.
+///
+/// Clause 45 registers are kept in a second map, accessed like on a real PHY: indirectly through
+/// the `MMD_CONTROL` and `MMD_DATA` registers, including post increment, or with clause 45 frames
+/// through [`StationManagementC45`].
+///
+/// Every [`StationManagement`] is also a [`StationManagementAsync`](super::StationManagementAsync),
+/// so this bus can test async PHY drivers too. Use [`RecordingMdioBusAsync`] to make them wait for every access.
+#[derive(Debug)]
+pub struct RecordingMdioBus {
+    /// Register values returned by reads, keyed by PHY address and register.
+    pub regs: BTreeMap<(u8, C22), u16>,
+    /// Clause 45 register values, keyed by PHY address and register.
+    pub mmd: BTreeMap<(u8, C45), u16>,
+    /// Accesses in the order they were made.
+    pub actions: Vec<Action>,
+    /// Keep the reset bit of `BMCR` set after a soft reset, like a PHY that never comes out of reset.
+    pub stuck_in_reset: bool,
+    // Bit `n` is set if there is a PHY at address `n`.
+    phys: u32,
+    // Values returned by the next reads of a register, before its value in `regs`.
+    scripts: BTreeMap<(u8, C22), VecDeque<u16>>,
+    // MMD address register of each PHY address.
+    mmd_addr: BTreeMap<u8, u16>,
+}
+
+impl Default for RecordingMdioBus {
+    fn default() -> Self {
+        Self::with_phys(0..32)
+    }
+}
+
+impl RecordingMdioBus {
+    /// Create an empty bus with a PHY at every address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty bus with PHYs only at `phy_addrs`.
+    pub fn with_phys(phy_addrs: impl IntoIterator<Item = u8>) -> Self {
+        Self {
+            regs: BTreeMap::new(),
+            mmd: BTreeMap::new(),
+            actions: Vec::new(),
+            stuck_in_reset: false,
+            phys: phy_addrs.into_iter().fold(0, |phys, addr| phys | 1 << addr),
+            scripts: BTreeMap::new(),
+            mmd_addr: BTreeMap::new(),
+        }
+    }
+
+    /// Set the value returned by reads of `reg` at `phy_addr`.
+    pub fn set(&mut self, phy_addr: u8, reg: C22, val: u16) {
+        self.regs.insert((phy_addr, reg), val);
+    }
+
+    /// Value of `reg` at `phy_addr`, without recording an access.
+    pub fn get(&self, phy_addr: u8, reg: C22) -> u16 {
+        self.regs.get(&(phy_addr, reg)).copied().unwrap_or(0)
+    }
+
+    /// Return `vals` from the next reads of `reg` at `phy_addr`, one per read, before returning the
+    /// value of the register again.
+    ///
+    /// Useful for registers that change between reads, such as the link bit of `BMSR` on a flapping
+    /// link.
+    pub fn script(&mut self, phy_addr: u8, reg: C22, vals: impl IntoIterator<Item = u16>) {
+        self.scripts.entry((phy_addr, reg)).or_default().extend(vals);
+    }
+
+    /// Set the value returned by reads of the clause 45 register `reg` at `phy_addr`.
+    pub fn set_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.mmd.insert((phy_addr, reg), val);
+    }
+
+    /// Value of the clause 45 register `reg` at `phy_addr`, without recording an access.
+    pub fn get_mmd(&self, phy_addr: u8, reg: C45) -> u16 {
+        self.mmd.get(&(phy_addr, reg)).copied().unwrap_or(0)
+    }
+
+    // Whether there is a PHY at `phy_addr` that answers accesses.
+    fn present(&self, phy_addr: u8) -> bool {
+        phy_addr < 32 && self.phys & 1 << phy_addr != 0
+    }
+
+    // Access `MMD_DATA` at `phy_addr`, returning the register it maps to, if any.
+    fn mmd_data(&mut self, phy_addr: u8, write: bool) -> Option<C45> {
+        let control = self.get(phy_addr, C22::MMD_CONTROL);
+        let addr = self.mmd_addr.entry(phy_addr).or_insert(0);
+        let reg = C45::new(Mmd((control & DEV_MASK) as u8), *addr);
+        // Function field, see `Reg13Op`
+        let increment = match control >> 14 {
+            0b00 => return None,
+            0b01 => false,
+            0b10 => true,
+            _ => write,
+        };
+        if increment {
+            *addr = addr.wrapping_add(1);
+        }
+        Some(reg)
+    }
+}
+
+impl StationManagement for RecordingMdioBus {
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let reg = C22(reg);
+        self.actions.push(Action::Read(phy_addr, reg));
+        if !self.present(phy_addr) {
+            return 0xffff;
+        }
+        let val = match reg {
+            C22::MMD_DATA => match self.mmd_data(phy_addr, false) {
+                Some(mmd) => self.get_mmd(phy_addr, mmd),
+                None => self.mmd_addr[&phy_addr],
+            },
+            _ => self.get(phy_addr, reg),
+        };
+        // A scripted value replaces the result, but not the side effects of the read
+        let scripted = self.scripts.get_mut(&(phy_addr, reg)).and_then(VecDeque::pop_front);
+        scripted.unwrap_or(val)
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        let reg = C22(reg);
+        self.actions.push(Action::Write(phy_addr, reg, val));
+        if !self.present(phy_addr) {
+            return;
+        }
+        match reg {
+            C22::MMD_DATA => match self.mmd_data(phy_addr, true) {
+                Some(mmd) => self.set_mmd(phy_addr, mmd, val),
+                None => {
+                    self.mmd_addr.insert(phy_addr, val);
+                }
+            },
+            C22::BMCR if !self.stuck_in_reset => self.set(phy_addr, reg, val & !(1 << 15)),
+            _ => self.set(phy_addr, reg, val),
+        }
+    }
+}
+
+impl StationManagementC45 for RecordingMdioBus {
+    fn c45_read(&mut self, phy_addr: u8, devad: Mmd, regnum: u16) -> u16 {
+        let reg = C45::new(devad, regnum);
+        self.actions.push(Action::ReadMmd(phy_addr, reg));
+        if !self.present(phy_addr) {
+            return 0xffff;
+        }
+        self.get_mmd(phy_addr, reg)
+    }
+
+    fn c45_write(&mut self, phy_addr: u8, devad: Mmd, regnum: u16, val: u16) {
+        let reg = C45::new(devad, regnum);
+        self.actions.push(Action::WriteMmd(phy_addr, reg, val));
+        if self.present(phy_addr) {
+            self.set_mmd(phy_addr, reg, val);
+        }
+    }
+}
+
+/// [`StationManagementAsync`](super::StationManagementAsync) bus that yields before every access to the wrapped [`RecordingMdioBus`].
+///
+/// Unlike the bus it wraps, this is not a [`StationManagement`], so it behaves like an async-only
+/// bus such as [`SpiMdio`](super::SpiMdio): every access suspends the calling future once before
+/// it completes. Clause 45 registers are accessed indirectly through the clause 22 registers.
+#[derive(Debug, Default)]
+pub struct RecordingMdioBusAsync(pub RecordingMdioBus);
+
+impl super::StationManagementAsync for RecordingMdioBusAsync {
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        embassy_futures::yield_now().await;
+        StationManagement::smi_read(&mut self.0, phy_addr, reg)
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        embassy_futures::yield_now().await;
+        StationManagement::smi_write(&mut self.0, phy_addr, reg, val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+
+    #[test]
+    fn records_accesses() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(1, C22::BMSR, 0x7809);
+
+        assert_eq!(sm.smi_read(1, C22::BMSR.0), 0x7809);
+        assert_eq!(sm.smi_read(2, C22::BMSR.0), 0);
+        sm.smi_write(1, C22::BMCR.0, 0x1200);
+        assert_eq!(sm.smi_read(1, C22::BMCR.0), 0x1200);
+
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Read(1, C22::BMSR),
+                Action::Read(2, C22::BMSR),
+                Action::Write(1, C22::BMCR, 0x1200),
+                Action::Read(1, C22::BMCR),
+            ]
+        );
+    }
+
+    #[test]
+    fn absent_phys() {
+        let mut sm = RecordingMdioBus::with_phys([3]);
+        sm.smi_write(2, C22::BMCR.0, 0x1200);
+        sm.smi_write(3, C22::BMCR.0, 0x1200);
+
+        assert_eq!(sm.smi_read(2, C22::BMCR.0), 0xffff);
+        assert_eq!(sm.smi_read(3, C22::BMCR.0), 0x1200);
+        assert_eq!(sm.actions.len(), 4);
+    }
+
+    #[test]
+    fn scripted_reads() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(0, C22::BMSR, 0x0004);
+        sm.script(0, C22::BMSR, [0x0000, 0x0024]);
+
+        assert_eq!(sm.smi_read(0, C22::BMSR.0), 0x0000);
+        assert_eq!(sm.smi_read(0, C22::BMSR.0), 0x0024);
+        assert_eq!(sm.smi_read(0, C22::BMSR.0), 0x0004);
+    }
+
+    #[test]
+    fn indirect_mmd_access() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set_mmd(1, C45::new(Mmd::PCS, 0x8061), 0x1234);
+        sm.set_mmd(1, C45::new(Mmd::PCS, 0x8062), 0x5678);
+
+        assert_eq!(sm.smi_read_mmd(1, C45::new(Mmd::PCS, 0x8062)), 0x5678);
+        let mut out = [0xffff; 3];
+        sm.smi_read_mmd_burst(1, C45::new(Mmd::PCS, 0x8061), &mut out);
+        assert_eq!(out, [0x1234, 0x5678, 0]);
+
+        sm.smi_write_mmd(1, C45::AN_EEE_ADV, 0x0006);
+        assert_eq!(sm.get_mmd(1, C45::AN_EEE_ADV), 0x0006);
+        // The MMD registers of other PHYs are separate.
+        assert_eq!(sm.get_mmd(2, C45::AN_EEE_ADV), 0);
+
+        sm.actions.clear();
+        assert_eq!(sm.c45_read(1, Mmd::AN, 60), 0x0006);
+        sm.c45_write(1, Mmd::AN, 60, 0x0002);
+        assert_eq!(sm.get_mmd(1, C45::AN_EEE_ADV), 0x0002);
+        assert_eq!(
+            sm.actions,
+            [
+                Action::ReadMmd(1, C45::AN_EEE_ADV),
+                Action::WriteMmd(1, C45::AN_EEE_ADV, 0x0002),
+            ]
+        );
+    }
+
+    #[test]
+    fn async_bus_yields() {
+        let mut sm = RecordingMdioBusAsync::default();
+        sm.0.set(1, C22::BMSR, 0x7809);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        {
+            let mut read = pin!(crate::eth::StationManagementAsync::smi_read(&mut sm, 1, C22::BMSR.0));
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(read.as_mut().poll(&mut cx), Poll::Ready(0x7809));
+        }
+        assert_eq!(sm.0.actions, [Action::Read(1, C22::BMSR)]);
+    }
+
+    #[test]
+    fn reset_clears_itself() {
+        let mut sm = RecordingMdioBus::new();
+        sm.smi_write(0, C22::BMCR.0, 0x9140);
+        assert_eq!(sm.smi_read(0, C22::BMCR.0), 0x1140);
+
+        sm.stuck_in_reset = true;
+        sm.smi_write(0, C22::BMCR.0, 0x9140);
+        assert_eq!(sm.smi_read(0, C22::BMCR.0), 0x9140);
+    }
+}
@@ -4,7 +4,21 @@
 #[cfg_attr(any(eth_v1a, eth_v1b, eth_v1c), path = "v1/mod.rs")]
 #[cfg_attr(eth_v2, path = "v2/mod.rs")]
 mod _version;
+mod ar8035;
+mod capabilities;
+mod debounced_phy;
 mod generic_phy;
+mod ksz8081;
+mod lan8742;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod phy_group;
+pub mod regs;
+mod retry_smi;
+mod rtl8211f;
+mod shared_smi;
+mod spi_mdio;
+mod tracing_smi;
 
 use core::mem::MaybeUninit;
 use core::task::Context;
@@ -14,7 +28,19 @@ use embassy_net_driver::{Capabilities, HardwareAddress, LinkState};
 use embassy_sync::waitqueue::AtomicWaker;
 
 pub use self::_version::{InterruptHandler, *};
+pub use self::ar8035::*;
+pub use self::capabilities::*;
+pub use self::debounced_phy::*;
 pub use self::generic_phy::*;
+pub use self::ksz8081::*;
+pub use self::lan8742::*;
+pub use self::phy_group::*;
+use self::regs::{Mmd, Reg13Op, C22, C45};
+pub use self::retry_smi::*;
+pub use self::rtl8211f::*;
+pub use self::shared_smi::*;
+pub use self::spi_mdio::*;
+pub use self::tracing_smi::*;
 use crate::rcc::RccPeripheral;
 
 #[allow(unused)]
@@ -157,24 +183,605 @@ impl<'a, 'd> embassy_net_driver::TxToken for TxToken<'a, 'd> {
     }
 }
 
+/// SMI bus configuration, see [`StationManagement::set_bus_config`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmiBusConfig {
+    /// Maximum MDC clock frequency in Hz.
+    pub mdc_hz: u32,
+    /// Omit the preamble before each management frame, for PHYs that accept it.
+    pub suppress_preamble: bool,
+}
+
 /// Station Management Interface (SMI) on an ethernet PHY
 pub trait StationManagement {
+    /// Whether [`smi_read_mmd`](Self::smi_read_mmd) and [`smi_write_mmd`](Self::smi_write_mmd) issue
+    /// clause 45 frames directly instead of the indirect access through clause 22 registers.
+    ///
+    /// Implementations that override the MMD accessors with direct access set this to `true`.
+    const SUPPORTS_C45_DIRECT: bool = false;
+
     /// Read a register over SMI.
     fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16;
     /// Write a register over SMI.
     fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16);
+
+    /// Configure the MDC clock and preamble, e.g. to slow the bus down for long MDIO traces.
+    ///
+    /// Implementations use the fastest supported MDC clock not above `config.mdc_hz` and ignore
+    /// settings the hardware doesn't support. The default implementation does nothing.
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        let _ = config;
+    }
+
+    /// Read several registers over SMI, storing the value of `regs[i]` in `out[i]`.
+    ///
+    /// The default implementation reads the registers one by one. Implementations that can
+    /// pipeline reads may override it.
+    ///
+    /// # Panics
+    /// `regs` and `out` must have the same length.
+    fn smi_read_many(&mut self, phy_addr: u8, regs: &[C22], out: &mut [u16]) {
+        assert_eq!(regs.len(), out.len());
+        for (reg, out) in regs.iter().zip(out) {
+            *out = self.smi_read(phy_addr, reg.0);
+        }
+    }
+
+    /// Read a register, apply `f` to its value and write the result back.
+    ///
+    /// Returns the value written.
+    fn smi_modify(&mut self, phy_addr: u8, reg: C22, f: impl FnOnce(u16) -> u16) -> u16 {
+        let val = f(self.smi_read(phy_addr, reg.0));
+        self.smi_write(phy_addr, reg.0, val);
+        val
+    }
+
+    /// Read a clause 45 register.
+    ///
+    /// The default implementation uses the indirect access through the clause 22 MMD access
+    /// registers. Override it, and set `SUPPORTS_C45_DIRECT`, if the hardware can issue clause 45
+    /// frames directly.
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(reg.devad));
+        self.smi_write(phy_addr, C22::MMD_DATA.0, reg.regnum);
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Data.control(reg.devad));
+        self.smi_read(phy_addr, C22::MMD_DATA.0)
+    }
+
+    /// Read `out.len()` consecutive clause 45 registers starting at `start`.
+    ///
+    /// The default implementation sets up the address once and then reads the MMD data register
+    /// with post-increment, which is faster than reading each register with [`smi_read_mmd`](Self::smi_read_mmd).
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(start.devad));
+        self.smi_write(phy_addr, C22::MMD_DATA.0, start.regnum);
+        self.smi_write(
+            phy_addr,
+            C22::MMD_CONTROL.0,
+            Reg13Op::PostReadIncAddr.control(start.devad),
+        );
+        for out in out {
+            *out = self.smi_read(phy_addr, C22::MMD_DATA.0);
+        }
+    }
+
+    /// Write a clause 45 register.
+    ///
+    /// The default implementation uses the indirect access through the clause 22 MMD access
+    /// registers. Override it, and set `SUPPORTS_C45_DIRECT`, if the hardware can issue clause 45
+    /// frames directly.
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(reg.devad));
+        self.smi_write(phy_addr, C22::MMD_DATA.0, reg.regnum);
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Data.control(reg.devad));
+        self.smi_write(phy_addr, C22::MMD_DATA.0, val);
+    }
+
+    /// Read an extended register of a clause 22 PHY.
+    ///
+    /// Some clause 22 PHYs expose vendor-specific extended registers through the MMD access
+    /// registers as device [`Mmd::C22_EXT`] (29), which is not a clause 45 MMD like the PCS or
+    /// PMA/PMD. This is a shorthand for [`smi_read_mmd`](Self::smi_read_mmd) on that device.
+    fn smi_read_ext(&mut self, phy_addr: u8, regnum: u16) -> u16 {
+        self.smi_read_mmd(phy_addr, C45::new(Mmd::C22_EXT, regnum))
+    }
+
+    /// Write an extended register of a clause 22 PHY, see [`smi_read_ext`](Self::smi_read_ext).
+    fn smi_write_ext(&mut self, phy_addr: u8, regnum: u16, val: u16) {
+        self.smi_write_mmd(phy_addr, C45::new(Mmd::C22_EXT, regnum), val)
+    }
+}
+
+/// Asynchronous Station Management Interface (SMI) on an ethernet PHY
+///
+/// This is implemented for every [`StationManagement`], so PHY drivers written against it also work
+/// with the blocking SMI of the Ethernet peripheral. Import only one of the two traits where both
+/// apply, since their method names are identical.
+pub trait StationManagementAsync {
+    /// Whether [`smi_read_mmd`](Self::smi_read_mmd) and [`smi_write_mmd`](Self::smi_write_mmd) issue
+    /// clause 45 frames directly instead of the indirect access through clause 22 registers.
+    ///
+    /// Implementations that override the MMD accessors with direct access set this to `true`.
+    const SUPPORTS_C45_DIRECT: bool = false;
+
+    /// Read a register over SMI.
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16;
+    /// Write a register over SMI.
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16);
+
+    /// Configure the MDC clock and preamble, e.g. to slow the bus down for long MDIO traces.
+    ///
+    /// Implementations use the fastest supported MDC clock not above `config.mdc_hz` and ignore
+    /// settings the hardware doesn't support. The default implementation does nothing.
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        let _ = config;
+    }
+
+    /// Read several registers over SMI, storing the value of `regs[i]` in `out[i]`.
+    ///
+    /// The default implementation reads the registers one by one. Implementations that can
+    /// pipeline reads may override it.
+    ///
+    /// # Panics
+    /// `regs` and `out` must have the same length.
+    async fn smi_read_many(&mut self, phy_addr: u8, regs: &[C22], out: &mut [u16]) {
+        assert_eq!(regs.len(), out.len());
+        for (reg, out) in regs.iter().zip(out) {
+            *out = self.smi_read(phy_addr, reg.0).await;
+        }
+    }
+
+    /// Read a register, apply `f` to its value and write the result back.
+    ///
+    /// Returns the value written.
+    async fn smi_modify(&mut self, phy_addr: u8, reg: C22, f: impl FnOnce(u16) -> u16) -> u16 {
+        let val = f(self.smi_read(phy_addr, reg.0).await);
+        self.smi_write(phy_addr, reg.0, val).await;
+        val
+    }
+
+    /// Read a clause 45 register.
+    ///
+    /// The default implementation uses the indirect access through the clause 22 MMD access
+    /// registers. Override it, and set `SUPPORTS_C45_DIRECT`, if the hardware can issue clause 45
+    /// frames directly.
+    async fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(reg.devad))
+            .await;
+        self.smi_write(phy_addr, C22::MMD_DATA.0, reg.regnum).await;
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Data.control(reg.devad))
+            .await;
+        self.smi_read(phy_addr, C22::MMD_DATA.0).await
+    }
+
+    /// Read `out.len()` consecutive clause 45 registers starting at `start`.
+    ///
+    /// The default implementation sets up the address once and then reads the MMD data register
+    /// with post-increment, which is faster than reading each register with [`smi_read_mmd`](Self::smi_read_mmd).
+    async fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(start.devad))
+            .await;
+        self.smi_write(phy_addr, C22::MMD_DATA.0, start.regnum).await;
+        self.smi_write(
+            phy_addr,
+            C22::MMD_CONTROL.0,
+            Reg13Op::PostReadIncAddr.control(start.devad),
+        )
+        .await;
+        for out in out {
+            *out = self.smi_read(phy_addr, C22::MMD_DATA.0).await;
+        }
+    }
+
+    /// Write a clause 45 register.
+    ///
+    /// The default implementation uses the indirect access through the clause 22 MMD access
+    /// registers. Override it, and set `SUPPORTS_C45_DIRECT`, if the hardware can issue clause 45
+    /// frames directly.
+    async fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Address.control(reg.devad))
+            .await;
+        self.smi_write(phy_addr, C22::MMD_DATA.0, reg.regnum).await;
+        self.smi_write(phy_addr, C22::MMD_CONTROL.0, Reg13Op::Data.control(reg.devad))
+            .await;
+        self.smi_write(phy_addr, C22::MMD_DATA.0, val).await;
+    }
+
+    /// Read an extended register of a clause 22 PHY.
+    ///
+    /// Some clause 22 PHYs expose vendor-specific extended registers through the MMD access
+    /// registers as device [`Mmd::C22_EXT`] (29), which is not a clause 45 MMD like the PCS or
+    /// PMA/PMD. This is a shorthand for [`smi_read_mmd`](Self::smi_read_mmd) on that device.
+    async fn smi_read_ext(&mut self, phy_addr: u8, regnum: u16) -> u16 {
+        self.smi_read_mmd(phy_addr, C45::new(Mmd::C22_EXT, regnum)).await
+    }
+
+    /// Write an extended register of a clause 22 PHY, see [`smi_read_ext`](Self::smi_read_ext).
+    async fn smi_write_ext(&mut self, phy_addr: u8, regnum: u16, val: u16) {
+        self.smi_write_mmd(phy_addr, C45::new(Mmd::C22_EXT, regnum), val).await
+    }
+}
+
+impl<T: StationManagement> StationManagementAsync for T {
+    const SUPPORTS_C45_DIRECT: bool = <T as StationManagement>::SUPPORTS_C45_DIRECT;
+
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        StationManagement::smi_read(self, phy_addr, reg)
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        StationManagement::smi_write(self, phy_addr, reg, val)
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        StationManagement::set_bus_config(self, config)
+    }
+
+    async fn smi_read_many(&mut self, phy_addr: u8, regs: &[C22], out: &mut [u16]) {
+        StationManagement::smi_read_many(self, phy_addr, regs, out)
+    }
+
+    async fn smi_modify(&mut self, phy_addr: u8, reg: C22, f: impl FnOnce(u16) -> u16) -> u16 {
+        StationManagement::smi_modify(self, phy_addr, reg, f)
+    }
+
+    async fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        StationManagement::smi_read_mmd(self, phy_addr, reg)
+    }
+
+    async fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        StationManagement::smi_read_mmd_burst(self, phy_addr, start, out)
+    }
+
+    async fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        StationManagement::smi_write_mmd(self, phy_addr, reg, val)
+    }
+
+    async fn smi_read_ext(&mut self, phy_addr: u8, regnum: u16) -> u16 {
+        StationManagement::smi_read_ext(self, phy_addr, regnum)
+    }
+
+    async fn smi_write_ext(&mut self, phy_addr: u8, regnum: u16, val: u16) {
+        StationManagement::smi_write_ext(self, phy_addr, regnum, val)
+    }
+}
+
+/// Station Management Interface (SMI) that can issue clause 45 management frames
+///
+/// Clause 45 frames carry the MMD device address in the frame itself, so a register access takes
+/// a single frame instead of the four clause 22 frames of the indirect access. Wrap the bus in
+/// [`C45Direct`] to use them for the MMD accessors of [`StationManagement`].
+pub trait StationManagementC45 {
+    /// Read register `regnum` of MMD `devad` with clause 45 frames.
+    fn c45_read(&mut self, phy_addr: u8, devad: Mmd, regnum: u16) -> u16;
+    /// Write register `regnum` of MMD `devad` with clause 45 frames.
+    fn c45_write(&mut self, phy_addr: u8, devad: Mmd, regnum: u16, val: u16);
+}
+
+/// [`StationManagement`] adapter that accesses clause 45 registers with native clause 45 frames.
+///
+/// Clause 22 registers are accessed through the wrapped bus as before, while
+/// [`smi_read_mmd`](StationManagement::smi_read_mmd), [`smi_read_mmd_burst`](StationManagement::smi_read_mmd_burst)
+/// and [`smi_write_mmd`](StationManagement::smi_write_mmd) use [`StationManagementC45`].
+/// Only use it with PHYs that answer clause 45 frames.
+pub struct C45Direct<S>(pub S);
+
+impl<S: StationManagement + StationManagementC45> StationManagement for C45Direct<S> {
+    const SUPPORTS_C45_DIRECT: bool = true;
+
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        self.0.smi_read(phy_addr, reg)
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.0.smi_write(phy_addr, reg, val)
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.0.set_bus_config(config)
+    }
+
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.0.c45_read(phy_addr, reg.devad, reg.regnum)
+    }
+
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        // Wraps around after register 0xffff, like the address auto-increment of indirect access
+        for (i, out) in out.iter_mut().enumerate() {
+            let regnum = start.regnum.wrapping_add(i as u16);
+            *out = self.0.c45_read(phy_addr, start.devad, regnum);
+        }
+    }
+
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.0.c45_write(phy_addr, reg.devad, reg.regnum, val)
+    }
+}
+
+/// [`StationManagement`] adapter that selects clause 45 or indirect clause 22 MMD access at runtime.
+///
+/// Like [`C45Direct`] with native clause 45 frames enabled by [`prefer_c45`](Self::prefer_c45), and
+/// like the plain bus otherwise. Clause 22 registers always use clause 22 frames, so PHYs with
+/// standard clause 22 registers and clause 45 MMDs work either way. Indirect access is the
+/// default, since every PHY with MMDs behind clause 22 supports it.
+///
+/// `SUPPORTS_C45_DIRECT` is `false`, since the access mode is only known at runtime.
+pub struct C45Select<S> {
+    inner: S,
+    c45: bool,
+}
+
+impl<S> C45Select<S> {
+    /// Wrap `inner`, starting with indirect MMD access.
+    pub fn new(inner: S) -> Self {
+        Self { inner, c45: false }
+    }
+
+    /// Use native clause 45 frames (`true`) or indirect clause 22 access (`false`) for MMD registers.
+    pub fn prefer_c45(&mut self, yes: bool) {
+        self.c45 = yes;
+    }
+
+    /// Access the wrapped bus.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Return the wrapped bus.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: StationManagement + StationManagementC45> StationManagement for C45Select<S> {
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        self.inner.smi_read(phy_addr, reg)
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.inner.smi_write(phy_addr, reg, val)
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.inner.set_bus_config(config)
+    }
+
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        match self.c45 {
+            true => self.inner.c45_read(phy_addr, reg.devad, reg.regnum),
+            false => self.inner.smi_read_mmd(phy_addr, reg),
+        }
+    }
+
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        if !self.c45 {
+            return self.inner.smi_read_mmd_burst(phy_addr, start, out);
+        }
+        for (i, out) in out.iter_mut().enumerate() {
+            let regnum = start.regnum.wrapping_add(i as u16);
+            *out = self.inner.c45_read(phy_addr, start.devad, regnum);
+        }
+    }
+
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        match self.c45 {
+            true => self.inner.c45_write(phy_addr, reg.devad, reg.regnum, val),
+            false => self.inner.smi_write_mmd(phy_addr, reg, val),
+        }
+    }
+}
+
+// Index of the smallest of the ascending MDC clock `dividers` that brings `hclk_hz` down to at most
+// `mdc_hz`, or of the largest divider if none does.
+fn mdc_divider_index(hclk_hz: u32, mdc_hz: u32, dividers: &[u32]) -> usize {
+    dividers
+        .iter()
+        .position(|&div| hclk_hz / div <= mdc_hz)
+        .unwrap_or(dividers.len() - 1)
+}
+
+/// Ethernet link speed.
+///
+/// Speeds are ordered by line rate, so `speed.min(Speed::_100)` caps a speed at 100 Mbit/s.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// 10 Mbit/s
+    _10,
+    /// 100 Mbit/s
+    _100,
+    /// 1000 Mbit/s
+    _1000,
+    /// 2500 Mbit/s
+    _2500,
+    /// 5000 Mbit/s
+    _5000,
+    /// 10000 Mbit/s
+    _10000,
+}
+
+impl Speed {
+    /// Line rate in bits per second.
+    pub const fn bits_per_second(self) -> u64 {
+        self.as_mbit() as u64 * 1_000_000
+    }
+
+    /// Line rate in Mbit/s.
+    pub const fn as_mbit(self) -> u32 {
+        match self {
+            Speed::_10 => 10,
+            Speed::_100 => 100,
+            Speed::_1000 => 1000,
+            Speed::_2500 => 2500,
+            Speed::_5000 => 5000,
+            Speed::_10000 => 10000,
+        }
+    }
+}
+
+/// Line rate in Mbit/s that does not correspond to a [`Speed`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidSpeed(pub u32);
+
+impl TryFrom<u32> for Speed {
+    type Error = InvalidSpeed;
+
+    /// Convert a line rate in Mbit/s.
+    fn try_from(mbit: u32) -> Result<Self, Self::Error> {
+        match mbit {
+            10 => Ok(Speed::_10),
+            100 => Ok(Speed::_100),
+            1000 => Ok(Speed::_1000),
+            2500 => Ok(Speed::_2500),
+            5000 => Ok(Speed::_5000),
+            10000 => Ok(Speed::_10000),
+            _ => Err(InvalidSpeed(mbit)),
+        }
+    }
+}
+
+/// Ethernet duplex mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DuplexMode {
+    /// Half duplex
+    Half,
+    /// Full duplex
+    Full,
+}
+
+/// Resolved state of the link reported by a PHY.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkStatus {
+    /// No link.
+    Down,
+    /// Link is up with the given speed and duplex mode.
+    Up {
+        /// Negotiated speed.
+        speed: Speed,
+        /// Negotiated duplex mode.
+        duplex: DuplexMode,
+    },
+}
+
+impl LinkStatus {
+    /// Returns `true` if the link is up.
+    pub const fn is_up(&self) -> bool {
+        matches!(self, LinkStatus::Up { .. })
+    }
+
+    /// Returns `true` if the link is down.
+    pub const fn is_down(&self) -> bool {
+        !self.is_up()
+    }
+
+    /// Speed and duplex mode of the link, or `None` if the link is down.
+    pub const fn as_up(&self) -> Option<(Speed, DuplexMode)> {
+        match *self {
+            LinkStatus::Up { speed, duplex } => Some((speed, duplex)),
+            LinkStatus::Down => None,
+        }
+    }
+
+    /// Speed of the link, or `None` if the link is down.
+    pub const fn speed(&self) -> Option<Speed> {
+        match *self {
+            LinkStatus::Up { speed, .. } => Some(speed),
+            LinkStatus::Down => None,
+        }
+    }
+
+    /// Duplex mode of the link, or `None` if the link is down.
+    pub const fn duplex(&self) -> Option<DuplexMode> {
+        match *self {
+            LinkStatus::Up { duplex, .. } => Some(duplex),
+            LinkStatus::Down => None,
+        }
+    }
+
+    /// Speed and duplex settings for the MAC matching this link, or `None` if the link is down or
+    /// its speed is not supported by the MAC.
+    pub const fn mac_config(&self) -> Option<MacSpeedDuplex> {
+        match *self {
+            LinkStatus::Up { speed, duplex } => {
+                let fast_ethernet = match speed {
+                    Speed::_10 => false,
+                    Speed::_100 => true,
+                    _ => return None,
+                };
+                Some(MacSpeedDuplex {
+                    fast_ethernet,
+                    full_duplex: matches!(duplex, DuplexMode::Full),
+                })
+            }
+            LinkStatus::Down => None,
+        }
+    }
+}
+
+/// MAC speed and duplex settings, see [`LinkStatus::mac_config`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MacSpeedDuplex {
+    /// 100 Mbit/s instead of 10 Mbit/s (MACCR.FES).
+    pub fast_ethernet: bool,
+    /// Full duplex instead of half duplex (MACCR.DM).
+    pub full_duplex: bool,
+}
+
+/// Error reported by PHY operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PhyError {
+    /// Auto-detection found no PHY on any address.
+    NoPhyFound,
+    /// The PHY did not come out of reset in time.
+    ResetTimeout,
+    /// The PHY does not support the operation.
+    Unsupported,
+    /// The hardware reset pin of the PHY could not be driven.
+    ResetPin,
 }
 
 /// Trait for an Ethernet PHY
 pub trait Phy {
     /// Reset PHY and wait for it to come out of reset.
     fn phy_reset<S: StationManagement>(&mut self, sm: &mut S);
+    /// Reset PHY like [`Phy::phy_reset`], returning an error instead of panicking if the PHY
+    /// doesn't respond.
+    ///
+    /// The default implementation calls [`Phy::phy_reset`] and never fails.
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.phy_reset(sm);
+        Ok(())
+    }
     /// PHY initialisation.
     fn phy_init<S: StationManagement>(&mut self, sm: &mut S);
     /// Poll link to see if it is up and FD with 100Mbps
     fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool;
 }
 
+/// Trait for an Ethernet PHY driven over an asynchronous SMI
+pub trait PhyAsync {
+    /// Reset PHY and wait for it to come out of reset.
+    async fn phy_reset<S: StationManagementAsync>(&mut self, sm: &mut S);
+    /// Reset PHY like [`PhyAsync::phy_reset`], returning an error instead of panicking if the PHY
+    /// doesn't respond.
+    ///
+    /// The default implementation calls [`PhyAsync::phy_reset`] and never fails.
+    async fn try_phy_reset<S: StationManagementAsync>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.phy_reset(sm).await;
+        Ok(())
+    }
+    /// PHY initialisation.
+    async fn phy_init<S: StationManagementAsync>(&mut self, sm: &mut S);
+    /// Check whether the link is up.
+    async fn poll_link<S: StationManagementAsync>(&mut self, sm: &mut S) -> bool;
+}
+
 impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
     /// Directly expose the SMI interface used by the Ethernet driver.
     ///
@@ -225,3 +832,213 @@ pin_trait!(TXD1Pin, Instance);
 pin_trait!(TXD2Pin, Instance);
 pin_trait!(TXD3Pin, Instance);
 pin_trait!(TXEnPin, Instance);
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{Action, RecordingMdioBus};
+    use super::*;
+
+    #[test]
+    fn mdc_divider_selection() {
+        let dividers = [16, 26, 42, 62, 102];
+        assert_eq!(mdc_divider_index(168_000_000, 2_500_000, &dividers), 4);
+        assert_eq!(mdc_divider_index(168_000_000, 5_000_000, &dividers), 2);
+        assert_eq!(mdc_divider_index(32_000_000, 2_500_000, &dividers), 0);
+        assert_eq!(mdc_divider_index(168_000_000, 100_000, &dividers), 4);
+    }
+
+    #[test]
+    fn speed_conversions() {
+        let all = [
+            (Speed::_10, 10),
+            (Speed::_100, 100),
+            (Speed::_1000, 1000),
+            (Speed::_2500, 2500),
+            (Speed::_5000, 5000),
+            (Speed::_10000, 10000),
+        ];
+        for (speed, mbit) in all {
+            assert_eq!(speed.as_mbit(), mbit);
+            assert_eq!(speed.bits_per_second(), mbit as u64 * 1_000_000);
+            assert_eq!(Speed::try_from(mbit), Ok(speed));
+        }
+        assert_eq!(Speed::try_from(40000), Err(InvalidSpeed(40000)));
+
+        assert!(all.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(Speed::_1000.min(Speed::_100), Speed::_100);
+        assert_eq!(Speed::_10.max(Speed::_100), Speed::_100);
+    }
+
+    #[test]
+    fn c45_direct_support_is_forwarded() {
+        assert_eq!(
+            [
+                <RecordingMdioBus as StationManagementAsync>::SUPPORTS_C45_DIRECT,
+                <C45Direct<RecordingMdioBus> as StationManagementAsync>::SUPPORTS_C45_DIRECT,
+            ],
+            [false, true]
+        );
+    }
+
+    #[test]
+    fn link_status_accessors() {
+        let up = LinkStatus::Up {
+            speed: Speed::_100,
+            duplex: DuplexMode::Half,
+        };
+        assert_eq!(up.as_up(), Some((Speed::_100, DuplexMode::Half)));
+        assert_eq!(up.speed(), Some(Speed::_100));
+        assert_eq!(up.duplex(), Some(DuplexMode::Half));
+
+        assert_eq!(LinkStatus::Down.as_up(), None);
+        assert_eq!(LinkStatus::Down.speed(), None);
+        assert_eq!(LinkStatus::Down.duplex(), None);
+    }
+
+    #[test]
+    fn link_status_mac_config() {
+        let up = |speed, duplex| LinkStatus::Up { speed, duplex };
+        let cfg = |fast_ethernet, full_duplex| {
+            Some(MacSpeedDuplex {
+                fast_ethernet,
+                full_duplex,
+            })
+        };
+        assert_eq!(up(Speed::_10, DuplexMode::Half).mac_config(), cfg(false, false));
+        assert_eq!(up(Speed::_10, DuplexMode::Full).mac_config(), cfg(false, true));
+        assert_eq!(up(Speed::_100, DuplexMode::Half).mac_config(), cfg(true, false));
+        assert_eq!(up(Speed::_100, DuplexMode::Full).mac_config(), cfg(true, true));
+        assert_eq!(up(Speed::_1000, DuplexMode::Half).mac_config(), None);
+        assert_eq!(up(Speed::_1000, DuplexMode::Full).mac_config(), None);
+        assert_eq!(LinkStatus::Down.mac_config(), None);
+    }
+
+    #[test]
+    fn c45_direct_uses_native_frames() {
+        let mut bus = RecordingMdioBus::new();
+        bus.set_mmd(1, C45::AN_EEE_ADV, 0x0002);
+        for (regnum, val) in (20..).zip([0x0006, 0x0000, 0x0010]) {
+            bus.set_mmd(2, C45::new(Mmd::PCS, regnum), val);
+        }
+        let mut sm = C45Direct(bus);
+        assert!(<C45Direct<RecordingMdioBus> as StationManagement>::SUPPORTS_C45_DIRECT);
+
+        assert_eq!(StationManagement::smi_read_mmd(&mut sm, 1, C45::AN_EEE_ADV), 0x0002);
+        let mut out = [0; 3];
+        StationManagement::smi_read_mmd_burst(&mut sm, 2, C45::new(Mmd::PCS, 20), &mut out);
+        assert_eq!(out, [0x0006, 0x0000, 0x0010]);
+        StationManagement::smi_write_mmd(&mut sm, 1, C45::AN_EEE_ADV, 0x0006);
+
+        // No clause 22 frames
+        assert_eq!(
+            sm.0.actions,
+            [
+                Action::ReadMmd(1, C45::AN_EEE_ADV),
+                Action::ReadMmd(2, C45::new(Mmd::PCS, 20)),
+                Action::ReadMmd(2, C45::new(Mmd::PCS, 21)),
+                Action::ReadMmd(2, C45::new(Mmd::PCS, 22)),
+                Action::WriteMmd(1, C45::AN_EEE_ADV, 0x0006),
+            ]
+        );
+
+        sm.0.set_mmd(2, C45::new(Mmd::PCS, 0xffff), 0x1234);
+        let mut out = [0xffff; 2];
+        StationManagement::smi_read_mmd_burst(&mut sm, 2, C45::new(Mmd::PCS, 0xffff), &mut out);
+        assert_eq!(out, [0x1234, 0]);
+        assert_eq!(sm.0.actions[6], Action::ReadMmd(2, C45::new(Mmd::PCS, 0)));
+    }
+
+    #[test]
+    fn async_mmd_burst_sets_up_address_once() {
+        // Implements only the async trait, so the async default is used instead of the sync one.
+        struct AsyncOnly(RecordingMdioBus);
+        impl StationManagementAsync for AsyncOnly {
+            async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+                StationManagement::smi_read(&mut self.0, phy_addr, reg)
+            }
+            async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+                StationManagement::smi_write(&mut self.0, phy_addr, reg, val)
+            }
+        }
+
+        let mut bus = RecordingMdioBus::new();
+        for (regnum, val) in (0x8061..).zip([0xbc9a, 0x7856, 0x3412]) {
+            bus.set_mmd(2, C45::new(Mmd::PCS, regnum), val);
+        }
+        let mut sm = AsyncOnly(bus);
+        let mut out = [0; 3];
+        embassy_futures::block_on(StationManagementAsync::smi_read_mmd_burst(
+            &mut sm,
+            2,
+            C45::new(Mmd::PCS, 0x8061),
+            &mut out,
+        ));
+        assert_eq!(out, [0xbc9a, 0x7856, 0x3412]);
+        assert_eq!(
+            sm.0.actions,
+            [
+                Action::Write(2, C22::MMD_CONTROL, 0x0003),
+                Action::Write(2, C22::MMD_DATA, 0x8061),
+                Action::Write(2, C22::MMD_CONTROL, 0x8003),
+                Action::Read(2, C22::MMD_DATA),
+                Action::Read(2, C22::MMD_DATA),
+                Action::Read(2, C22::MMD_DATA),
+            ]
+        );
+    }
+
+    #[test]
+    fn c45_select_per_access() {
+        let mut bus = RecordingMdioBus::new();
+        bus.set(1, C22::BMSR, 0x796d);
+        bus.set_mmd(1, C45::PCS_STAT1, 0x0045);
+        let mut sm = C45Select::new(bus);
+
+        // Indirect access: 3 writes and a read
+        assert_eq!(StationManagement::smi_read_mmd(&mut sm, 1, C45::PCS_STAT1), 0x0045);
+        assert_eq!(sm.inner_mut().actions.len(), 4);
+        assert_eq!(sm.inner_mut().actions[3], Action::Read(1, C22::MMD_DATA));
+
+        sm.inner_mut().actions.clear();
+        sm.prefer_c45(true);
+        assert_eq!(StationManagement::smi_read_mmd(&mut sm, 1, C45::PCS_STAT1), 0x0045);
+        // Clause 22 registers still use clause 22 frames
+        assert_eq!(StationManagement::smi_read(&mut sm, 1, C22::BMSR.0), 0x796d);
+        assert_eq!(
+            sm.inner_mut().actions,
+            [Action::ReadMmd(1, C45::PCS_STAT1), Action::Read(1, C22::BMSR)]
+        );
+
+        // Bursts wrap around after register 0xffff
+        sm.inner_mut().actions.clear();
+        let mut out = [0; 2];
+        StationManagement::smi_read_mmd_burst(&mut sm, 1, C45::new(Mmd::PCS, 0xffff), &mut out);
+        assert_eq!(
+            sm.inner_mut().actions,
+            [
+                Action::ReadMmd(1, C45::new(Mmd::PCS, 0xffff)),
+                Action::ReadMmd(1, C45::new(Mmd::PCS, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn ext_registers_use_c22_ext_device() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set_mmd(0, C45::new(Mmd::C22_EXT, 0x10b), 0x1234);
+
+        assert_eq!(StationManagement::smi_read_ext(&mut sm, 0, 0x10b), 0x1234);
+        StationManagement::smi_write_ext(&mut sm, 0, 0x10b, 0x0001);
+        assert_eq!(sm.get_mmd(0, C45::new(Mmd::C22_EXT, 0x10b)), 0x0001);
+
+        let setup = [
+            Action::Write(0, C22::MMD_CONTROL, 29),
+            Action::Write(0, C22::MMD_DATA, 0x10b),
+            Action::Write(0, C22::MMD_CONTROL, 0x4000 | 29),
+        ];
+        assert_eq!(sm.actions[..3], setup);
+        assert_eq!(sm.actions[3], Action::Read(0, C22::MMD_DATA));
+        assert_eq!(sm.actions[4..7], setup);
+        assert_eq!(sm.actions[7], Action::Write(0, C22::MMD_DATA, 0x0001));
+    }
+}
@@ -0,0 +1,117 @@
+//! Group of PHYs on one SMI bus
+
+use super::{GenericPhy, LinkStatus, Phy, PhyLinkStatus, StationManagement};
+
+/// `N` PHYs sharing one [`StationManagement`] bus, addressed as a group.
+///
+/// Switch chips expose one PHY per port behind a single MDIO bus. `PhyGroup` keeps a PHY driver
+/// for each port and runs it against the shared bus, so all ports can be initialized and polled
+/// together. The ports are identified by their index in the array passed to [`new`](Self::new).
+pub struct PhyGroup<S, P: Phy, const N: usize> {
+    sm: S,
+    phys: [P; N],
+}
+
+impl<S: StationManagement, P: Phy, const N: usize> PhyGroup<S, P, N> {
+    /// Create a group of `phys` on `sm`.
+    pub fn new(sm: S, phys: [P; N]) -> Self {
+        Self { sm, phys }
+    }
+
+    /// Reset and initialize every PHY in the group, in order.
+    pub fn init_all(&mut self) {
+        for phy in &mut self.phys {
+            phy.phy_reset(&mut self.sm);
+            phy.phy_init(&mut self.sm);
+        }
+    }
+
+    /// Read the link status of every PHY in the group, in order.
+    pub fn poll_all_links(&mut self) -> [LinkStatus; N]
+    where
+        P: PhyLinkStatus,
+    {
+        // The `StationManagementAsync` impl of a blocking bus never yields, so this doesn't spin.
+        self.phys
+            .each_mut()
+            .map(|phy| embassy_futures::block_on(phy.link_status_async(&mut self.sm)))
+    }
+
+    /// Get the PHY at `index`, e.g. to configure it before [`init_all`](Self::init_all).
+    pub fn phy_mut(&mut self, index: usize) -> Option<&mut P> {
+        self.phys.get_mut(index)
+    }
+
+    /// Get a mutable reference to the bus.
+    pub fn station_management(&mut self) -> &mut S {
+        &mut self.sm
+    }
+
+    /// Return the bus.
+    pub fn into_inner(self) -> S {
+        self.sm
+    }
+}
+
+impl<S: StationManagement, const N: usize> PhyGroup<S, GenericPhy, N> {
+    /// Create a group of a [`GenericPhy`] at each of `addrs` on `sm`.
+    ///
+    /// # Panics
+    /// Each address must be in range `0..32`.
+    pub fn new_generic(sm: S, addrs: [u8; N]) -> Self {
+        Self::new(sm, addrs.map(GenericPhy::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::RecordingMdioBus;
+    use crate::eth::regs::{Bmsr, C22};
+    use crate::eth::{DuplexMode, Ksz8081, Speed};
+
+    #[test]
+    fn poll_all_links() {
+        const BMSR_LINK_UP_AN_DONE: u16 = 1 << 2 | 1 << 5;
+        const AN_100FD: u16 = 1 << 8 | 1;
+
+        // Two PHYs at addresses 1 and 2; every other address reads as absent.
+        let mut bus = RecordingMdioBus::with_phys([1, 2]);
+        bus.set(1, C22::BMSR, BMSR_LINK_UP_AN_DONE);
+        bus.set(1, C22::ADVERTISE, AN_100FD);
+        bus.set(1, C22::LPA, AN_100FD);
+
+        let mut group = PhyGroup::new_generic(bus, [1, 2]);
+        let links = group.poll_all_links();
+        assert_eq!(
+            links,
+            [
+                LinkStatus::Up {
+                    speed: Speed::_100,
+                    duplex: DuplexMode::Full
+                },
+                LinkStatus::Down,
+            ]
+        );
+
+        assert_eq!(group.phy_mut(1).and_then(|phy| phy.phy_addr()), Some(2));
+        assert!(group.phy_mut(2).is_none());
+    }
+
+    #[test]
+    fn vendor_phys() {
+        // PHY Control 1 of the KSZ8081, reporting 100BASE-TX full duplex.
+        const PHYCTRL1: C22 = C22::vendor_specific::<0x1e>();
+
+        let mut bus = RecordingMdioBus::new();
+        bus.set(3, C22::BMSR, Bmsr(0).with_link_up(true).0);
+        bus.set(3, PHYCTRL1, 0b110);
+
+        let mut group = PhyGroup::new(bus, [Ksz8081::new(3), Ksz8081::new(4)]);
+        let up = LinkStatus::Up {
+            speed: Speed::_100,
+            duplex: DuplexMode::Full,
+        };
+        assert_eq!(group.poll_all_links(), [up, LinkStatus::Down]);
+    }
+}
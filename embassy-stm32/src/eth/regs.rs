@@ -0,0 +1,535 @@
+//! Typed views of standard PHY registers.
+
+macro_rules! bit_accessors {
+    ($($(#[$attr:meta])* $get:ident, $set:ident: $bit:literal;)*) => {
+        $(
+            $(#[$attr])*
+            pub const fn $get(&self) -> bool {
+                self.0 & (1 << $bit) != 0
+            }
+
+            #[doc = concat!("Return a copy with [`Self::", stringify!($get), "`] set to `val`.")]
+            pub const fn $set(self, val: bool) -> Self {
+                if val {
+                    Self(self.0 | (1 << $bit))
+                } else {
+                    Self(self.0 & !(1 << $bit))
+                }
+            }
+        )*
+    };
+}
+
+/// Clause 22 register address.
+///
+/// With the `defmt` feature, known registers are formatted by name and others by address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct C22(pub u8);
+
+impl C22 {
+    /// Basic mode control register.
+    pub const BMCR: C22 = C22(0x00);
+    /// Basic mode status register.
+    pub const BMSR: C22 = C22(0x01);
+    /// PHY identifier 1.
+    pub const PHYSID1: C22 = C22(0x02);
+    /// PHY identifier 2.
+    pub const PHYSID2: C22 = C22(0x03);
+    /// Auto-negotiation advertisement.
+    pub const ADVERTISE: C22 = C22(0x04);
+    /// Auto-negotiation link partner ability.
+    pub const LPA: C22 = C22(0x05);
+    /// Auto-negotiation expansion.
+    pub const EXPANSION: C22 = C22(0x06);
+    /// Auto-negotiation next page transmit.
+    pub const NPTX: C22 = C22(0x07);
+    /// Auto-negotiation link partner next page receive.
+    pub const NPRX: C22 = C22(0x08);
+    /// 1000BASE-T control, see [`MsControl`].
+    pub const MASTER_SLAVE_CONTROL: C22 = C22(0x09);
+    /// 1000BASE-T status, see [`MsStatus`].
+    pub const MASTER_SLAVE_STATUS: C22 = C22(0x0a);
+    /// MMD access control.
+    pub const MMD_CONTROL: C22 = C22(0x0d);
+    /// MMD access address/data.
+    pub const MMD_DATA: C22 = C22(0x0e);
+    /// Extended status.
+    pub const ESTATUS: C22 = C22(0x0f);
+
+    /// Vendor-specific register `N`, which must be in the range `16..=31`.
+    pub const fn vendor_specific<const N: u8>() -> C22 {
+        const { ::core::assert!(N >= 16 && N <= 31, "vendor-specific registers are 16..=31") };
+        C22(N)
+    }
+
+    /// All vendor-specific registers, `16..=31`, e.g. to dump them from an unknown PHY.
+    pub fn vendor_range() -> impl Iterator<Item = C22> {
+        (16..=31).map(C22)
+    }
+
+    /// Returns `true` if this is a vendor-specific register, see [`C22::vendor_specific`].
+    pub const fn is_vendor_specific(&self) -> bool {
+        matches!(self.0, 16..=31)
+    }
+
+    /// Name of the register if it is one of the constants above.
+    pub const fn name(&self) -> Option<&'static str> {
+        Some(match *self {
+            C22::BMCR => "BMCR",
+            C22::BMSR => "BMSR",
+            C22::PHYSID1 => "PHYSID1",
+            C22::PHYSID2 => "PHYSID2",
+            C22::ADVERTISE => "ADVERTISE",
+            C22::LPA => "LPA",
+            C22::EXPANSION => "EXPANSION",
+            C22::NPTX => "NPTX",
+            C22::NPRX => "NPRX",
+            C22::MASTER_SLAVE_CONTROL => "MASTER_SLAVE_CONTROL",
+            C22::MASTER_SLAVE_STATUS => "MASTER_SLAVE_STATUS",
+            C22::MMD_CONTROL => "MMD_CONTROL",
+            C22::MMD_DATA => "MMD_DATA",
+            C22::ESTATUS => "ESTATUS",
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for C22 {
+    fn format(&self, f: defmt::Formatter) {
+        match self.name() {
+            Some(name) => defmt::write!(f, "{=str}", name),
+            None => defmt::write!(f, "{=u8:#04x}", self.0),
+        }
+    }
+}
+
+/// Mask applied to the MMD device address in the MMD access control register.
+pub const DEV_MASK: u16 = 0x1f;
+
+/// Clause 45 MMD (MDIO manageable device) address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mmd(pub u8);
+
+impl Mmd {
+    /// PMA/PMD
+    pub const PMAPMD: Mmd = Mmd(1);
+    /// WIS
+    pub const WIS: Mmd = Mmd(2);
+    /// PCS
+    pub const PCS: Mmd = Mmd(3);
+    /// PHY XS
+    pub const PHYXS: Mmd = Mmd(4);
+    /// DTE XS
+    pub const DTEXS: Mmd = Mmd(5);
+    /// TC
+    pub const TC: Mmd = Mmd(6);
+    /// Auto-negotiation
+    pub const AN: Mmd = Mmd(7);
+    /// Clause 22 extension
+    pub const C22_EXT: Mmd = Mmd(29);
+    /// Vendor specific 1
+    pub const VEND1: Mmd = Mmd(30);
+    /// Vendor specific 2
+    pub const VEND2: Mmd = Mmd(31);
+}
+
+/// Error returned when an MMD device address is above [`DEV_MASK`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidMmd(pub Mmd);
+
+/// Clause 45 register address: an MMD device address and a register number within it.
+///
+/// Common registers are available as associated constants:
+///
+/// ```ignore
+/// let eee_adv = sm.smi_read_mmd(phy_addr, C45::AN_EEE_ADV);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct C45 {
+    pub(crate) devad: Mmd,
+    pub(crate) regnum: u16,
+}
+
+impl C45 {
+    /// PMA/PMD control 1 (1.0), see [`PmaCtrl1`].
+    pub const PMA_CTRL1: C45 = C45::new(Mmd::PMAPMD, 0);
+    /// PMA/PMD speed ability (1.4), see [`PmaSpeedAbility`].
+    pub const PMA_SPEED: C45 = C45::new(Mmd::PMAPMD, 4);
+    /// PCS status 1 (3.1), see [`PcsStat1`].
+    pub const PCS_STAT1: C45 = C45::new(Mmd::PCS, 1);
+    /// PCS EEE capability (3.20).
+    pub const PCS_EEE_ABLE: C45 = C45::new(Mmd::PCS, 20);
+    /// Auto-negotiation advertisement (7.16).
+    pub const AN_ADV: C45 = C45::new(Mmd::AN, 16);
+    /// Auto-negotiation link partner base page ability (7.19).
+    pub const AN_LPA: C45 = C45::new(Mmd::AN, 19);
+    /// EEE advertisement (7.60).
+    pub const AN_EEE_ADV: C45 = C45::new(Mmd::AN, 60);
+    /// EEE link partner ability (7.61).
+    pub const AN_EEE_LPABLE: C45 = C45::new(Mmd::AN, 61);
+
+    /// Create a clause 45 register address.
+    ///
+    /// Device addresses are 5 bits; the upper bits of `devad` are masked off when the register is
+    /// accessed, so an out-of-range address silently accesses a different device. Use
+    /// [`C45::try_new`] to reject it instead.
+    pub const fn new(devad: Mmd, regnum: u16) -> Self {
+        Self { devad, regnum }
+    }
+
+    /// Create a clause 45 register address, returning an error if `devad` is above [`DEV_MASK`].
+    pub const fn try_new(devad: Mmd, regnum: u16) -> Result<Self, InvalidMmd> {
+        if devad.0 as u16 > DEV_MASK {
+            return Err(InvalidMmd(devad));
+        }
+        Ok(Self { devad, regnum })
+    }
+
+    /// MMD device address.
+    pub const fn devad(&self) -> Mmd {
+        self.devad
+    }
+
+    /// Register number within the MMD.
+    pub const fn regnum(&self) -> u16 {
+        self.regnum
+    }
+}
+
+/// Function field of the MMD access control register (register 0x0d, bits 15:14).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reg13Op {
+    /// Access the MMD address register.
+    Address = 0b00,
+    /// Access data, no post increment.
+    Data = 0b01,
+    /// Access data, post increment the address on reads and writes.
+    PostReadIncAddr = 0b10,
+    /// Access data, post increment the address on writes only.
+    PostWriteIncAddr = 0b11,
+}
+
+impl Reg13Op {
+    /// MMD access control register value selecting this function for device `devad`.
+    ///
+    /// The device address is masked with [`DEV_MASK`].
+    pub const fn control(self, devad: Mmd) -> u16 {
+        (self as u16) << 14 | (devad.0 as u16 & DEV_MASK)
+    }
+}
+
+/// PHY identifier from the `PHYSID1`/`PHYSID2` registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PhyId {
+    oui: u32,
+    model: u8,
+    revision: u8,
+}
+
+impl PhyId {
+    /// Decode the identifier from the values of `PHYSID1` and `PHYSID2`.
+    ///
+    /// `PHYSID1` holds OUI bits 3-18, `PHYSID2` holds OUI bits 19-24 in bits 10-15,
+    /// the model number in bits 4-9 and the revision in bits 0-3.
+    pub const fn from_regs(physid1: u16, physid2: u16) -> Self {
+        Self {
+            oui: (physid1 as u32) << 6 | (physid2 >> 10) as u32,
+            model: ((physid2 >> 4) & 0x3f) as u8,
+            revision: (physid2 & 0x0f) as u8,
+        }
+    }
+
+    /// Organizationally unique identifier (bits 3-24 of the IEEE OUI).
+    pub const fn oui(&self) -> u32 {
+        self.oui
+    }
+
+    /// Vendor model number.
+    pub const fn model(&self) -> u8 {
+        self.model
+    }
+
+    /// Vendor revision number.
+    pub const fn revision(&self) -> u8 {
+        self.revision
+    }
+}
+
+/// Basic mode status register (BMSR, register 0x01).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Bmsr(pub u16);
+
+impl Bmsr {
+    /// Register address.
+    pub const ADDR: u8 = 0x01;
+
+    bit_accessors! {
+        /// Extended register capabilities.
+        extended_capability, with_extended_capability: 0;
+        /// Jabber condition detected (latches high until read).
+        jabber_detect, with_jabber_detect: 1;
+        /// Link is up (latches low until read).
+        link_up, with_link_up: 2;
+        /// Able to perform auto-negotiation.
+        can_autoneg, with_can_autoneg: 3;
+        /// Remote fault detected (latches high until read).
+        remote_fault, with_remote_fault: 4;
+        /// Auto-negotiation process completed.
+        autoneg_complete, with_autoneg_complete: 5;
+        /// Accepts management frames with preamble suppressed.
+        preamble_suppression, with_preamble_suppression: 6;
+        /// Extended status information in register 0x0f.
+        extended_status, with_extended_status: 8;
+        /// 100BASE-T2 half duplex capable.
+        can_100base_t2_hd, with_can_100base_t2_hd: 9;
+        /// 100BASE-T2 full duplex capable.
+        can_100base_t2_fd, with_can_100base_t2_fd: 10;
+        /// 10BASE-T half duplex capable.
+        can_10base_t_hd, with_can_10base_t_hd: 11;
+        /// 10BASE-T full duplex capable.
+        can_10base_t_fd, with_can_10base_t_fd: 12;
+        /// 100BASE-TX half duplex capable.
+        can_100base_tx_hd, with_can_100base_tx_hd: 13;
+        /// 100BASE-TX full duplex capable.
+        can_100base_tx_fd, with_can_100base_tx_fd: 14;
+        /// 100BASE-T4 capable.
+        can_100base_t4, with_can_100base_t4: 15;
+    }
+}
+
+/// 1000BASE-T control register (MASTER-SLAVE control, register 0x09).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MsControl(pub u16);
+
+impl MsControl {
+    /// Register address.
+    pub const ADDR: u8 = 0x09;
+
+    bit_accessors! {
+        /// Advertise 1000BASE-T half duplex.
+        adv_1000_half, with_adv_1000_half: 8;
+        /// Advertise 1000BASE-T full duplex.
+        adv_1000_full, with_adv_1000_full: 9;
+        /// Multiport device (prefer master) instead of single-port device (prefer slave).
+        multiport, with_multiport: 10;
+        /// Manual configuration value: `true` for master, `false` for slave.
+        master, with_master: 11;
+        /// Enable manual MASTER-SLAVE configuration.
+        manual_config, with_manual_config: 12;
+    }
+}
+
+/// 1000BASE-T status register (MASTER-SLAVE status, register 0x0a).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MsStatus(pub u16);
+
+impl MsStatus {
+    /// Register address.
+    pub const ADDR: u8 = 0x0a;
+
+    bit_accessors! {
+        /// Link partner is capable of 1000BASE-T half duplex.
+        lp_1000_half, with_lp_1000_half: 10;
+        /// Link partner is capable of 1000BASE-T full duplex.
+        lp_1000_full, with_lp_1000_full: 11;
+        /// Remote receiver status is OK.
+        remote_receiver_ok, with_remote_receiver_ok: 12;
+        /// Local receiver status is OK.
+        local_receiver_ok, with_local_receiver_ok: 13;
+        /// MASTER-SLAVE resolved to master.
+        resolved_master, with_resolved_master: 14;
+        /// MASTER-SLAVE configuration fault detected.
+        config_fault, with_config_fault: 15;
+    }
+
+    /// Number of idle errors since the last read.
+    pub const fn idle_error_count(&self) -> u8 {
+        self.0 as u8
+    }
+}
+
+/// PMA/PMD control 1 register (1.0).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PmaCtrl1(pub u16);
+
+impl PmaCtrl1 {
+    /// Register address.
+    pub const ADDR: C45 = C45::PMA_CTRL1;
+
+    bit_accessors! {
+        /// Speed selection MSB (bit 6). Set together with the LSB to select the speed in bits 5:2.
+        speed_sel_msb, with_speed_sel_msb: 6;
+        /// Low power mode.
+        low_power, with_low_power: 11;
+        /// Speed selection LSB (bit 13). Set together with the MSB to select the speed in bits 5:2.
+        speed_sel_lsb, with_speed_sel_lsb: 13;
+        /// Reset the PMA/PMD (self-clearing).
+        reset, with_reset: 15;
+    }
+
+    /// Extended speed selection (bits 5:2), only meaningful if bits 13 and 6 are both set.
+    pub const fn speed_sel_ext(&self) -> u8 {
+        (self.0 >> 2) as u8 & 0xf
+    }
+}
+
+/// PMA/PMD speed ability register (1.4).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PmaSpeedAbility(pub u16);
+
+impl PmaSpeedAbility {
+    /// Register address.
+    pub const ADDR: C45 = C45::PMA_SPEED;
+
+    bit_accessors! {
+        /// Capable of 10 Gbit/s.
+        can_10g, with_can_10g: 0;
+        /// Capable of 1000 Mbit/s.
+        can_1000, with_can_1000: 4;
+        /// Capable of 100 Mbit/s.
+        can_100, with_can_100: 5;
+        /// Capable of 10 Mbit/s.
+        can_10, with_can_10: 6;
+        /// Capable of 2.5 Gbit/s.
+        can_2g5, with_can_2g5: 13;
+        /// Capable of 5 Gbit/s.
+        can_5g, with_can_5g: 14;
+    }
+}
+
+/// PCS status 1 register (3.1).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PcsStat1(pub u16);
+
+impl PcsStat1 {
+    /// Register address.
+    pub const ADDR: C45 = C45::PCS_STAT1;
+
+    bit_accessors! {
+        /// Supports low power mode.
+        low_power_ability, with_low_power_ability: 1;
+        /// PCS receive link is up (latches low until read).
+        link_up, with_link_up: 2;
+        /// Fault condition detected.
+        fault, with_fault: 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c22_names() {
+        assert_eq!(C22::BMSR.name(), Some("BMSR"));
+        assert_eq!(C22(0x09).name(), Some("MASTER_SLAVE_CONTROL"));
+        assert_eq!(C22::vendor_specific::<31>().name(), None);
+    }
+
+    #[test]
+    fn vendor_range() {
+        assert_eq!(C22::vendor_range().count(), 16);
+        assert!(C22::vendor_range().all(|reg| reg.is_vendor_specific()));
+        assert_eq!(C22::vendor_range().next(), Some(C22::vendor_specific::<16>()));
+        assert!(!C22::ESTATUS.is_vendor_specific());
+        assert!(!C22(32).is_vendor_specific());
+    }
+
+    #[test]
+    fn c45_accessors() {
+        let reg = C45::new(Mmd::AN, 60);
+        assert_eq!(reg.devad(), Mmd::AN);
+        assert_eq!(reg.regnum(), 60);
+        assert_eq!(reg, C45::new(Mmd(7), 60));
+        assert_ne!(reg, C45::new(Mmd::PCS, 60));
+    }
+
+    #[test]
+    fn c45_try_new() {
+        assert_eq!(C45::try_new(Mmd::VEND2, 1), Ok(C45::new(Mmd::VEND2, 1)));
+        assert_eq!(C45::try_new(Mmd(32), 1), Err(InvalidMmd(Mmd(32))));
+    }
+
+    #[test]
+    fn phy_id_decode() {
+        // LAN8742A
+        let id = PhyId::from_regs(0x0007, 0xc131);
+        assert_eq!(id.oui(), 0x0001f0);
+        assert_eq!(id.model(), 0x13);
+        assert_eq!(id.revision(), 1);
+    }
+
+    #[test]
+    fn bmsr_decode() {
+        let bmsr = Bmsr(0x786d);
+        assert!(bmsr.extended_capability());
+        assert!(!bmsr.jabber_detect());
+        assert!(bmsr.link_up());
+        assert!(bmsr.can_autoneg());
+        assert!(!bmsr.remote_fault());
+        assert!(bmsr.autoneg_complete());
+        assert!(bmsr.preamble_suppression());
+        assert!(!bmsr.extended_status());
+        assert!(bmsr.can_10base_t_hd());
+        assert!(bmsr.can_10base_t_fd());
+        assert!(bmsr.can_100base_tx_hd());
+        assert!(bmsr.can_100base_tx_fd());
+        assert!(!bmsr.can_100base_t4());
+    }
+
+    #[test]
+    fn pma_ctrl1_decode() {
+        // 2.5G selected
+        let ctrl = PmaCtrl1(0x2058);
+        assert!(ctrl.speed_sel_msb());
+        assert!(ctrl.speed_sel_lsb());
+        assert_eq!(ctrl.speed_sel_ext(), 0b0110);
+        assert!(!ctrl.low_power());
+        assert_eq!(PmaCtrl1::default().with_reset(true).0, 0x8000);
+        // 100M selects with the LSB, 1000M with the MSB
+        assert_eq!(PmaCtrl1::default().with_speed_sel_lsb(true).0, 0x2000);
+        assert_eq!(PmaCtrl1::default().with_speed_sel_msb(true).0, 0x0040);
+    }
+
+    #[test]
+    fn ms_control_round_trip() {
+        let ctrl = MsControl::default()
+            .with_adv_1000_full(true)
+            .with_manual_config(true)
+            .with_master(true);
+        assert_eq!(ctrl.0, 0x1a00);
+
+        let ctrl = MsControl(ctrl.0);
+        assert!(ctrl.adv_1000_full());
+        assert!(!ctrl.adv_1000_half());
+        assert!(ctrl.manual_config());
+        assert!(ctrl.master());
+        assert!(!ctrl.multiport());
+
+        assert_eq!(ctrl.with_master(false).0, 0x1200);
+    }
+
+    #[test]
+    fn ms_status_decode() {
+        let status = MsStatus(0x3c05);
+        assert!(!status.config_fault());
+        assert!(!status.resolved_master());
+        assert!(status.local_receiver_ok());
+        assert!(status.remote_receiver_ok());
+        assert!(status.lp_1000_full());
+        assert!(status.lp_1000_half());
+        assert_eq!(status.idle_error_count(), 5);
+    }
+}
@@ -0,0 +1,218 @@
+//! SMI bus with read retries
+
+use super::regs::C45;
+use super::{SmiBusConfig, StationManagement};
+
+/// [`StationManagement`] wrapper that retries reads returning `0xffff`.
+///
+/// A glitch on a noisy MDIO bus, or a PHY that doesn't drive MDIO in time, reads as all ones. Such
+/// reads are repeated up to `retries` times; if every attempt returns `0xffff`, that value is
+/// returned, so an address without a PHY still reads as `0xffff`. A clause 45 burst read is
+/// repeated as a whole while any register in it reads `0xffff`, since it goes through the wrapped
+/// bus's own burst access, which may be a post-increment sequence that can't resume halfway.
+///
+/// Writes are passed through once and never retried, since the bus gives no acknowledgement and
+/// writing some registers (e.g. self-clearing or latched bits) again is not harmless.
+///
+/// This wraps a blocking bus only. Use [`RetrySmiAsync`] for async buses such as
+/// [`SpiMdio`](super::SpiMdio).
+pub struct RetrySmi<S> {
+    inner: S,
+    retries: u8,
+}
+
+impl<S> RetrySmi<S> {
+    /// Create a new `RetrySmi` that repeats failed reads on `inner` up to `retries` times.
+    pub fn new(inner: S, retries: u8) -> Self {
+        Self { inner, retries }
+    }
+
+    /// Get a mutable reference to the wrapped bus.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Return the wrapped bus.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn retry(&mut self, mut read: impl FnMut(&mut S) -> u16) -> u16 {
+        let mut val = read(&mut self.inner);
+        for _ in 0..self.retries {
+            if val != 0xffff {
+                break;
+            }
+            val = read(&mut self.inner);
+        }
+        val
+    }
+}
+
+impl<S: StationManagement> StationManagement for RetrySmi<S> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        self.retry(|bus| bus.smi_read(phy_addr, reg))
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.inner.smi_write(phy_addr, reg, val)
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.inner.set_bus_config(config)
+    }
+
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.retry(|bus| bus.smi_read_mmd(phy_addr, reg))
+    }
+
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.inner.smi_read_mmd_burst(phy_addr, start, out);
+        for _ in 0..self.retries {
+            if !out.contains(&0xffff) {
+                break;
+            }
+            self.inner.smi_read_mmd_burst(phy_addr, start, out);
+        }
+    }
+
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.inner.smi_write_mmd(phy_addr, reg, val)
+    }
+}
+
+/// [`StationManagementAsync`](super::StationManagementAsync) wrapper that retries reads returning
+/// `0xffff`.
+///
+/// This is the async counterpart of [`RetrySmi`], for buses that only implement
+/// [`StationManagementAsync`](super::StationManagementAsync), and retries the same way.
+pub struct RetrySmiAsync<S> {
+    inner: S,
+    retries: u8,
+}
+
+impl<S> RetrySmiAsync<S> {
+    /// Create a new `RetrySmiAsync` that repeats failed reads on `inner` up to `retries` times.
+    pub fn new(inner: S, retries: u8) -> Self {
+        Self { inner, retries }
+    }
+
+    /// Get a mutable reference to the wrapped bus.
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Return the wrapped bus.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: super::StationManagementAsync> super::StationManagementAsync for RetrySmiAsync<S> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let mut val = self.inner.smi_read(phy_addr, reg).await;
+        for _ in 0..self.retries {
+            if val != 0xffff {
+                break;
+            }
+            val = self.inner.smi_read(phy_addr, reg).await;
+        }
+        val
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.inner.smi_write(phy_addr, reg, val).await
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.inner.set_bus_config(config)
+    }
+
+    async fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        let mut val = self.inner.smi_read_mmd(phy_addr, reg).await;
+        for _ in 0..self.retries {
+            if val != 0xffff {
+                break;
+            }
+            val = self.inner.smi_read_mmd(phy_addr, reg).await;
+        }
+        val
+    }
+
+    async fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.inner.smi_read_mmd_burst(phy_addr, start, out).await;
+        for _ in 0..self.retries {
+            if !out.contains(&0xffff) {
+                break;
+            }
+            self.inner.smi_read_mmd_burst(phy_addr, start, out).await;
+        }
+    }
+
+    async fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.inner.smi_write_mmd(phy_addr, reg, val).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::eth::mock::{Action, RecordingMdioBus, RecordingMdioBusAsync};
+    use crate::eth::regs::C22;
+
+    // Bus whose first `failures` reads of BMSR at address 0 return all ones.
+    fn flaky_bus(failures: usize) -> RecordingMdioBus {
+        let mut bus = RecordingMdioBus::new();
+        bus.set(0, C22::BMSR, 0x7809);
+        bus.script(0, C22::BMSR, core::iter::repeat(0xffff).take(failures));
+        bus
+    }
+
+    #[test]
+    fn read_is_retried() {
+        let mut sm = RetrySmi::new(flaky_bus(2), 3);
+        assert_eq!(sm.smi_read(0, 1), 0x7809);
+        assert_eq!(sm.inner_mut().actions, [Action::Read(0, C22::BMSR); 3]);
+
+        sm.inner_mut().actions.clear();
+        sm.smi_write(0, 0, 0x8000);
+        assert_eq!(sm.into_inner().actions, [Action::Write(0, C22::BMCR, 0x8000)]);
+    }
+
+    #[test]
+    fn retries_are_bounded() {
+        let mut sm = RetrySmi::new(flaky_bus(2), 1);
+        assert_eq!(sm.smi_read(0, 1), 0xffff);
+        assert_eq!(sm.into_inner().actions.len(), 2);
+    }
+
+    #[test]
+    fn burst_is_retried_whole() {
+        let mut bus = RecordingMdioBus::new();
+        bus.set_mmd(0, C45::AN_EEE_ADV, 0x0006);
+        bus.set_mmd(0, C45::AN_EEE_LPABLE, 0x0002);
+        bus.script(0, C22::MMD_DATA, [0xffff]);
+        let mut sm = RetrySmi::new(bus, 3);
+        let mut out = [0; 2];
+        sm.smi_read_mmd_burst(0, C45::AN_EEE_ADV, &mut out);
+        assert_eq!(out, [0x0006, 0x0002]);
+
+        // The address setup is repeated along with the reads.
+        let actions = sm.into_inner().actions;
+        let reads = actions.iter().filter(|action| matches!(action, Action::Read(..)));
+        assert_eq!((reads.count(), actions.len()), (4, 10));
+    }
+
+    #[test]
+    fn async_read_is_retried() {
+        let mut sm = RetrySmiAsync::new(RecordingMdioBusAsync(flaky_bus(2)), 3);
+        let val = embassy_futures::block_on(crate::eth::StationManagementAsync::smi_read(&mut sm, 0, 1));
+        assert_eq!(val, 0x7809);
+        assert_eq!(sm.into_inner().0.actions, [Action::Read(0, C22::BMSR); 3]);
+    }
+}
@@ -0,0 +1,148 @@
+//! Realtek RTL8211F gigabit PHY
+
+use core::task::Context;
+
+use super::regs::C22;
+use super::{GenericPhy, InterfaceMode, InterfaceModeConfig, Phy, PhyError, StationManagement};
+
+// Page select register, mapping a page of vendor registers into 16..=31.
+const PAGSR: C22 = C22::vendor_specific::<0x1f>();
+
+// MII control register on page 0xd08.
+const PAGE_MIICR: u16 = 0xd08;
+const MIICR_TX_DELAY_REG: C22 = C22::vendor_specific::<0x11>();
+const MIICR_TX_DELAY: u16 = 1 << 8;
+const MIICR_RX_DELAY_REG: C22 = C22::vendor_specific::<0x15>();
+const MIICR_RX_DELAY: u16 = 1 << 3;
+
+/// Realtek RTL8211F gigabit PHY, connected through RGMII.
+///
+/// Resets, initializes and polls the link like [`GenericPhy`]. The RGMII clock delays, which are
+/// set by strap pins at power up and restored by a soft reset, can be overridden with
+/// [`InterfaceModeConfig`].
+pub struct Rtl8211f {
+    inner: GenericPhy,
+    delays: Option<(bool, bool)>,
+}
+
+impl Rtl8211f {
+    /// Construct the PHY at address `phy_addr`.
+    ///
+    /// # Panics
+    /// `phy_addr` must be in range `0..32`
+    pub fn new(phy_addr: u8) -> Self {
+        Self {
+            inner: GenericPhy::new(phy_addr),
+            delays: None,
+        }
+    }
+
+    /// Construct the PHY, probing all addresses from 0 to 31 during initialization.
+    ///
+    /// # Panics
+    /// Initialization panics if the PHY didn't respond on any address
+    pub fn new_auto() -> Self {
+        Self {
+            inner: GenericPhy::new_auto(),
+            delays: None,
+        }
+    }
+
+    fn write_delays<S: StationManagement>(&mut self, sm: &mut S, rx_delay: bool, tx_delay: bool) {
+        let phy_addr = self.inner.smi_addr();
+        let page = sm.smi_read(phy_addr, PAGSR.0);
+        sm.smi_write(phy_addr, PAGSR.0, PAGE_MIICR);
+        sm.smi_modify(phy_addr, MIICR_TX_DELAY_REG, |reg| match tx_delay {
+            true => reg | MIICR_TX_DELAY,
+            false => reg & !MIICR_TX_DELAY,
+        });
+        sm.smi_modify(phy_addr, MIICR_RX_DELAY_REG, |reg| match rx_delay {
+            true => reg | MIICR_RX_DELAY,
+            false => reg & !MIICR_RX_DELAY,
+        });
+        sm.smi_write(phy_addr, PAGSR.0, page);
+    }
+}
+
+impl Phy for Rtl8211f {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        self.inner.phy_reset(sm);
+    }
+
+    fn try_phy_reset<S: StationManagement>(&mut self, sm: &mut S) -> Result<(), PhyError> {
+        self.inner.try_phy_reset(sm)
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        // A reset restores the strapped delays
+        if let Some((rx_delay, tx_delay)) = self.delays {
+            self.write_delays(sm, rx_delay, tx_delay);
+        }
+
+        self.inner.phy_init(sm);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        self.inner.poll_link(sm, cx)
+    }
+}
+
+/// Only [`InterfaceMode::Rgmii`] is supported. The delays take effect immediately and are kept by
+/// later calls of [`Phy::phy_init`].
+impl InterfaceModeConfig for Rtl8211f {
+    fn set_interface_mode<S: StationManagement>(&mut self, sm: &mut S, mode: InterfaceMode) -> Result<(), PhyError> {
+        let InterfaceMode::Rgmii { rx_delay, tx_delay } = mode else {
+            return Err(PhyError::Unsupported);
+        };
+        self.delays = Some((rx_delay, tx_delay));
+        self.write_delays(sm, rx_delay, tx_delay);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::{Action, RecordingMdioBus};
+
+    #[test]
+    fn rgmii_delays() {
+        let mut sm = RecordingMdioBus::new();
+        sm.set(0, MIICR_TX_DELAY_REG, 0x0009 | MIICR_TX_DELAY);
+        let mut phy = Rtl8211f::new(0);
+
+        let mode = InterfaceMode::Rgmii {
+            rx_delay: true,
+            tx_delay: false,
+        };
+        assert_eq!(phy.set_interface_mode(&mut sm, mode), Ok(()));
+        assert_eq!(sm.get(0, MIICR_TX_DELAY_REG), 0x0009);
+        assert_eq!(sm.get(0, MIICR_RX_DELAY_REG), MIICR_RX_DELAY);
+        // The delay registers are accessed on the MIICR page, then the page is restored.
+        assert_eq!(
+            sm.actions,
+            [
+                Action::Read(0, PAGSR),
+                Action::Write(0, PAGSR, PAGE_MIICR),
+                Action::Read(0, MIICR_TX_DELAY_REG),
+                Action::Write(0, MIICR_TX_DELAY_REG, 0x0009),
+                Action::Read(0, MIICR_RX_DELAY_REG),
+                Action::Write(0, MIICR_RX_DELAY_REG, MIICR_RX_DELAY),
+                Action::Write(0, PAGSR, 0),
+            ]
+        );
+
+        // Re-initializing after a reset restores the selected delays.
+        sm.set(0, MIICR_RX_DELAY_REG, 0);
+        sm.actions.clear();
+        phy.phy_init(&mut sm);
+        assert_eq!(sm.get(0, MIICR_RX_DELAY_REG), MIICR_RX_DELAY);
+        assert_eq!(sm.actions[1], Action::Write(0, PAGSR, PAGE_MIICR));
+        assert_eq!(sm.get(0, PAGSR), 0);
+
+        assert_eq!(
+            phy.set_interface_mode(&mut sm, InterfaceMode::Rmii),
+            Err(PhyError::Unsupported)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Shared SMI bus
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::mutex::{Mutex as AsyncMutex, MutexGuard};
+
+use super::regs::{C22, C45};
+use super::{SmiBusConfig, StationManagement};
+
+/// Handle to a [`StationManagement`] bus shared between several PHY drivers.
+///
+/// Boards with multiple PHYs on one MDIO bus (e.g. a switch plus an uplink PHY) give each PHY driver
+/// its own `SharedSmi`. Every access locks the mutex for its whole duration, so transactions from
+/// different handles never interleave. This includes the frames of an indirect clause 45 access and
+/// the read and write of [`smi_modify`](StationManagement::smi_modify).
+///
+/// This shares a blocking bus only. Use [`SharedSmiAsync`] to share an async bus such as
+/// [`SpiMdio`](super::SpiMdio).
+pub struct SharedSmi<'a, M: RawMutex, S> {
+    bus: &'a Mutex<M, RefCell<S>>,
+}
+
+impl<'a, M: RawMutex, S> SharedSmi<'a, M, S> {
+    /// Create a new `SharedSmi`.
+    pub fn new(bus: &'a Mutex<M, RefCell<S>>) -> Self {
+        Self { bus }
+    }
+}
+
+impl<M: RawMutex, S: StationManagement> StationManagement for SharedSmi<'_, M, S> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        self.bus.lock(|bus| bus.borrow_mut().smi_read(phy_addr, reg))
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.bus.lock(|bus| bus.borrow_mut().smi_write(phy_addr, reg, val))
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.bus.lock(|bus| bus.borrow_mut().set_bus_config(config))
+    }
+
+    fn smi_read_many(&mut self, phy_addr: u8, regs: &[C22], out: &mut [u16]) {
+        self.bus.lock(|bus| bus.borrow_mut().smi_read_many(phy_addr, regs, out))
+    }
+
+    fn smi_modify(&mut self, phy_addr: u8, reg: C22, f: impl FnOnce(u16) -> u16) -> u16 {
+        self.bus.lock(|bus| bus.borrow_mut().smi_modify(phy_addr, reg, f))
+    }
+
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.bus.lock(|bus| bus.borrow_mut().smi_read_mmd(phy_addr, reg))
+    }
+
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.bus
+            .lock(|bus| bus.borrow_mut().smi_read_mmd_burst(phy_addr, start, out))
+    }
+
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.bus.lock(|bus| bus.borrow_mut().smi_write_mmd(phy_addr, reg, val))
+    }
+
+    fn smi_read_ext(&mut self, phy_addr: u8, regnum: u16) -> u16 {
+        self.bus.lock(|bus| bus.borrow_mut().smi_read_ext(phy_addr, regnum))
+    }
+
+    fn smi_write_ext(&mut self, phy_addr: u8, regnum: u16, val: u16) {
+        self.bus
+            .lock(|bus| bus.borrow_mut().smi_write_ext(phy_addr, regnum, val))
+    }
+}
+
+/// Handle to a [`StationManagementAsync`](super::StationManagementAsync) bus shared between several
+/// PHY drivers.
+///
+/// This is the async counterpart of [`SharedSmi`], locking an async mutex instead, so a task waiting
+/// for the bus doesn't block others. Every access holds the lock for its whole duration, including
+/// the frames of an indirect clause 45 access and the read and write of
+/// [`smi_modify`](super::StationManagementAsync::smi_modify).
+///
+/// [`set_bus_config`](super::StationManagementAsync::set_bus_config) can't wait for the lock, so the
+/// configuration is applied to the bus at the start of the next access through this handle.
+pub struct SharedSmiAsync<'a, M: RawMutex, S> {
+    bus: &'a AsyncMutex<M, S>,
+    config: Option<SmiBusConfig>,
+}
+
+impl<'a, M: RawMutex, S: super::StationManagementAsync> SharedSmiAsync<'a, M, S> {
+    /// Create a new `SharedSmiAsync`.
+    pub fn new(bus: &'a AsyncMutex<M, S>) -> Self {
+        Self { bus, config: None }
+    }
+
+    async fn lock(&mut self) -> MutexGuard<'a, M, S> {
+        let mut bus = self.bus.lock().await;
+        if let Some(config) = self.config.take() {
+            bus.set_bus_config(config);
+        }
+        bus
+    }
+}
+
+impl<M: RawMutex, S: super::StationManagementAsync> super::StationManagementAsync for SharedSmiAsync<'_, M, S> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        self.lock().await.smi_read(phy_addr, reg).await
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        self.lock().await.smi_write(phy_addr, reg, val).await
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.config = Some(config);
+    }
+
+    async fn smi_read_many(&mut self, phy_addr: u8, regs: &[C22], out: &mut [u16]) {
+        self.lock().await.smi_read_many(phy_addr, regs, out).await
+    }
+
+    async fn smi_modify(&mut self, phy_addr: u8, reg: C22, f: impl FnOnce(u16) -> u16) -> u16 {
+        self.lock().await.smi_modify(phy_addr, reg, f).await
+    }
+
+    async fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        self.lock().await.smi_read_mmd(phy_addr, reg).await
+    }
+
+    async fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.lock().await.smi_read_mmd_burst(phy_addr, start, out).await
+    }
+
+    async fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        self.lock().await.smi_write_mmd(phy_addr, reg, val).await
+    }
+
+    async fn smi_read_ext(&mut self, phy_addr: u8, regnum: u16) -> u16 {
+        self.lock().await.smi_read_ext(phy_addr, regnum).await
+    }
+
+    async fn smi_write_ext(&mut self, phy_addr: u8, regnum: u16, val: u16) {
+        self.lock().await.smi_write_ext(phy_addr, regnum, val).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::eth::mock::{Action, RecordingMdioBus, RecordingMdioBusAsync};
+    use crate::eth::regs::{Mmd, Reg13Op};
+    use crate::eth::C45Direct;
+
+    static LOCKS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Mutex that counts how often it was locked.
+    struct CountingRawMutex;
+
+    unsafe impl RawMutex for CountingRawMutex {
+        const INIT: Self = Self;
+
+        fn lock<R>(&self, f: impl FnOnce() -> R) -> R {
+            LOCKS.fetch_add(1, Ordering::Relaxed);
+            f()
+        }
+    }
+
+    #[test]
+    fn interleaved_handles() {
+        let mut mock = RecordingMdioBus::new();
+        mock.set(1, C22::BMCR, 0x1140);
+        mock.set(2, C22::BMSR, 0x796d);
+        let bus = Mutex::<NoopRawMutex, _>::new(RefCell::new(mock));
+        let mut switch = SharedSmi::new(&bus);
+        let mut uplink = SharedSmi::new(&bus);
+
+        switch.smi_write(1, 4, 0x0de1);
+        assert_eq!(uplink.smi_read(2, 1), 0x796d);
+        assert_eq!(switch.smi_read(1, 0), 0x1140);
+        uplink.smi_write(2, 4, 0x01e1);
+
+        let bus = bus.into_inner().into_inner();
+        assert_eq!(
+            bus.actions,
+            [
+                Action::Write(1, C22::ADVERTISE, 0x0de1),
+                Action::Read(2, C22::BMSR),
+                Action::Read(1, C22::BMCR),
+                Action::Write(2, C22::ADVERTISE, 0x01e1),
+            ]
+        );
+    }
+
+    #[test]
+    fn mmd_access_locks_once() {
+        let mut mock = RecordingMdioBus::new();
+        mock.set(2, C22::BMCR, 0x1000);
+        let bus = Mutex::<CountingRawMutex, _>::new(RefCell::new(mock));
+        let mut switch = SharedSmi::new(&bus);
+        let mut uplink = SharedSmi::new(&bus);
+
+        switch.smi_read_mmd(1, C45::new(Mmd::PCS, 0x14));
+        uplink.smi_write_mmd(2, C45::new(Mmd::AN, 0x3c), 0x0006);
+        uplink.smi_modify(2, C22::BMCR, |v| v | 0x0200);
+
+        assert_eq!(LOCKS.load(Ordering::Relaxed), 3);
+        let bus = bus.into_inner().into_inner();
+        assert_eq!(
+            bus.actions,
+            [
+                Action::Write(1, C22::MMD_CONTROL, Reg13Op::Address.control(Mmd::PCS)),
+                Action::Write(1, C22::MMD_DATA, 0x14),
+                Action::Write(1, C22::MMD_CONTROL, Reg13Op::Data.control(Mmd::PCS)),
+                Action::Read(1, C22::MMD_DATA),
+                Action::Write(2, C22::MMD_CONTROL, Reg13Op::Address.control(Mmd::AN)),
+                Action::Write(2, C22::MMD_DATA, 0x3c),
+                Action::Write(2, C22::MMD_CONTROL, Reg13Op::Data.control(Mmd::AN)),
+                Action::Write(2, C22::MMD_DATA, 0x0006),
+                Action::Read(2, C22::BMCR),
+                Action::Write(2, C22::BMCR, 0x1200),
+            ]
+        );
+    }
+
+    #[test]
+    fn c45_direct_support_is_forwarded() {
+        assert!(<SharedSmi<NoopRawMutex, C45Direct<RecordingMdioBus>> as StationManagement>::SUPPORTS_C45_DIRECT);
+        assert!(!<SharedSmi<NoopRawMutex, RecordingMdioBus> as StationManagement>::SUPPORTS_C45_DIRECT);
+    }
+
+    #[test]
+    fn async_mmd_access_is_not_interleaved() {
+        use crate::eth::StationManagementAsync;
+
+        let bus = AsyncMutex::<NoopRawMutex, _>::new(RecordingMdioBusAsync::default());
+        let mut switch = SharedSmiAsync::new(&bus);
+        let mut uplink = SharedSmiAsync::new(&bus);
+
+        // Every access to the bus yields, so both handles contend for the lock.
+        embassy_futures::block_on(embassy_futures::join::join(
+            switch.smi_write_mmd(1, C45::new(Mmd::AN, 0x3c), 0x0006),
+            uplink.smi_read(2, C22::BMSR.0),
+        ));
+
+        let bus = bus.into_inner().0;
+        assert_eq!(
+            bus.actions,
+            [
+                Action::Write(1, C22::MMD_CONTROL, Reg13Op::Address.control(Mmd::AN)),
+                Action::Write(1, C22::MMD_DATA, 0x3c),
+                Action::Write(1, C22::MMD_CONTROL, Reg13Op::Data.control(Mmd::AN)),
+                Action::Write(1, C22::MMD_DATA, 0x0006),
+                Action::Read(2, C22::BMSR),
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! SMI over an SPI register access protocol
+
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+use super::{StationManagement, StationManagementAsync};
+
+/// Command layout of an SPI register access protocol, used by [`SpiMdio`] and [`BlockingSpiMdio`].
+///
+/// A register access is one SPI transaction: a command header, followed by the 16 bit register value
+/// written or read most significant byte first.
+pub trait SpiMdioProtocol {
+    /// Encode the command header of a read of `reg` at `phy_addr` into `buf`, returning its length.
+    fn read_header(&self, phy_addr: u8, reg: u8, buf: &mut [u8; 4]) -> usize;
+    /// Encode the command header of a write of `reg` at `phy_addr` into `buf`, returning its length.
+    fn write_header(&self, phy_addr: u8, reg: u8, buf: &mut [u8; 4]) -> usize;
+}
+
+/// Protocol with a 3 byte header: an opcode, the PHY address and the register address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OpcodeHeader {
+    /// Opcode of a register read.
+    pub read: u8,
+    /// Opcode of a register write.
+    pub write: u8,
+}
+
+impl SpiMdioProtocol for OpcodeHeader {
+    fn read_header(&self, phy_addr: u8, reg: u8, buf: &mut [u8; 4]) -> usize {
+        buf[..3].copy_from_slice(&[self.read, phy_addr, reg]);
+        3
+    }
+
+    fn write_header(&self, phy_addr: u8, reg: u8, buf: &mut [u8; 4]) -> usize {
+        buf[..3].copy_from_slice(&[self.write, phy_addr, reg]);
+        3
+    }
+}
+
+/// [`StationManagementAsync`] for PHYs and switches whose registers are accessed over SPI.
+///
+/// Translates every register access into one transaction on `spi`, so the [`PhyAsync`](super::PhyAsync)
+/// drivers work unchanged. The [`Phy`](super::Phy) drivers need a blocking bus, use
+/// [`BlockingSpiMdio`] for them. Use an `embassy_embedded_hal::shared_bus` SPI device to share the
+/// bus with other chips.
+///
+/// [`StationManagementAsync`] can't report errors. A failed read returns `0xffff`, like an address
+/// without a PHY, and a failed write is dropped; the error is kept for [`SpiMdio::take_error`].
+pub struct SpiMdio<SPI: SpiDevice, P> {
+    spi: SPI,
+    protocol: P,
+    error: Option<SPI::Error>,
+}
+
+impl<SPI: SpiDevice, P: SpiMdioProtocol> SpiMdio<SPI, P> {
+    /// Create a new `SpiMdio`.
+    pub fn new(spi: SPI, protocol: P) -> Self {
+        Self {
+            spi,
+            protocol,
+            error: None,
+        }
+    }
+
+    /// Take the error of the last failed register access, if any.
+    pub fn take_error(&mut self) -> Option<SPI::Error> {
+        self.error.take()
+    }
+
+    /// Return the SPI device.
+    pub fn into_inner(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<SPI: SpiDevice, P: SpiMdioProtocol> StationManagementAsync for SpiMdio<SPI, P> {
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let mut header = [0; 4];
+        let len = self.protocol.read_header(phy_addr, reg, &mut header);
+        let mut val = [0; 2];
+        let res = self
+            .spi
+            .transaction(&mut [Operation::Write(&header[..len]), Operation::Read(&mut val)])
+            .await;
+        match res {
+            Ok(()) => u16::from_be_bytes(val),
+            Err(e) => {
+                self.error = Some(e);
+                0xffff
+            }
+        }
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        let mut header = [0; 4];
+        let len = self.protocol.write_header(phy_addr, reg, &mut header);
+        let res = self
+            .spi
+            .transaction(&mut [Operation::Write(&header[..len]), Operation::Write(&val.to_be_bytes())])
+            .await;
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// [`StationManagement`] for PHYs and switches whose registers are accessed over a blocking SPI
+/// device.
+///
+/// This is the blocking counterpart of [`SpiMdio`], with the same transactions, so it works with the
+/// [`Phy`](super::Phy) drivers too. Like every [`StationManagement`], it is also a
+/// [`StationManagementAsync`].
+///
+/// [`StationManagement`] can't report errors. A failed read returns `0xffff`, like an address
+/// without a PHY, and a failed write is dropped; the error is kept for
+/// [`BlockingSpiMdio::take_error`].
+pub struct BlockingSpiMdio<SPI: embedded_hal_1::spi::SpiDevice, P> {
+    spi: SPI,
+    protocol: P,
+    error: Option<SPI::Error>,
+}
+
+impl<SPI: embedded_hal_1::spi::SpiDevice, P: SpiMdioProtocol> BlockingSpiMdio<SPI, P> {
+    /// Create a new `BlockingSpiMdio`.
+    pub fn new(spi: SPI, protocol: P) -> Self {
+        Self {
+            spi,
+            protocol,
+            error: None,
+        }
+    }
+
+    /// Take the error of the last failed register access, if any.
+    pub fn take_error(&mut self) -> Option<SPI::Error> {
+        self.error.take()
+    }
+
+    /// Return the SPI device.
+    pub fn into_inner(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<SPI: embedded_hal_1::spi::SpiDevice, P: SpiMdioProtocol> StationManagement for BlockingSpiMdio<SPI, P> {
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let mut header = [0; 4];
+        let len = self.protocol.read_header(phy_addr, reg, &mut header);
+        let mut val = [0; 2];
+        let res = self
+            .spi
+            .transaction(&mut [Operation::Write(&header[..len]), Operation::Read(&mut val)]);
+        match res {
+            Ok(()) => u16::from_be_bytes(val),
+            Err(e) => {
+                self.error = Some(e);
+                0xffff
+            }
+        }
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        let mut header = [0; 4];
+        let len = self.protocol.write_header(phy_addr, reg, &mut header);
+        let res = self
+            .spi
+            .transaction(&mut [Operation::Write(&header[..len]), Operation::Write(&val.to_be_bytes())]);
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal_async::spi::{ErrorKind, ErrorType};
+
+    use super::*;
+
+    // Chip with 32 registers per address, answering the OpcodeHeader protocol with opcodes 0x0b/0x0a.
+    struct MockSpiDevice {
+        regs: [[u16; 32]; 2],
+        fail: bool,
+    }
+
+    impl ErrorType for MockSpiDevice {
+        type Error = ErrorKind;
+    }
+
+    impl MockSpiDevice {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            if self.fail {
+                return Err(ErrorKind::Other);
+            }
+            let [Operation::Write(&[op, phy_addr, reg]), data] = operations else {
+                panic!("unexpected transaction");
+            };
+            let reg = &mut self.regs[phy_addr as usize][reg as usize];
+            match (op, data) {
+                (0x0b, Operation::Read(buf)) => buf.copy_from_slice(&reg.to_be_bytes()),
+                (0x0a, Operation::Write(buf)) => *reg = u16::from_be_bytes([buf[0], buf[1]]),
+                _ => panic!("unexpected transaction"),
+            }
+            Ok(())
+        }
+    }
+
+    impl SpiDevice for MockSpiDevice {
+        async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            MockSpiDevice::transaction(self, operations)
+        }
+    }
+
+    impl embedded_hal_1::spi::SpiDevice for MockSpiDevice {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            MockSpiDevice::transaction(self, operations)
+        }
+    }
+
+    #[test]
+    fn register_access() {
+        let spi = MockSpiDevice {
+            regs: [[0; 32]; 2],
+            fail: false,
+        };
+        let mut sm = SpiMdio::new(
+            spi,
+            OpcodeHeader {
+                read: 0x0b,
+                write: 0x0a,
+            },
+        );
+
+        embassy_futures::block_on(async {
+            sm.smi_write(1, 4, 0x01e1).await;
+            assert_eq!(sm.smi_read(1, 4).await, 0x01e1);
+            assert_eq!(sm.smi_read(0, 4).await, 0);
+        });
+        assert_eq!(sm.take_error(), None);
+
+        let mut spi = sm.into_inner();
+        assert_eq!(spi.regs[1][4], 0x01e1);
+
+        spi.fail = true;
+        let mut sm = SpiMdio::new(
+            spi,
+            OpcodeHeader {
+                read: 0x0b,
+                write: 0x0a,
+            },
+        );
+        assert_eq!(embassy_futures::block_on(sm.smi_read(1, 4)), 0xffff);
+        assert_eq!(sm.take_error(), Some(ErrorKind::Other));
+        assert_eq!(sm.take_error(), None);
+    }
+
+    #[test]
+    fn blocking_register_access() {
+        let spi = MockSpiDevice {
+            regs: [[0; 32]; 2],
+            fail: false,
+        };
+        let mut sm = BlockingSpiMdio::new(
+            spi,
+            OpcodeHeader {
+                read: 0x0b,
+                write: 0x0a,
+            },
+        );
+
+        StationManagement::smi_write(&mut sm, 1, 4, 0x01e1);
+        assert_eq!(StationManagement::smi_read(&mut sm, 1, 4), 0x01e1);
+        assert_eq!(sm.take_error(), None);
+
+        let mut spi = sm.into_inner();
+        assert_eq!(spi.regs[1][4], 0x01e1);
+
+        spi.fail = true;
+        let mut sm = BlockingSpiMdio::new(
+            spi,
+            OpcodeHeader {
+                read: 0x0b,
+                write: 0x0a,
+            },
+        );
+        assert_eq!(StationManagement::smi_read(&mut sm, 1, 4), 0xffff);
+        assert_eq!(sm.take_error(), Some(ErrorKind::Other));
+    }
+}
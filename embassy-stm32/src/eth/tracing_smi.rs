@@ -0,0 +1,309 @@
+//! SMI bus with a tracing hook
+
+use super::regs::C45;
+use super::{SmiBusConfig, StationManagement};
+
+/// SMI register access reported by [`TracingSmi`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SmiEvent {
+    /// A register was read, returning `val`.
+    Read {
+        /// PHY address.
+        phy_addr: u8,
+        /// Clause 22 register address.
+        reg: u8,
+        /// Value read.
+        val: u16,
+    },
+    /// A register is about to be written with `val`.
+    Write {
+        /// PHY address.
+        phy_addr: u8,
+        /// Clause 22 register address.
+        reg: u8,
+        /// Value written.
+        val: u16,
+    },
+    /// A clause 45 register was read, returning `val`.
+    ReadMmd {
+        /// PHY address.
+        phy_addr: u8,
+        /// Clause 45 register address.
+        reg: C45,
+        /// Value read.
+        val: u16,
+    },
+    /// A clause 45 register is about to be written with `val`.
+    WriteMmd {
+        /// PHY address.
+        phy_addr: u8,
+        /// Clause 45 register address.
+        reg: C45,
+        /// Value written.
+        val: u16,
+    },
+}
+
+/// [`StationManagement`] wrapper that reports every register access to a closure.
+///
+/// Reads are reported after they complete, writes before they are issued. Clause 45 accesses go
+/// through the wrapped bus's own MMD accessors, so direct clause 45 access keeps working, and are
+/// reported as [`SmiEvent::ReadMmd`] and [`SmiEvent::WriteMmd`] rather than as the frames they take
+/// on the bus. A burst read is reported as one event per register. With optimizations enabled, an
+/// empty closure compiles down to the plain bus accesses.
+///
+/// This wraps a blocking bus only. Use [`TracingSmiAsync`] for async buses such as
+/// [`SpiMdio`](super::SpiMdio).
+pub struct TracingSmi<S, F: FnMut(SmiEvent)> {
+    inner: S,
+    on_event: F,
+}
+
+impl<S, F: FnMut(SmiEvent)> TracingSmi<S, F> {
+    /// Create a new `TracingSmi` calling `on_event` for every access to `inner`.
+    pub fn new(inner: S, on_event: F) -> Self {
+        Self { inner, on_event }
+    }
+
+    /// Return the wrapped bus.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: StationManagement, F: FnMut(SmiEvent)> StationManagement for TracingSmi<S, F> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let val = self.inner.smi_read(phy_addr, reg);
+        (self.on_event)(SmiEvent::Read { phy_addr, reg, val });
+        val
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        (self.on_event)(SmiEvent::Write { phy_addr, reg, val });
+        self.inner.smi_write(phy_addr, reg, val)
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.inner.set_bus_config(config)
+    }
+
+    fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        let val = self.inner.smi_read_mmd(phy_addr, reg);
+        (self.on_event)(SmiEvent::ReadMmd { phy_addr, reg, val });
+        val
+    }
+
+    fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.inner.smi_read_mmd_burst(phy_addr, start, out);
+        // The address auto-increments and wraps around like in the PHY
+        for (i, &val) in out.iter().enumerate() {
+            let reg = C45::new(start.devad, start.regnum.wrapping_add(i as u16));
+            (self.on_event)(SmiEvent::ReadMmd { phy_addr, reg, val });
+        }
+    }
+
+    fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        (self.on_event)(SmiEvent::WriteMmd { phy_addr, reg, val });
+        self.inner.smi_write_mmd(phy_addr, reg, val)
+    }
+}
+
+/// [`StationManagementAsync`](super::StationManagementAsync) wrapper that reports every register
+/// access to a closure.
+///
+/// This is the async counterpart of [`TracingSmi`], for buses that only implement
+/// [`StationManagementAsync`](super::StationManagementAsync), and reports the same events.
+pub struct TracingSmiAsync<S, F: FnMut(SmiEvent)> {
+    inner: S,
+    on_event: F,
+}
+
+impl<S, F: FnMut(SmiEvent)> TracingSmiAsync<S, F> {
+    /// Create a new `TracingSmiAsync` calling `on_event` for every access to `inner`.
+    pub fn new(inner: S, on_event: F) -> Self {
+        Self { inner, on_event }
+    }
+
+    /// Return the wrapped bus.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: super::StationManagementAsync, F: FnMut(SmiEvent)> super::StationManagementAsync for TracingSmiAsync<S, F> {
+    const SUPPORTS_C45_DIRECT: bool = S::SUPPORTS_C45_DIRECT;
+
+    async fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let val = self.inner.smi_read(phy_addr, reg).await;
+        (self.on_event)(SmiEvent::Read { phy_addr, reg, val });
+        val
+    }
+
+    async fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        (self.on_event)(SmiEvent::Write { phy_addr, reg, val });
+        self.inner.smi_write(phy_addr, reg, val).await
+    }
+
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        self.inner.set_bus_config(config)
+    }
+
+    async fn smi_read_mmd(&mut self, phy_addr: u8, reg: C45) -> u16 {
+        let val = self.inner.smi_read_mmd(phy_addr, reg).await;
+        (self.on_event)(SmiEvent::ReadMmd { phy_addr, reg, val });
+        val
+    }
+
+    async fn smi_read_mmd_burst(&mut self, phy_addr: u8, start: C45, out: &mut [u16]) {
+        self.inner.smi_read_mmd_burst(phy_addr, start, out).await;
+        // The address auto-increments and wraps around like in the PHY
+        for (i, &val) in out.iter().enumerate() {
+            let reg = C45::new(start.devad, start.regnum.wrapping_add(i as u16));
+            (self.on_event)(SmiEvent::ReadMmd { phy_addr, reg, val });
+        }
+    }
+
+    async fn smi_write_mmd(&mut self, phy_addr: u8, reg: C45, val: u16) {
+        (self.on_event)(SmiEvent::WriteMmd { phy_addr, reg, val });
+        self.inner.smi_write_mmd(phy_addr, reg, val).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::mock::{Action, RecordingMdioBus, RecordingMdioBusAsync};
+    use crate::eth::regs::{Mmd, C22};
+    use crate::eth::C45Direct;
+
+    #[test]
+    fn read_modify_events() {
+        let mut events = Vec::new();
+        let mut bus = RecordingMdioBus::new();
+        bus.set(2, C22::BMCR, 0x1000);
+        let mut sm = TracingSmi::new(bus, |event| events.push(event));
+
+        sm.smi_modify(2, C22::BMCR, |bmcr| bmcr | 0x0200);
+        assert_eq!(sm.into_inner().get(2, C22::BMCR), 0x1200);
+
+        assert_eq!(
+            events,
+            [
+                SmiEvent::Read {
+                    phy_addr: 2,
+                    reg: 0,
+                    val: 0x1000
+                },
+                SmiEvent::Write {
+                    phy_addr: 2,
+                    reg: 0,
+                    val: 0x1200
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mmd_events() {
+        let mut events = Vec::new();
+        let mut bus = RecordingMdioBus::new();
+        bus.set_mmd(2, C45::AN_EEE_LPABLE, 0x0002);
+        let mut sm = TracingSmi::new(C45Direct(bus), |event| events.push(event));
+
+        sm.smi_write_mmd(2, C45::AN_EEE_ADV, 0x0006);
+        let mut out = [0; 2];
+        sm.smi_read_mmd_burst(2, C45::AN_EEE_ADV, &mut out);
+        assert_eq!(out, [0x0006, 0x0002]);
+        // The MMD accesses reach the wrapped bus instead of going through clause 22 frames.
+        let bus = sm.into_inner().0;
+        assert!(bus
+            .actions
+            .iter()
+            .all(|a| matches!(a, Action::ReadMmd(..) | Action::WriteMmd(..))));
+        assert!(<TracingSmi<C45Direct<RecordingMdioBus>, fn(SmiEvent)> as StationManagement>::SUPPORTS_C45_DIRECT);
+
+        assert_eq!(
+            events,
+            [
+                SmiEvent::WriteMmd {
+                    phy_addr: 2,
+                    reg: C45::AN_EEE_ADV,
+                    val: 0x0006
+                },
+                SmiEvent::ReadMmd {
+                    phy_addr: 2,
+                    reg: C45::AN_EEE_ADV,
+                    val: 0x0006
+                },
+                SmiEvent::ReadMmd {
+                    phy_addr: 2,
+                    reg: C45::AN_EEE_LPABLE,
+                    val: 0x0002
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mmd_burst_wraps_around() {
+        let mut events = Vec::new();
+        let mut bus = RecordingMdioBus::new();
+        bus.set_mmd(1, C45::new(Mmd::VEND1, 0xffff), 0x1234);
+        bus.set_mmd(1, C45::new(Mmd::VEND1, 0x0000), 0x5678);
+        let mut sm = TracingSmi::new(bus, |event| events.push(event));
+
+        let mut out = [0; 2];
+        sm.smi_read_mmd_burst(1, C45::new(Mmd::VEND1, 0xffff), &mut out);
+        assert_eq!(out, [0x1234, 0x5678]);
+        assert_eq!(
+            events,
+            [
+                SmiEvent::ReadMmd {
+                    phy_addr: 1,
+                    reg: C45::new(Mmd::VEND1, 0xffff),
+                    val: 0x1234
+                },
+                SmiEvent::ReadMmd {
+                    phy_addr: 1,
+                    reg: C45::new(Mmd::VEND1, 0x0000),
+                    val: 0x5678
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn async_events() {
+        let mut events = Vec::new();
+        let mut bus = RecordingMdioBusAsync::default();
+        bus.0.set(2, C22::BMCR, 0x1000);
+        let mut sm = TracingSmiAsync::new(bus, |event| events.push(event));
+
+        embassy_futures::block_on(crate::eth::StationManagementAsync::smi_modify(
+            &mut sm,
+            2,
+            C22::BMCR,
+            |bmcr| bmcr | 0x0200,
+        ));
+        assert_eq!(sm.into_inner().0.get(2, C22::BMCR), 0x1200);
+
+        assert_eq!(
+            events,
+            [
+                SmiEvent::Read {
+                    phy_addr: 2,
+                    reg: 0,
+                    val: 0x1000
+                },
+                SmiEvent::Write {
+                    phy_addr: 2,
+                    reg: 0,
+                    val: 0x1200
+                },
+            ]
+        );
+    }
+}
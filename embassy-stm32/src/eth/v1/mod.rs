@@ -23,6 +23,7 @@ use crate::pac::AFIO;
 use crate::pac::SYSCFG;
 use crate::pac::{ETH, RCC};
 use crate::rcc::SealedRccPeripheral;
+use crate::time::Hertz;
 
 /// Interrupt handler.
 pub struct InterruptHandler {}
@@ -249,6 +250,7 @@ impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
             station_management: EthernetStationManagement {
                 peri: PhantomData,
                 clock_range: clock_range,
+                hclk,
             },
             mac_addr,
             tx: TDesRing::new(&mut queue.tx_desc, &mut queue.tx_buf),
@@ -371,15 +373,41 @@ impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
 
         Self::new_inner(queue, peri, irq, pins, phy, mac_addr)
     }
+
+    /// Program the MAC speed and duplex, e.g. after the PHY reported a new link status.
+    pub fn set_mac_config(&mut self, config: MacSpeedDuplex) {
+        T::regs().ethernet_mac().maccr().modify(|w| {
+            w.set_fes(if config.fast_ethernet { Fes::FES100 } else { Fes::FES10 });
+            w.set_dm(if config.full_duplex {
+                Dm::FULL_DUPLEX
+            } else {
+                Dm::HALF_DUPLEX
+            });
+        });
+    }
 }
 
 /// Ethernet station management interface.
 pub(crate) struct EthernetStationManagement<T: Instance> {
     peri: PhantomData<T>,
     clock_range: Cr,
+    hclk: Hertz,
 }
 
 impl<T: Instance> StationManagement for EthernetStationManagement<T> {
+    // The MAC always sends the preamble.
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        const RANGES: [(u32, Cr); 5] = [
+            (16, Cr::CR_20_35),
+            (26, Cr::CR_35_60),
+            (42, Cr::CR_60_100),
+            (62, Cr::CR_100_150),
+            (102, Cr::CR_150_168),
+        ];
+        let i = mdc_divider_index(self.hclk.0, config.mdc_hz, &RANGES.map(|(div, _)| div));
+        self.clock_range = RANGES[i].1;
+    }
+
     fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
         let mac = T::regs().ethernet_mac();
 
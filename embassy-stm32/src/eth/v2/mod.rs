@@ -13,6 +13,7 @@ use crate::interrupt;
 use crate::interrupt::InterruptExt;
 use crate::pac::ETH;
 use crate::rcc::SealedRccPeripheral;
+use crate::time::Hertz;
 
 /// Interrupt handler.
 pub struct InterruptHandler {}
@@ -260,6 +261,7 @@ impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
             station_management: EthernetStationManagement {
                 peri: PhantomData,
                 clock_range: clock_range,
+                hclk,
             },
             mac_addr,
         };
@@ -294,15 +296,31 @@ impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
 
         this
     }
+
+    /// Program the MAC speed and duplex, e.g. after the PHY reported a new link status.
+    pub fn set_mac_config(&mut self, config: MacSpeedDuplex) {
+        T::regs().ethernet_mac().maccr().modify(|w| {
+            w.set_fes(config.fast_ethernet);
+            w.set_dm(config.full_duplex);
+        });
+    }
 }
 
 /// Ethernet SMI driver.
 pub struct EthernetStationManagement<T: Instance> {
     peri: PhantomData<T>,
     clock_range: u8,
+    hclk: Hertz,
 }
 
 impl<T: Instance> StationManagement for EthernetStationManagement<T> {
+    // Preamble suppression is not supported.
+    fn set_bus_config(&mut self, config: SmiBusConfig) {
+        const RANGES: [(u32, u8); 6] = [(16, 2), (26, 3), (42, 0), (62, 1), (102, 4), (124, 5)];
+        let i = mdc_divider_index(self.hclk.0, config.mdc_hz, &RANGES.map(|(div, _)| div));
+        self.clock_range = RANGES[i].1;
+    }
+
     fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
         let mac = T::regs().ethernet_mac();
 
@@ -332,6 +350,45 @@ impl<T: Instance> StationManagement for EthernetStationManagement<T> {
     }
 }
 
+impl<T: Instance> StationManagementC45 for EthernetStationManagement<T> {
+    fn c45_read(&mut self, phy_addr: u8, devad: Mmd, regnum: u16) -> u16 {
+        let mac = T::regs().ethernet_mac();
+
+        mac.macmdiodr().write(|w| w.set_ra(regnum));
+        mac.macmdioar().modify(|w| {
+            w.set_pa(phy_addr);
+            w.set_rda(devad.0);
+            w.set_c45e(true);
+            w.set_goc(0b11); // read
+            w.set_cr(self.clock_range);
+            w.set_mb(true);
+        });
+        while mac.macmdioar().read().mb() {}
+        let val = mac.macmdiodr().read().md();
+        mac.macmdioar().modify(|w| w.set_c45e(false));
+        val
+    }
+
+    fn c45_write(&mut self, phy_addr: u8, devad: Mmd, regnum: u16, val: u16) {
+        let mac = T::regs().ethernet_mac();
+
+        mac.macmdiodr().write(|w| {
+            w.set_ra(regnum);
+            w.set_md(val);
+        });
+        mac.macmdioar().modify(|w| {
+            w.set_pa(phy_addr);
+            w.set_rda(devad.0);
+            w.set_c45e(true);
+            w.set_goc(0b01); // write
+            w.set_cr(self.clock_range);
+            w.set_mb(true);
+        });
+        while mac.macmdioar().read().mb() {}
+        mac.macmdioar().modify(|w| w.set_c45e(false));
+    }
+}
+
 impl<'d, T: Instance, P: Phy> Drop for Ethernet<'d, T, P> {
     fn drop(&mut self) {
         let dma = T::regs().ethernet_dma();
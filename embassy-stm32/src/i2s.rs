@@ -1,9 +1,25 @@
 //! Inter-IC Sound (I2S)
+//!
+//! The SPI/I2S peripheral only supports two-channel (stereo) framing on all STM32 families,
+//! including the spi_v3+ variants. It has no TDM slot configuration. For codecs that need
+//! TDM with more than two time slots, use the [SAI](crate::sai) peripheral instead, which
+//! exposes `slot_count` and `slot_enable` in its [`Config`](crate::sai::Config).
+//!
+//! The peripheral has no PDM mode either, on any family, so this driver has no hardware PDM
+//! configuration to offer. Hardware PDM filtering lives in other peripherals (DFSDM, MDF, or the
+//! PDM interface of the SAI on some families) and is out of scope for I2S. A PDM microphone can
+//! still be captured by running the I2S as a receiving master with the bit clock as PDM clock:
+//! every word read is 16 or 32 consecutive PDM bits, MSB first, which
+//! [`PdmDecimator::process_words`] converts to PCM.
+
+use core::future::poll_fn;
+use core::ptr;
+use core::task::{Poll, Waker};
 
 use embassy_futures::join::join;
 use stm32_metapac::spi::vals;
 
-use crate::dma::{ringbuffer, ChannelAndRequest, ReadableRingBuffer, TransferOptions, WritableRingBuffer};
+use crate::dma::{ringbuffer, word, ChannelAndRequest, ReadableRingBuffer, TransferOptions, WritableRingBuffer};
 use crate::gpio::{AfType, AnyPin, OutputType, SealedPin, Speed};
 use crate::mode::Async;
 use crate::spi::{Config as SpiConfig, RegsExt as _, *};
@@ -47,27 +63,112 @@ pub enum Standard {
     PcmShortSync,
 }
 
-/// SAI error
+/// I2S error
 #[derive(Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
-    /// `write` called on a SAI in receive mode.
+    /// `write` called on an I2S in receive mode.
     NotATransmitter,
-    /// `read` called on a SAI in transmit mode.
+    /// `read` called on an I2S in transmit mode.
     NotAReceiver,
-    /// Overrun
+    /// Overrun: received data was lost because it wasn't read in time.
     Overrun,
+    /// Underrun: the transmitter ran out of data.
+    Underrun,
+    /// Frame format error, usually caused by a word select glitch in slave mode.
+    Framing,
+    /// The DMA ring buffer lost track of the transfer position, e.g. because the DMA channel was
+    /// reset underneath the driver.
+    DmaError,
+    /// The requested sample rate can't be reached with the I2S clock divider.
+    InvalidSampleRate,
+    /// The peripheral reported a condition that can only come from a wrong configuration, like a
+    /// mode fault.
+    InvalidConfig,
+    /// `blocking_write` called on an I2S whose transmit DMA feeds the data register.
+    Busy,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Self::NotATransmitter => "Not a Transmitter",
+            Self::NotAReceiver => "Not a Receiver",
+            Self::Overrun => "Receive Overrun",
+            Self::Underrun => "Transmit Underrun",
+            Self::Framing => "Invalid Framing",
+            Self::DmaError => "DMA Ring Buffer Out of Sync",
+            Self::InvalidSampleRate => "Invalid Sample Rate",
+            Self::InvalidConfig => "Invalid Configuration",
+            Self::Busy => "Transmit DMA Active",
+        };
+
+        f.write_str(message)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Transfer statistics, see [`I2S::stats`].
+///
+/// The counters saturate instead of wrapping around.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct I2sStats {
+    /// Receive overruns, from the ringbuffer or the peripheral.
+    pub overruns: u32,
+    /// Transmit underruns, from the ringbuffer or the peripheral.
+    pub underruns: u32,
+    /// Bytes successfully read or written.
+    pub bytes_transferred: u64,
+}
+
+impl I2sStats {
+    fn record(&mut self, result: &Result<(), Error>, bytes: usize) {
+        match result {
+            Ok(()) => self.bytes_transferred = self.bytes_transferred.saturating_add(bytes as u64),
+            Err(Error::Overrun) => self.overruns = self.overruns.saturating_add(1),
+            Err(Error::Underrun) => self.underruns = self.underruns.saturating_add(1),
+            Err(_) => {}
+        }
+    }
 }
 
 impl From<ringbuffer::Error> for Error {
-    fn from(#[allow(unused)] err: ringbuffer::Error) -> Self {
-        #[cfg(feature = "defmt")]
-        {
-            if err == ringbuffer::Error::DmaUnsynced {
+    fn from(err: ringbuffer::Error) -> Self {
+        match err {
+            ringbuffer::Error::Overrun => Self::Overrun,
+            ringbuffer::Error::DmaUnsynced => {
+                #[cfg(feature = "defmt")]
                 defmt::error!("Ringbuffer broken invariants detected!");
+                Self::DmaError
             }
         }
-        Self::Overrun
+    }
+}
+
+/// Maps the SPI errors the peripheral can report to their I2S meaning. CRC and mode faults can't
+/// happen in I2S mode unless the peripheral is misconfigured.
+impl From<crate::spi::Error> for Error {
+    fn from(err: crate::spi::Error) -> Self {
+        match err {
+            crate::spi::Error::Framing => Self::Framing,
+            crate::spi::Error::Overrun => Self::Overrun,
+            crate::spi::Error::Crc | crate::spi::Error::ModeFault => Self::InvalidConfig,
+        }
+    }
+}
+
+// First error of the status register flags, in the order `check_errors` reports them.
+fn status_error(overrun: bool, underrun: bool, framing: bool) -> Result<(), Error> {
+    if overrun {
+        Err(Error::Overrun)
+    } else if underrun {
+        Err(Error::Underrun)
+    } else if framing {
+        Err(Error::Framing)
+    } else {
+        Ok(())
     }
 }
 
@@ -168,6 +269,8 @@ pub struct Config {
     /// Clock polarity.
     pub clock_polarity: ClockPolarity,
     /// True to enable master clock output from this instance.
+    ///
+    /// Always enabled by the constructors that take an MCK pin.
     pub master_clock: bool,
 }
 
@@ -193,12 +296,16 @@ impl<'s, 'd, W: Word> Writer<'s, 'd, W> {
     /// This appends the data to the buffer and returns immediately. The data will be transmitted in the background.
     /// If thfre’s no space in the buffer, this waits until there is.
     pub async fn write(&mut self, data: &[W]) -> Result<(), Error> {
-        self.0.write_exact(data).await?;
+        // The DMA catching up with the writer means the transmitter was starved.
+        self.0.write_exact(data).await.map_err(|err| match err {
+            ringbuffer::Error::Overrun => Error::Underrun,
+            err => err.into(),
+        })?;
         Ok(())
     }
 
     /// Reset the ring buffer to its initial state.
-    /// Can be used to recover from overrun.
+    /// Can be used to recover from underrun.
     /// The ringbuffer will always auto-reset on Overrun in any case.
     pub fn reset(&mut self) {
         self.0.clear();
@@ -237,10 +344,18 @@ pub struct I2S<'d, W: Word> {
     mck: Option<Peri<'d, AnyPin>>,
     tx_ring_buffer: Option<WritableRingBuffer<'d, W>>,
     rx_ring_buffer: Option<ReadableRingBuffer<'d, W>>,
+    pclk: Hertz,
+    master_clock: bool,
+    format: Format,
+    divider: (bool, u8),
+    stats: I2sStats,
 }
 
 impl<'d, W: Word> I2S<'d, W> {
     /// Create a transmitter driver.
+    ///
+    /// The master clock is output on `mck` at 256 times the sample rate, regardless of
+    /// [`Config::master_clock`].
     pub fn new_txonly<T: Instance>(
         peri: Peri<'d, T>,
         sd: Peri<'d, impl MosiPin<T>>,
@@ -289,7 +404,35 @@ impl<'d, W: Word> I2S<'d, W> {
         )
     }
 
+    /// Create a transmitter driver without a master clock pin or DMA.
+    ///
+    /// Only [`I2S::blocking_write`] can be used to send data. This is intended for setup tones and
+    /// diagnostics: polling the peripheral can't keep up with high sample rates.
+    pub fn new_txonly_nomck_blocking<T: Instance>(
+        peri: Peri<'d, T>,
+        sd: Peri<'d, impl MosiPin<T>>,
+        ws: Peri<'d, impl WsPin<T>>,
+        ck: Peri<'d, impl CkPin<T>>,
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            new_pin!(sd, AfType::output(OutputType::PushPull, Speed::VeryHigh)),
+            None,
+            ws,
+            ck,
+            None,
+            None,
+            None,
+            config,
+            Function::Transmit,
+        )
+    }
+
     /// Create a receiver driver.
+    ///
+    /// The master clock is output on `mck` at 256 times the sample rate, regardless of
+    /// [`Config::master_clock`].
     pub fn new_rxonly<T: Instance>(
         peri: Peri<'d, T>,
         sd: Peri<'d, impl MisoPin<T>>,
@@ -314,8 +457,35 @@ impl<'d, W: Word> I2S<'d, W> {
         )
     }
 
+    /// Create a receiver driver without a master clock pin.
+    pub fn new_rxonly_nomck<T: Instance>(
+        peri: Peri<'d, T>,
+        sd: Peri<'d, impl MisoPin<T>>,
+        ws: Peri<'d, impl WsPin<T>>,
+        ck: Peri<'d, impl CkPin<T>>,
+        rxdma: Peri<'d, impl RxDma<T>>,
+        rxdma_buf: &'d mut [W],
+        config: Config,
+    ) -> Self {
+        Self::new_inner(
+            peri,
+            None,
+            new_pin!(sd, AfType::output(OutputType::PushPull, Speed::VeryHigh)),
+            ws,
+            ck,
+            None,
+            None,
+            new_dma!(rxdma).map(|d| (d, rxdma_buf)),
+            config,
+            Function::Receive,
+        )
+    }
+
     #[cfg(spi_v3)]
     /// Create a full duplex driver.
+    ///
+    /// The master clock is output on `mck` at 256 times the sample rate, regardless of
+    /// [`Config::master_clock`].
     pub fn new_full_duplex<T: Instance>(
         peri: Peri<'d, T>,
         txsd: Peri<'d, impl MosiPin<T>>,
@@ -373,6 +543,9 @@ impl<'d, W: Word> I2S<'d, W> {
 
     /// Reset the ring buffer to its initial state.
     /// Can be used to recover from overrun.
+    ///
+    /// [`stop`](Self::stop) calls this, so stale samples are not played out after a restart. While
+    /// the DMA is running, words already handed to the peripheral are still transmitted.
     pub fn clear(&mut self) {
         if let Some(rx_ring_buffer) = &mut self.rx_ring_buffer {
             rx_ring_buffer.clear();
@@ -422,6 +595,83 @@ impl<'d, W: Word> I2S<'d, W> {
         self.clear();
     }
 
+    /// Check the I2S error flags and clear them.
+    ///
+    /// Returns [`Error::Overrun`], [`Error::Underrun`] or [`Error::Framing`] for the first flag that
+    /// is set, in that order. Clearing an overrun on SPI v1 reads the data register, which drops
+    /// one received word.
+    pub fn check_errors(&mut self) -> Result<(), Error> {
+        let regs = self.spi.info.regs;
+        let sr = regs.sr().read();
+        self.stats.overruns = self.stats.overruns.saturating_add(sr.ovr() as u32);
+        self.stats.underruns = self.stats.underruns.saturating_add(sr.udr() as u32);
+
+        // UDR and FRE are cleared by the SR read above, OVR by reading DR followed by SR.
+        #[cfg(not(any(spi_v3, spi_v4, spi_v5)))]
+        if sr.ovr() {
+            let _ = regs.dr().read();
+            let _ = regs.sr().read();
+        }
+        #[cfg(any(spi_v3, spi_v4, spi_v5))]
+        regs.ifcr().write(|w| {
+            w.set_ovrc(sr.ovr());
+            w.set_udrc(sr.udr());
+            w.set_tifrec(sr.tifre());
+        });
+
+        #[cfg(not(any(spi_v3, spi_v4, spi_v5)))]
+        let framing = sr.fre();
+        #[cfg(any(spi_v3, spi_v4, spi_v5))]
+        let framing = sr.tifre();
+        status_error(sr.ovr(), sr.udr(), framing)
+    }
+
+    /// Change the sample rate, keeping the ring buffers.
+    ///
+    /// A running driver is stopped while the clock divider is reprogrammed and started again
+    /// afterwards, so buffered data is discarded. A stopped driver stays stopped. Returns
+    /// [`Error::InvalidSampleRate`] without touching the driver if `freq` can't be reached from the
+    /// I2S clock.
+    pub async fn set_sample_rate(&mut self, freq: Hertz) -> Result<(), Error> {
+        let (odd, div) =
+            try_compute_baud_rate(self.pclk, freq, self.master_clock, self.format).ok_or(Error::InvalidSampleRate)?;
+
+        // `start` enables the peripheral and `stop` disables it.
+        let running = self.spi.info.regs.cr1().read().spe();
+        if running {
+            self.stop().await;
+        }
+
+        #[cfg(any(spi_v1, spi_v3, spi_f1))]
+        {
+            let regs = self.spi.info.regs;
+
+            // The prescaler must only be written while I2S is disabled.
+            #[cfg(any(spi_v1, spi_f1))]
+            regs.i2scfgr().modify(|w| w.set_i2se(false));
+
+            set_clock_divider(regs, odd, div);
+
+            #[cfg(any(spi_v1, spi_f1))]
+            regs.i2scfgr().modify(|w| w.set_i2se(true));
+        }
+        self.divider = (odd, div);
+
+        if running {
+            self.start();
+        }
+        Ok(())
+    }
+
+    /// The sample rate produced by the programmed clock divider.
+    ///
+    /// The divider is an integer, so this differs slightly from the requested frequency unless the I2S
+    /// clock is an exact multiple of it.
+    pub fn actual_sample_rate(&self) -> Hertz {
+        let (odd, div) = self.divider;
+        compute_sample_rate(self.pclk, odd, div, self.master_clock, self.format)
+    }
+
     /// Split the driver into a Reader/Writer pair.
     /// Useful for splitting the reader/writer functionality across tasks or
     /// for calling the read/write methods in parallel.
@@ -437,20 +687,186 @@ impl<'d, W: Word> I2S<'d, W> {
     /// SAI is always receiving data in the background. This function pops already-received data from the buffer.
     /// If there’s less than data.len() data in the buffer, this waits until there is.
     pub async fn read(&mut self, data: &mut [W]) -> Result<(), Error> {
-        match &mut self.rx_ring_buffer {
+        let result = match &mut self.rx_ring_buffer {
             Some(ring) => Reader(ring).read(data).await,
             _ => Err(Error::NotAReceiver),
-        }
+        };
+        self.stats.record(&result, core::mem::size_of_val(data));
+        result
     }
 
     /// Write data to the I2S ringbuffer.
     /// This appends the data to the buffer and returns immediately. The data will be transmitted in the background.
     /// If thfre’s no space in the buffer, this waits until there is.
     pub async fn write(&mut self, data: &[W]) -> Result<(), Error> {
-        match &mut self.tx_ring_buffer {
+        let result = match &mut self.tx_ring_buffer {
             Some(ring) => Writer(ring).write(data).await,
             _ => Err(Error::NotATransmitter),
+        };
+        self.stats.record(&result, core::mem::size_of_val(data));
+        result
+    }
+
+    /// Write separate left and right channel samples to the I2S ringbuffer.
+    ///
+    /// The samples are interleaved left first, which is the order the peripheral transmits them in
+    /// for every [`Standard`]:
+    ///  - `Philips`: left while WS is low, then right while WS is high.
+    ///  - `MsbFirst` and `LsbFirst`: left while WS is high, then right while WS is low.
+    ///  - `PcmLongSync` and `PcmShortSync`: no channel indication, the samples alternate in
+    ///    consecutive frames starting with left.
+    ///
+    /// With 24 or 32 bit data and 16 bit words, each sample is two words, most significant half
+    /// first, and both channel slices must hold whole samples. Panics if `left` and `right` differ
+    /// in length or hold a partial sample.
+    pub async fn write_stereo(&mut self, left: &[W], right: &[W]) -> Result<(), Error> {
+        let words = words_per_sample(W::bits(), self.format);
+        assert_eq!(left.len(), right.len());
+        assert_eq!(left.len() % words, 0, "partial sample");
+
+        let mut buf = [W::default(); STEREO_CHUNK];
+        for (l, r) in left.chunks(STEREO_CHUNK / 2).zip(right.chunks(STEREO_CHUNK / 2)) {
+            let n = interleave(l, r, &mut buf, words);
+            self.write(&buf[..n]).await?;
+        }
+        Ok(())
+    }
+
+    /// Overrun, underrun and transfer counters since the driver was created or [`I2S::reset_stats`] was called.
+    ///
+    /// Counts transfers made through [`I2S::read`], [`I2S::write`] and the functions built on them,
+    /// and the error flags seen by [`I2S::check_errors`]. Transfers through the [`Reader`] and
+    /// [`Writer`] returned by [`I2S::split`] are not counted.
+    pub fn stats(&self) -> I2sStats {
+        self.stats
+    }
+
+    /// Reset the counters reported by [`I2S::stats`].
+    pub fn reset_stats(&mut self) {
+        self.stats = I2sStats::default();
+    }
+
+    /// Position of the DMA in the ring buffer, in words.
+    ///
+    /// This is the transmit buffer if the driver has one, the receive buffer otherwise.
+    pub fn index(&self) -> usize {
+        match (&self.tx_ring_buffer, &self.rx_ring_buffer) {
+            (Some(ring), _) => ring.dma_index(),
+            (None, Some(ring)) => ring.dma_index(),
+            (None, None) => 0,
+        }
+    }
+
+    /// Wait until the DMA crosses into the other half of the ring buffer.
+    ///
+    /// For ping-pong processing: once this returns, the half the DMA just left can be refilled or
+    /// consumed while the DMA works on the other one, see [`index`](Self::index). The wake-up comes
+    /// from the DMA half and full transfer interrupts, so the latency is the interrupt latency plus
+    /// the executor's scheduling delay. If the task is not polled for more than half a buffer, a
+    /// boundary can be missed.
+    pub async fn wait_half(&mut self) {
+        let Some(start) = self.dma_half(None) else {
+            return;
+        };
+        poll_fn(|cx| match self.dma_half(Some(cx.waker())) {
+            Some(half) if half == start => Poll::Pending,
+            _ => Poll::Ready(()),
+        })
+        .await;
+    }
+
+    // Whether the DMA is in the second half of the ring buffer, registering `waker` for the next
+    // half or full transfer interrupt first.
+    fn dma_half(&mut self, waker: Option<&Waker>) -> Option<bool> {
+        let (index, capacity) = match (&mut self.tx_ring_buffer, &mut self.rx_ring_buffer) {
+            (Some(ring), _) => {
+                if let Some(waker) = waker {
+                    ring.set_waker(waker);
+                }
+                (ring.dma_index(), ring.capacity())
+            }
+            (None, Some(ring)) => {
+                if let Some(waker) = waker {
+                    ring.set_waker(waker);
+                }
+                (ring.dma_index(), ring.capacity())
+            }
+            (None, None) => return None,
+        };
+        Some(index >= capacity / 2)
+    }
+
+    /// Loopback self-test for board validation.
+    ///
+    /// Writes `pattern` and reads the receive path back, which must be externally jumpered to the
+    /// transmit path. Returns `Ok(true)` if the whole pattern was received within the codec latency
+    /// (up to [`SELF_TEST_MAX_LATENCY`] words of leading data are discarded), `Ok(false)` otherwise.
+    ///
+    /// The driver must have been created with both a transmitter and a receiver and must be started.
+    /// The pattern should not repeat its own prefix (a ramp works well), because on a mismatch the
+    /// comparison only restarts at the current word.
+    pub async fn self_test(&mut self, pattern: &[W]) -> Result<bool, Error>
+    where
+        W: PartialEq,
+    {
+        if pattern.is_empty() {
+            return Ok(true);
+        }
+
+        let (mut reader, mut writer) = self.split()?;
+        let mut matcher = PatternMatcher::new(pattern);
+
+        let tx_f = writer.write(pattern);
+        let rx_f = async {
+            let mut word = [W::default()];
+            for _ in 0..pattern.len() + SELF_TEST_MAX_LATENCY {
+                reader.read(&mut word).await?;
+                if matcher.push(word[0]) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+
+        let (tx, rx) = join(tx_f, rx_f).await;
+        tx?;
+        rx
+    }
+
+    /// Write data by polling the peripheral, without DMA.
+    ///
+    /// Blocks until every word has been handed to the peripheral. The driver must be started. Use this
+    /// for short setup or diagnostic transfers only; at high sample rates the CPU can't keep the
+    /// transmitter fed and the stream underruns.
+    ///
+    /// Only works on drivers created without transmit DMA, like with
+    /// [`new_txonly_nomck_blocking`](Self::new_txonly_nomck_blocking). Otherwise the DMA owns the data
+    /// register and this returns [`Error::Busy`].
+    pub fn blocking_write(&mut self, data: &[W]) -> Result<(), Error> {
+        if self.txsd.is_none() {
+            return Err(Error::NotATransmitter);
+        }
+        if self.tx_ring_buffer.is_some() {
+            return Err(Error::Busy);
+        }
+
+        let regs = self.spi.info.regs;
+        for word in data {
+            loop {
+                let sr = regs.sr().read();
+                #[cfg(not(any(spi_v3, spi_v4, spi_v5)))]
+                if sr.txe() {
+                    break;
+                }
+                #[cfg(any(spi_v3, spi_v4, spi_v5))]
+                if sr.txp() {
+                    break;
+                }
+            }
+            unsafe { ptr::write_volatile(regs.tx_ptr(), *word) };
         }
+        self.stats.record(&Ok(()), core::mem::size_of_val(data));
+        Ok(())
     }
 
     /// Write data directly to the raw I2S ringbuffer.
@@ -471,9 +887,17 @@ impl<'d, W: Word> I2S<'d, W> {
         mck: Option<Peri<'d, AnyPin>>,
         txdma: Option<(ChannelAndRequest<'d>, &'d mut [W])>,
         rxdma: Option<(ChannelAndRequest<'d>, &'d mut [W])>,
-        config: Config,
+        mut config: Config,
         function: Function,
     ) -> Self {
+        assert!(
+            word_fits_format(W::bits(), config.format, DATA_REGISTER_BITS),
+            "DMA word size doesn't match the I2S data format"
+        );
+
+        // An MCK pin is useless without the master clock output.
+        config.master_clock |= mck.is_some();
+
         ws.set_as_af(ws.af_num(), AfType::output(OutputType::PushPull, config.gpio_speed));
         ck.set_as_af(ck.af_num(), AfType::output(OutputType::PushPull, config.gpio_speed));
 
@@ -501,7 +925,7 @@ impl<'d, W: Word> I2S<'d, W> {
                 reset_incompatible_bitfields::<T>();
             }
 
-            use stm32_metapac::spi::vals::{I2scfg, Odd};
+            use stm32_metapac::spi::vals::I2scfg;
 
             // 1. Select the I2SDIV[7:0] bits in the SPI_I2SPR/SPI_I2SCFGR register to define the serial clock baud
             // rate to reach the proper audio sample frequency. The ODD bit in the
@@ -524,26 +948,12 @@ impl<'d, W: Word> I2S<'d, W> {
 
             // 5. The I2SE bit in SPI_I2SCFGR register must be set.
 
-            let clk_reg = {
-                #[cfg(any(spi_v1, spi_f1))]
-                {
-                    regs.i2spr()
-                }
-                #[cfg(spi_v3)]
-                {
-                    regs.i2scfgr()
-                }
-            };
-
-            clk_reg.modify(|w| {
-                w.set_i2sdiv(div);
-                w.set_odd(match odd {
-                    true => Odd::ODD,
-                    false => Odd::EVEN,
-                });
+            set_clock_divider(regs, odd, div);
 
-                w.set_mckoe(config.master_clock);
-            });
+            #[cfg(any(spi_v1, spi_f1))]
+            regs.i2spr().modify(|w| w.set_mckoe(config.master_clock));
+            #[cfg(spi_v3)]
+            regs.i2scfgr().modify(|w| w.set_mckoe(config.master_clock));
 
             regs.i2scfgr().modify(|w| {
                 w.set_ckpol(config.clock_polarity.ckpol());
@@ -588,13 +998,187 @@ impl<'d, W: Word> I2S<'d, W> {
                 rx_ring_buffer: rxdma.map(|(ch, buf)| unsafe {
                     ReadableRingBuffer::new(ch.channel, ch.request, regs.rx_ptr(), buf, opts)
                 }),
+                pclk,
+                master_clock: config.master_clock,
+                format: config.format,
+                divider: (odd, div),
+                stats: I2sStats::default(),
             }
         }
     }
 }
 
+/// Software CIC (cascaded integrator-comb) filter converting a 1 bit PDM stream to 16 bit PCM.
+///
+/// The filter is third order and decimates by `decimation`, e.g. a 3.072MHz PDM clock decimated
+/// by 64 gives 48kHz PCM. It keeps its state between calls of [`process`](Self::process), so a
+/// continuous stream can be converted buffer by buffer. A CIC filter droops towards the output
+/// Nyquist frequency; voice applications usually don't need further compensation.
+pub struct PdmDecimator {
+    decimation: u16,
+    count: u16,
+    integrators: [i32; 3],
+    combs: [i32; 3],
+}
+
+impl PdmDecimator {
+    /// Create a decimator with the given decimation ratio.
+    ///
+    /// # Panics
+    /// `decimation` must be in range `1..=1024`, so the filter can't overflow.
+    pub const fn new(decimation: u16) -> Self {
+        ::core::assert!(decimation >= 1 && decimation <= 1024, "decimation must be 1..=1024");
+        Self {
+            decimation,
+            count: 0,
+            integrators: [0; 3],
+            combs: [0; 3],
+        }
+    }
+
+    /// Decimate the PDM bits of `raw`, MSB of each byte first, into `out`.
+    ///
+    /// Returns the number of bytes of `raw` consumed and the number of samples written. Whole
+    /// bytes are consumed, stopping before a byte whose samples don't fit in `out`; pass the
+    /// remaining bytes to the next call to keep a continuous stream intact. Size `out` for
+    /// `raw.len() * 8 / decimation` samples, rounded up, to consume all of `raw` at once. The first
+    /// two samples of a stream are the filter settling.
+    ///
+    /// Words received by [`I2S`] must be passed to [`process_words`](Self::process_words) instead,
+    /// or converted with `to_be_bytes()`: reinterpreting a `u16` or `u32` buffer as bytes on a
+    /// little-endian target feeds the bits of each word out of order.
+    pub fn process(&mut self, raw: &[u8], out: &mut [i16]) -> (usize, usize) {
+        self.process_words(raw, out)
+    }
+
+    /// Decimate the PDM bits of `raw`, MSB of each word first, into `out`.
+    ///
+    /// Takes the words as received by [`I2S`], e.g. `u16` for 16 bit data formats. Works like
+    /// [`process`](Self::process), consuming whole words: returns the number of words of `raw`
+    /// consumed and the number of samples written.
+    pub fn process_words<W: word::Word + Into<u32>>(&mut self, raw: &[W], out: &mut [i16]) -> (usize, usize) {
+        let bits = W::bits();
+        let gain = (self.decimation as i64).pow(3);
+        let mut n = 0;
+        for (consumed, &word) in raw.iter().enumerate() {
+            if n + (self.count as usize + bits) / self.decimation as usize > out.len() {
+                return (consumed, n);
+            }
+            let word: u32 = word.into();
+            for bit in (0..bits).rev() {
+                let x = if word >> bit & 1 != 0 { 1 } else { -1 };
+                // Wrapping arithmetic is exact as long as the result fits, which `new` ensures.
+                self.integrators[0] = self.integrators[0].wrapping_add(x);
+                self.integrators[1] = self.integrators[1].wrapping_add(self.integrators[0]);
+                self.integrators[2] = self.integrators[2].wrapping_add(self.integrators[1]);
+
+                self.count += 1;
+                if self.count < self.decimation {
+                    continue;
+                }
+                self.count = 0;
+
+                let mut y = self.integrators[2];
+                for comb in &mut self.combs {
+                    let prev = core::mem::replace(comb, y);
+                    y = y.wrapping_sub(prev);
+                }
+                out[n] = (y as i64 * i16::MAX as i64 / gain) as i16;
+                n += 1;
+            }
+        }
+        (raw.len(), n)
+    }
+}
+
+/// Convert a single buffer of PDM bits to PCM with a fresh [`PdmDecimator`].
+///
+/// Returns the number of samples written. Use a [`PdmDecimator`] directly for a continuous stream,
+/// since every call of this function starts the filter over.
+pub fn pdm_to_pcm(raw: &[u8], out: &mut [i16], decimation: u16) -> usize {
+    PdmDecimator::new(decimation).process(raw, out).1
+}
+
+/// Width of the data register the DMA transfers words from or to.
+const DATA_REGISTER_BITS: usize = if cfg!(spi_v3) { 32 } else { 16 };
+
+/// Whether DMA words of `word_bits` carry samples of `format` through a data register of `dr_bits`.
+///
+/// A 16 bit data register moves 24 and 32 bit samples as two half words, so it needs 16 bit words
+/// for every format. A 32 bit data register needs 32 bit words for samples wider than 16 bits, and
+/// takes 16 bit samples one per word or packed two per word.
+fn word_fits_format(word_bits: usize, format: Format, dr_bits: usize) -> bool {
+    match (dr_bits, format) {
+        (16, _) => word_bits == 16,
+        (_, Format::Data16Channel16 | Format::Data16Channel32) => word_bits == 16 || word_bits == 32,
+        (_, Format::Data24Channel32 | Format::Data32Channel32) => word_bits == 32,
+    }
+}
+
+/// Number of words [`I2S::write_stereo`] interleaves on the stack per ringbuffer write.
+const STEREO_CHUNK: usize = 32;
+
+/// Number of DMA words of `word_bits` that carry one sample of `format`.
+fn words_per_sample(word_bits: usize, format: Format) -> usize {
+    match format {
+        Format::Data24Channel32 | Format::Data32Channel32 if word_bits == 16 => 2,
+        _ => 1,
+    }
+}
+
+/// Interleave the samples of `words` words each in `left` and `right` into `out`, left first.
+/// Returns the number of words written.
+fn interleave<W: Copy>(left: &[W], right: &[W], out: &mut [W], words: usize) -> usize {
+    let mut n = 0;
+    let samples = left.chunks_exact(words).zip(right.chunks_exact(words));
+    for (frame, (l, r)) in out.chunks_exact_mut(2 * words).zip(samples) {
+        frame[..words].copy_from_slice(l);
+        frame[words..].copy_from_slice(r);
+        n += 2 * words;
+    }
+    n
+}
+
+/// Maximum number of words [`I2S::self_test`] discards while waiting for the pattern to come back.
+pub const SELF_TEST_MAX_LATENCY: usize = 64;
+
+/// Matches a pattern against a stream of received words.
+struct PatternMatcher<'a, W> {
+    pattern: &'a [W],
+    matched: usize,
+}
+
+impl<'a, W: PartialEq> PatternMatcher<'a, W> {
+    fn new(pattern: &'a [W]) -> Self {
+        Self { pattern, matched: 0 }
+    }
+
+    /// Feed one received word. Returns `true` once the whole pattern has been seen.
+    fn push(&mut self, word: W) -> bool {
+        if self.pattern[self.matched] == word {
+            self.matched += 1;
+        } else if self.pattern[0] == word {
+            self.matched = 1;
+        } else {
+            self.matched = 0;
+        }
+        self.matched == self.pattern.len()
+    }
+}
+
 impl<'d, W: Word> Drop for I2S<'d, W> {
     fn drop(&mut self) {
+        // The ring buffers stop their DMA channel when they are dropped, but that only happens after
+        // this function returns. Stop it first so the pins are not released mid-transfer.
+        if let Some(ring) = &mut self.tx_ring_buffer {
+            ring.request_stop();
+            while ring.is_running() {}
+        }
+        if let Some(ring) = &mut self.rx_ring_buffer {
+            ring.request_stop();
+            while ring.is_running() {}
+        }
+
         self.txsd.as_ref().map(|x| x.set_as_disconnected());
         self.rxsd.as_ref().map(|x| x.set_as_disconnected());
         self.ws.as_ref().map(|x| x.set_as_disconnected());
@@ -618,17 +1202,28 @@ impl<'d, W: Word> Drop for I2S<'d, W> {
 //
 // note: division = (2 * div) + odd = (div << 1) + odd
 // in other word, from bits point of view, division[8:1] = div[7:0] and division[0] = odd
-fn compute_baud_rate(i2s_clock: Hertz, request_freq: Hertz, mclk: bool, data_format: Format) -> (bool, u8) {
-    let coef = if mclk {
+const fn clock_coef(mclk: bool, data_format: Format) -> u32 {
+    if mclk {
         256
     } else if let Format::Data16Channel16 = data_format {
         32
     } else {
         64
-    };
+    }
+}
+
+fn compute_division(i2s_clock: Hertz, request_freq: Hertz, mclk: bool, data_format: Format) -> u32 {
+    let (n, d) = (i2s_clock.0, clock_coef(mclk, data_format) * request_freq.0);
+    (n + (d >> 1)) / d
+}
 
-    let (n, d) = (i2s_clock.0, coef * request_freq.0);
-    let division = (n + (d >> 1)) / d;
+fn compute_sample_rate(i2s_clock: Hertz, odd: bool, div: u8, mclk: bool, data_format: Format) -> Hertz {
+    let division = ((div as u32) << 1) + odd as u32;
+    Hertz(i2s_clock.0 / (clock_coef(mclk, data_format) * division))
+}
+
+fn compute_baud_rate(i2s_clock: Hertz, request_freq: Hertz, mclk: bool, data_format: Format) -> (bool, u8) {
+    let division = compute_division(i2s_clock, request_freq, mclk, data_format);
 
     if division < 4 {
         (false, 2)
@@ -639,6 +1234,40 @@ fn compute_baud_rate(i2s_clock: Hertz, request_freq: Hertz, mclk: bool, data_for
     }
 }
 
+// Like `compute_baud_rate`, but returns `None` instead of clamping to the divider limits.
+fn try_compute_baud_rate(i2s_clock: Hertz, request_freq: Hertz, mclk: bool, data_format: Format) -> Option<(bool, u8)> {
+    let division = compute_division(i2s_clock, request_freq, mclk, data_format);
+
+    match division {
+        4..=511 => Some(((division & 1) == 1, (division >> 1) as u8)),
+        _ => None,
+    }
+}
+
+#[cfg(any(spi_v1, spi_v3, spi_f1))]
+fn set_clock_divider(regs: crate::pac::spi::Spi, odd: bool, div: u8) {
+    use stm32_metapac::spi::vals::Odd;
+
+    let clk_reg = {
+        #[cfg(any(spi_v1, spi_f1))]
+        {
+            regs.i2spr()
+        }
+        #[cfg(spi_v3)]
+        {
+            regs.i2scfgr()
+        }
+    };
+
+    clk_reg.modify(|w| {
+        w.set_i2sdiv(div);
+        w.set_odd(match odd {
+            true => Odd::ODD,
+            false => Odd::EVEN,
+        });
+    });
+}
+
 #[cfg(spi_v3)]
 // The STM32H7 reference manual specifies that any incompatible bitfields should be reset
 // to their reset values while operating in I2S mode.
@@ -706,3 +1335,228 @@ fn reset_incompatible_bitfields<T: Instance>() {
     regs.rxcrc().write(|w| w.0 = 0);
     regs.udrdr().write(|w| w.0 = 0);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &[u16], stream: &[u16]) -> bool {
+        let mut matcher = PatternMatcher::new(pattern);
+        stream.iter().any(|&w| matcher.push(w))
+    }
+
+    #[test]
+    fn word_size_matches_format() {
+        // 16 bit data register
+        assert!(word_fits_format(16, Format::Data16Channel16, 16));
+        assert!(word_fits_format(16, Format::Data32Channel32, 16));
+        assert!(!word_fits_format(32, Format::Data24Channel32, 16));
+        assert!(!word_fits_format(8, Format::Data16Channel16, 16));
+
+        // 32 bit data register
+        assert!(word_fits_format(16, Format::Data16Channel32, 32));
+        assert!(word_fits_format(32, Format::Data16Channel16, 32));
+        assert!(word_fits_format(32, Format::Data24Channel32, 32));
+        assert!(!word_fits_format(16, Format::Data32Channel32, 32));
+        assert!(!word_fits_format(16, Format::Data24Channel32, 32));
+    }
+
+    #[test]
+    fn pdm_decimation() {
+        let mut out = [0; 8];
+
+        // Full scale in both directions, after the filter settled
+        assert_eq!(pdm_to_pcm(&[0xff; 64], &mut out, 64), 8);
+        assert!(out[2..].iter().all(|&s| s == i16::MAX));
+        assert_eq!(pdm_to_pcm(&[0x00; 64], &mut out, 64), 8);
+        assert!(out[2..].iter().all(|&s| s == -i16::MAX));
+
+        // 50% density is silence
+        assert_eq!(pdm_to_pcm(&[0xaa; 64], &mut out, 64), 8);
+        assert!(out[2..].iter().all(|&s| s == 0));
+
+        // State is kept across buffers, and a full `out` stops before the byte that doesn't fit
+        let mut pdm = PdmDecimator::new(16);
+        assert_eq!(pdm.process(&[0xff; 6], &mut out[..2]), (5, 2));
+        assert_eq!(pdm.process(&[0xff; 5], &mut out), (5, 3));
+        assert_eq!(out[2], i16::MAX);
+
+        // Splitting a stream at a full `out` gives the same samples as one large buffer
+        let raw: [u8; 48] = core::array::from_fn(|i| (i * 37) as u8);
+        let mut whole = [0; 24];
+        assert_eq!(PdmDecimator::new(16).process(&raw, &mut whole), (48, 24));
+        let mut pdm = PdmDecimator::new(16);
+        let mut split = [0; 24];
+        let (consumed, n) = pdm.process(&raw, &mut split[..5]);
+        assert_eq!((consumed, n), (11, 5));
+        assert_eq!(pdm.process(&raw[consumed..], &mut split[n..]), (37, 19));
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn pdm_decimation_words() {
+        let words: [u16; 24] = core::array::from_fn(|i| (i * 7919) as u16);
+        let bytes: [u8; 48] = core::array::from_fn(|i| words[i / 2].to_be_bytes()[i % 2]);
+        let mut from_bytes = [0; 24];
+        assert_eq!(PdmDecimator::new(16).process(&bytes, &mut from_bytes), (48, 24));
+
+        // Words are MSB first, like their big-endian bytes
+        let mut from_words = [0; 24];
+        assert_eq!(PdmDecimator::new(16).process_words(&words, &mut from_words), (24, 24));
+        assert_eq!(from_words, from_bytes);
+
+        let words: [u32; 12] = core::array::from_fn(|i| u32::from(words[2 * i]) << 16 | u32::from(words[2 * i + 1]));
+        let mut pdm = PdmDecimator::new(16);
+        let mut split = [0; 24];
+        // A word of 32 bits gives 2 samples, so it doesn't fit in 1
+        assert_eq!(pdm.process_words(&words, &mut split[..3]), (1, 2));
+        assert_eq!(pdm.process_words(&words[1..], &mut split[2..]), (11, 22));
+        assert_eq!(split, from_bytes);
+    }
+
+    #[test]
+    fn sample_rate_dividers() {
+        let pclk = Hertz(61_440_000);
+        let divider = |fs, mclk, format| try_compute_baud_rate(pclk, Hertz(fs), mclk, format);
+
+        assert_eq!(divider(8_000, true, Format::Data16Channel16), Some((false, 15)));
+        assert_eq!(divider(16_000, true, Format::Data16Channel16), Some((true, 7)));
+        assert_eq!(divider(44_100, true, Format::Data16Channel16), Some((true, 2)));
+        assert_eq!(divider(48_000, true, Format::Data16Channel16), Some((true, 2)));
+        // A division of 3 is below the hardware minimum.
+        assert_eq!(divider(96_000, true, Format::Data16Channel16), None);
+        assert_eq!(divider(96_000, false, Format::Data16Channel16), Some((false, 10)));
+        assert_eq!(divider(96_000, false, Format::Data24Channel32), Some((false, 5)));
+        // A division of 960 exceeds the 8-bit divider.
+        assert_eq!(divider(1_000, false, Format::Data24Channel32), None);
+    }
+
+    #[test]
+    fn master_clock_divider() {
+        // With MCK enabled the divider is based on 256 * Fs, independent of the frame format.
+        assert_eq!(clock_coef(true, Format::Data16Channel16), 256);
+        assert_eq!(clock_coef(true, Format::Data32Channel32), 256);
+        assert_eq!(clock_coef(false, Format::Data16Channel16), 32);
+        assert_eq!(clock_coef(false, Format::Data24Channel32), 64);
+
+        let pclk = Hertz(61_440_000);
+        assert_eq!(
+            compute_baud_rate(pclk, Hertz(48_000), true, Format::Data24Channel32),
+            (true, 2)
+        );
+        assert_eq!(
+            compute_baud_rate(pclk, Hertz(48_000), false, Format::Data24Channel32),
+            (false, 10)
+        );
+    }
+
+    #[test]
+    fn actual_sample_rate() {
+        let pclk = Hertz(61_440_000);
+        let actual = |fs, mclk, format| {
+            let (odd, div) = compute_baud_rate(pclk, Hertz(fs), mclk, format);
+            compute_sample_rate(pclk, odd, div, mclk, format)
+        };
+
+        // Exact when the I2S clock is a multiple of the sample rate.
+        assert_eq!(actual(48_000, true, Format::Data16Channel16), Hertz(48_000));
+        assert_eq!(actual(8_000, false, Format::Data24Channel32), Hertz(8_000));
+        // 44.1 kHz rounds to the same divider as 48 kHz.
+        assert_eq!(actual(44_100, true, Format::Data16Channel16), Hertz(48_000));
+        assert_eq!(actual(22_050, false, Format::Data16Channel32), Hertz(21_818));
+    }
+
+    #[test]
+    fn stereo_interleave() {
+        let mut out = [0u16; 8];
+
+        assert_eq!(interleave(&[], &[], &mut out, 1), 0);
+        assert_eq!(interleave(&[1], &[2], &mut out, 1), 2);
+        assert_eq!(out[..2], [1, 2]);
+        assert_eq!(interleave(&[1, 3, 5], &[2, 4, 6], &mut out, 1), 6);
+        assert_eq!(out[..6], [1, 2, 3, 4, 5, 6]);
+        // Stops when the output is full.
+        assert_eq!(interleave(&[1, 3, 5, 7, 9], &[2, 4, 6, 8, 10], &mut out, 1), 8);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn stereo_interleave_two_word_samples() {
+        assert_eq!(words_per_sample(16, Format::Data16Channel32), 1);
+        assert_eq!(words_per_sample(16, Format::Data24Channel32), 2);
+        assert_eq!(words_per_sample(16, Format::Data32Channel32), 2);
+        assert_eq!(words_per_sample(32, Format::Data32Channel32), 1);
+
+        // Left samples 0x1111_2222 and 0x3333_4444, right samples 0xaaaa_bbbb and 0xcccc_dddd.
+        let left = [0x1111, 0x2222, 0x3333, 0x4444];
+        let right = [0xaaaa, 0xbbbb, 0xcccc, 0xdddd];
+        let mut out = [0u16; 8];
+        assert_eq!(interleave(&left, &right, &mut out, 2), 8);
+        assert_eq!(out, [0x1111, 0x2222, 0xaaaa, 0xbbbb, 0x3333, 0x4444, 0xcccc, 0xdddd]);
+
+        // A frame that doesn't fit is left out whole.
+        let mut out = [0u16; 6];
+        assert_eq!(interleave(&left, &right, &mut out, 2), 4);
+        assert_eq!(out[..4], [0x1111, 0x2222, 0xaaaa, 0xbbbb]);
+    }
+
+    #[test]
+    fn self_test_compare() {
+        let pattern = [1, 2, 3, 4];
+
+        assert!(matches(&pattern, &[1, 2, 3, 4]));
+        // Leading garbage from the codec latency is skipped.
+        assert!(matches(&pattern, &[0, 0, 7, 1, 2, 3, 4, 0]));
+        // A broken sequence restarts at the current word.
+        assert!(matches(&pattern, &[1, 2, 1, 2, 3, 4]));
+
+        assert!(!matches(&pattern, &[1, 2, 3]));
+        assert!(!matches(&pattern, &[1, 2, 0, 3, 4]));
+        assert!(!matches(&pattern, &[4, 3, 2, 1]));
+    }
+
+    #[test]
+    fn ringbuffer_error_mapping() {
+        assert_eq!(Error::from(ringbuffer::Error::Overrun), Error::Overrun);
+        assert_eq!(Error::from(ringbuffer::Error::DmaUnsynced), Error::DmaError);
+    }
+
+    #[test]
+    fn spi_error_mapping() {
+        assert_eq!(Error::from(crate::spi::Error::Framing), Error::Framing);
+        assert_eq!(Error::from(crate::spi::Error::Overrun), Error::Overrun);
+        assert_eq!(Error::from(crate::spi::Error::Crc), Error::InvalidConfig);
+        assert_eq!(Error::from(crate::spi::Error::ModeFault), Error::InvalidConfig);
+    }
+
+    #[test]
+    fn status_error_mapping() {
+        assert_eq!(status_error(false, false, false), Ok(()));
+        assert_eq!(status_error(true, true, true), Err(Error::Overrun));
+        assert_eq!(status_error(false, true, true), Err(Error::Underrun));
+        assert_eq!(status_error(false, false, true), Err(Error::Framing));
+    }
+
+    #[test]
+    fn stats_saturate() {
+        let mut stats = I2sStats {
+            overruns: u32::MAX - 1,
+            underruns: 0,
+            bytes_transferred: u64::MAX - 4,
+        };
+        stats.record(&Err(Error::Overrun), 8);
+        stats.record(&Err(Error::Overrun), 8);
+        stats.record(&Err(Error::Underrun), 8);
+        stats.record(&Err(Error::NotATransmitter), 8);
+        stats.record(&Ok(()), 8);
+
+        assert_eq!(
+            stats,
+            I2sStats {
+                overruns: u32::MAX,
+                underruns: 1,
+                bytes_transferred: u64::MAX,
+            }
+        );
+    }
+}
@@ -10,6 +10,9 @@
 //! ## Feature flags
 #![doc = document_features::document_features!(feature_label = r#"<span class="stab portability"><code>{feature}</code></span>"#)]
 
+#[cfg(any(test, feature = "mock"))]
+extern crate alloc;
+
 // This must go FIRST so that all the other modules see its macros.
 mod fmt;
 include!(concat!(env!("OUT_DIR"), "/_macros.rs"));
@@ -632,48 +632,7 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
-        // This can race, so do a retry loop.
-        let reg = T::regs().epr(ep_addr.index() as _);
-        match ep_addr.direction() {
-            Direction::In => {
-                loop {
-                    let r = reg.read();
-                    match r.stat_tx() {
-                        Stat::DISABLED => break, // if disabled, stall does nothing.
-                        Stat::STALL => break,    // done!
-                        _ => {
-                            let want_stat = match stalled {
-                                false => Stat::NAK,
-                                true => Stat::STALL,
-                            };
-                            let mut w = invariant(r);
-                            w.set_stat_tx(Stat::from_bits(r.stat_tx().to_bits() ^ want_stat.to_bits()));
-                            reg.write_value(w);
-                        }
-                    }
-                }
-                EP_IN_WAKERS[ep_addr.index()].wake();
-            }
-            Direction::Out => {
-                loop {
-                    let r = reg.read();
-                    match r.stat_rx() {
-                        Stat::DISABLED => break, // if disabled, stall does nothing.
-                        Stat::STALL => break,    // done!
-                        _ => {
-                            let want_stat = match stalled {
-                                false => Stat::VALID,
-                                true => Stat::STALL,
-                            };
-                            let mut w = invariant(r);
-                            w.set_stat_rx(Stat::from_bits(r.stat_rx().to_bits() ^ want_stat.to_bits()));
-                            reg.write_value(w);
-                        }
-                    }
-                }
-                EP_OUT_WAKERS[ep_addr.index()].wake();
-            }
-        }
+        set_stalled::<T>(ep_addr, stalled);
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
@@ -771,6 +730,51 @@ enum PacketBuffer {
     Tx,
 }
 
+fn set_stalled<T: Instance>(ep_addr: EndpointAddress, stalled: bool) {
+    // This can race, so do a retry loop.
+    let reg = T::regs().epr(ep_addr.index() as _);
+    match ep_addr.direction() {
+        Direction::In => {
+            loop {
+                let r = reg.read();
+                match r.stat_tx() {
+                    Stat::DISABLED => break, // if disabled, stall does nothing.
+                    Stat::STALL => break,    // done!
+                    _ => {
+                        let want_stat = match stalled {
+                            false => Stat::NAK,
+                            true => Stat::STALL,
+                        };
+                        let mut w = invariant(r);
+                        w.set_stat_tx(Stat::from_bits(r.stat_tx().to_bits() ^ want_stat.to_bits()));
+                        reg.write_value(w);
+                    }
+                }
+            }
+            EP_IN_WAKERS[ep_addr.index()].wake();
+        }
+        Direction::Out => {
+            loop {
+                let r = reg.read();
+                match r.stat_rx() {
+                    Stat::DISABLED => break, // if disabled, stall does nothing.
+                    Stat::STALL => break,    // done!
+                    _ => {
+                        let want_stat = match stalled {
+                            false => Stat::VALID,
+                            true => Stat::STALL,
+                        };
+                        let mut w = invariant(r);
+                        w.set_stat_rx(Stat::from_bits(r.stat_rx().to_bits() ^ want_stat.to_bits()));
+                        reg.write_value(w);
+                    }
+                }
+            }
+            EP_OUT_WAKERS[ep_addr.index()].wake();
+        }
+    }
+}
+
 /// USB endpoint.
 pub struct Endpoint<'d, T: Instance, D> {
     _phantom: PhantomData<(&'d mut T, D)>,
@@ -853,6 +857,11 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
         .await;
         trace!("wait_enabled IN OK");
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled::<T>(self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
@@ -875,6 +884,11 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
         .await;
         trace!("wait_enabled OUT OK");
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled::<T>(self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
@@ -227,6 +227,19 @@ pub trait Endpoint {
 
     /// Wait for the endpoint to be enabled.
     async fn wait_enabled(&mut self);
+
+    /// Set or clear the STALL condition of the endpoint, like [`Bus::endpoint_set_stalled`].
+    ///
+    /// Classes use this to signal errors on their data endpoints. The host clears the condition
+    /// with a `CLEAR_FEATURE(ENDPOINT_HALT)` request.
+    ///
+    /// # Errors
+    ///
+    /// * [`Unsupported`] - This driver can't stall endpoints without the bus. This is the default.
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        let _ = stalled;
+        Err(Unsupported)
+    }
 }
 
 /// OUT Endpoint trait.
@@ -877,28 +877,8 @@ impl<'d, const MAX_EP_COUNT: usize> embassy_usb_driver::Bus for Bus<'d, MAX_EP_C
             ep_addr.index()
         );
 
-        let regs = self.instance.regs;
-        let state = self.instance.state;
-        match ep_addr.direction() {
-            Direction::Out => {
-                critical_section::with(|_| {
-                    regs.doepctl(ep_addr.index()).modify(|w| {
-                        w.set_stall(stalled);
-                    });
-                });
-
-                state.ep_states[ep_addr.index()].out_waker.wake();
-            }
-            Direction::In => {
-                critical_section::with(|_| {
-                    regs.diepctl(ep_addr.index()).modify(|w| {
-                        w.set_stall(stalled);
-                    });
-                });
-
-                state.ep_states[ep_addr.index()].in_waker.wake();
-            }
-        }
+        let state = &self.instance.state.ep_states[ep_addr.index()];
+        set_stalled(self.instance.regs, state, ep_addr, stalled);
     }
 
     fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
@@ -1045,6 +1025,11 @@ impl<'d> embassy_usb_driver::Endpoint for Endpoint<'d, In> {
         })
         .await
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled(self.regs, self.state, self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d> embassy_usb_driver::Endpoint for Endpoint<'d, Out> {
@@ -1066,6 +1051,11 @@ impl<'d> embassy_usb_driver::Endpoint for Endpoint<'d, Out> {
         })
         .await
     }
+
+    fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+        set_stalled(self.regs, self.state, self.info.addr, stalled);
+        Ok(())
+    }
 }
 
 impl<'d> embassy_usb_driver::EndpointOut for Endpoint<'d, Out> {
@@ -1353,6 +1343,30 @@ impl<'d> embassy_usb_driver::ControlPipe for ControlPipe<'d> {
     }
 }
 
+/// Sets or clears the STALL bit of an endpoint and wakes its pending transfers
+fn set_stalled(regs: Otg, state: &EpState, ep_addr: EndpointAddress, stalled: bool) {
+    match ep_addr.direction() {
+        Direction::Out => {
+            critical_section::with(|_| {
+                regs.doepctl(ep_addr.index()).modify(|w| {
+                    w.set_stall(stalled);
+                });
+            });
+
+            state.out_waker.wake();
+        }
+        Direction::In => {
+            critical_section::with(|_| {
+                regs.diepctl(ep_addr.index()).modify(|w| {
+                    w.set_stall(stalled);
+                });
+            });
+
+            state.in_waker.wake();
+        }
+    }
+}
+
 /// Translates HAL [EndpointType] into PAC [vals::Eptyp]
 fn to_eptyp(ep_type: EndpointType) -> vals::Eptyp {
     match ep_type {
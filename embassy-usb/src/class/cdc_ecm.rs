@@ -0,0 +1,523 @@
+//! CDC-ECM class implementation, aka Ethernet over USB.
+//!
+//! ECM transfers each Ethernet frame as one bulk transfer, without the NTB aggregation of CDC-NCM.
+//! This makes it simpler and slower than NCM.
+//!
+//! # Compatibility
+//!
+//! Windows: NOT supported, there is no built-in driver. Use [CDC-NCM](crate::class::cdc_ncm) instead.
+//!
+//! Linux: Well-supported since forever.
+//!
+//! macOS: Supported out of the box.
+
+use core::mem::MaybeUninit;
+
+use crate::control::{self, InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::types::{InterfaceNumber, StringIndex};
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_CDC: u8 = 0x02;
+
+const USB_CLASS_CDC_DATA: u8 = 0x0a;
+const CDC_SUBCLASS_ECM: u8 = 0x06;
+
+const CDC_PROTOCOL_NONE: u8 = 0x00;
+
+const CS_INTERFACE: u8 = 0x24;
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_UNION: u8 = 0x06;
+const CDC_TYPE_ETHERNET: u8 = 0x0F;
+
+const REQ_SET_ETHERNET_MULTICAST_FILTERS: u8 = 0x40;
+//const REQ_SET_ETHERNET_POWER_MANAGEMENT_PATTERN_FILTER: u8 = 0x41;
+//const REQ_GET_ETHERNET_POWER_MANAGEMENT_PATTERN_FILTER: u8 = 0x42;
+const REQ_SET_ETHERNET_PACKET_FILTER: u8 = 0x43;
+//const REQ_GET_ETHERNET_STATISTIC: u8 = 0x44;
+
+/// Maximum Ethernet frame size without FCS, as announced in the Ethernet functional descriptor.
+pub const MAX_SEGMENT_SIZE: usize = 1514;
+
+/// Largest bulk max packet size, for high speed.
+const MAX_BULK_PACKET_SIZE: usize = 512;
+
+const ALTERNATE_SETTING_DISABLED: u8 = 0x00;
+const ALTERNATE_SETTING_ENABLED: u8 = 0x01;
+
+/// Internal state for the CDC-ECM class.
+pub struct State<'a> {
+    control: MaybeUninit<Control<'a>>,
+    shared: ControlShared,
+}
+
+impl<'a> Default for State<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> State<'a> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: ControlShared::default(),
+        }
+    }
+}
+
+/// Shared data between Control and `CdcEcmClass`
+#[derive(Default)]
+struct ControlShared {
+    mac_addr: [u8; 6],
+}
+
+struct Control<'a> {
+    mac_addr_string: StringIndex,
+    shared: &'a ControlShared,
+    mac_addr_str: [u8; 12],
+    comm_if: InterfaceNumber,
+    data_if: InterfaceNumber,
+}
+
+impl<'d> Handler for Control<'d> {
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        if iface != self.data_if {
+            return;
+        }
+
+        match alternate_setting {
+            ALTERNATE_SETTING_ENABLED => info!("ecm: interface enabled"),
+            ALTERNATE_SETTING_DISABLED => info!("ecm: interface disabled"),
+            _ => warn!("ecm: unknown alternate setting {}", alternate_setting),
+        }
+    }
+
+    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.comm_if.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_SET_ETHERNET_PACKET_FILTER => {
+                // All frames are passed to the application, which filters them itself.
+                Some(OutResponse::Accepted)
+            }
+            REQ_SET_ETHERNET_MULTICAST_FILTERS => {
+                // wNumberMCFilters is 0, but some hosts send this anyway.
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, _buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.comm_if.0 as u16)
+        {
+            return None;
+        }
+
+        // No statistics or power management filters are supported.
+        Some(InResponse::Rejected)
+    }
+
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        if index == self.mac_addr_string {
+            let mac_addr = self.shared.mac_addr;
+            let s = &mut self.mac_addr_str;
+            for i in 0..12 {
+                let n = (mac_addr[i / 2] >> ((1 - i % 2) * 4)) & 0xF;
+                s[i] = match n {
+                    0x0..=0x9 => b'0' + n,
+                    0xA..=0xF => b'A' + n - 0xA,
+                    _ => unreachable!(),
+                }
+            }
+
+            Some(unsafe { core::str::from_utf8_unchecked(s) })
+        } else {
+            warn!("unknown string index requested");
+            None
+        }
+    }
+}
+
+/// CDC-ECM class
+pub struct CdcEcmClass<'d, D: Driver<'d>> {
+    _comm_if: InterfaceNumber,
+    comm_ep: D::EndpointIn,
+
+    data_if: InterfaceNumber,
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+
+    _control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> CdcEcmClass<'d, D> {
+    /// Create a new CDC ECM class.
+    ///
+    /// `mac_address` is the MAC address the host uses for its side of the link, not the one of the
+    /// device's network stack.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+    ) -> Self {
+        state.shared.mac_addr = mac_address;
+
+        let mut func = builder.function(USB_CLASS_CDC, CDC_SUBCLASS_ECM, CDC_PROTOCOL_NONE);
+
+        // Control interface
+        let mut iface = func.interface();
+        let mac_addr_string = iface.string();
+        let comm_if = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_CDC, CDC_SUBCLASS_ECM, CDC_PROTOCOL_NONE, None);
+
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_HEADER, // bDescriptorSubtype
+                0x10,
+                0x01, // bcdCDC (1.10)
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_UNION,        // bDescriptorSubtype
+                comm_if.into(),        // bControlInterface
+                u8::from(comm_if) + 1, // bSubordinateInterface
+            ],
+        );
+        alt.descriptor(
+            CS_INTERFACE,
+            &[
+                CDC_TYPE_ETHERNET,      // bDescriptorSubtype
+                mac_addr_string.into(), // iMACAddress
+                0,                      // bmEthernetStatistics
+                0,                      // |
+                0,                      // |
+                0,                      // |
+                0xea,                   // wMaxSegmentSize = 1514
+                0x05,                   // |
+                0,                      // wNumberMCFilters
+                0,                      // |
+                0,                      // bNumberPowerFilters
+            ],
+        );
+
+        let comm_ep = alt.endpoint_interrupt_in(None, 16, 255);
+
+        // Data interface
+        let mut iface = func.interface();
+        let data_if = iface.interface_number();
+        let _alt = iface.alt_setting(USB_CLASS_CDC_DATA, 0x00, CDC_PROTOCOL_NONE, None);
+        let mut alt = iface.alt_setting(USB_CLASS_CDC_DATA, 0x00, CDC_PROTOCOL_NONE, None);
+        let read_ep = alt.endpoint_bulk_out(None, max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(None, max_packet_size);
+
+        drop(func);
+
+        let control = state.control.write(Control {
+            mac_addr_string,
+            shared: &state.shared,
+            mac_addr_str: [0; 12],
+            comm_if,
+            data_if,
+        });
+        builder.handler(control);
+
+        CdcEcmClass {
+            _comm_if: comm_if,
+            comm_ep,
+            data_if,
+            read_ep,
+            write_ep,
+            _control: &state.shared,
+        }
+    }
+
+    /// Split the class into a sender and receiver.
+    ///
+    /// This allows concurrently sending and receiving packets from separate tasks.
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
+        (
+            Sender {
+                write_ep: self.write_ep,
+            },
+            Receiver {
+                data_if: self.data_if,
+                comm_ep: self.comm_ep,
+                read_ep: self.read_ep,
+            },
+        )
+    }
+}
+
+/// CDC ECM class packet sender.
+///
+/// You can obtain a `Sender` with [`CdcEcmClass::split`]
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Write an Ethernet frame.
+    ///
+    /// This waits until the frame is successfully stored in the CDC-ECM endpoint buffers. A frame
+    /// longer than [`MAX_SEGMENT_SIZE`] fails with [`EndpointError::BufferOverflow`] and is not sent.
+    pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        write_frame(&mut self.write_ep, data).await
+    }
+}
+
+/// CDC ECM class packet receiver.
+///
+/// You can obtain a `Receiver` with [`CdcEcmClass::split`]
+pub struct Receiver<'d, D: Driver<'d>> {
+    data_if: InterfaceNumber,
+    comm_ep: D::EndpointIn,
+    read_ep: D::EndpointOut,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    /// Read an Ethernet frame.
+    ///
+    /// This waits until a frame is successfully received from the endpoint buffers. `buf` should
+    /// hold at least [`MAX_SEGMENT_SIZE`] bytes, a longer frame fails with
+    /// [`EndpointError::BufferOverflow`]. The rest of such a frame is discarded, so the next call
+    /// returns the next frame.
+    pub async fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        read_frame(&mut self.read_ep, buf).await
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) -> Result<(), EndpointError> {
+        loop {
+            self.read_ep.wait_enabled().await;
+            self.comm_ep.wait_enabled().await;
+
+            let buf = [
+                0xA1, //bmRequestType
+                0x00, //bNotificationType = NETWORK_CONNECTION
+                0x01, // wValue = connected
+                0x00,
+                self.data_if.into(), // wIndex = interface
+                0x00,
+                0x00, // wLength
+                0x00,
+            ];
+            match self.comm_ep.write(&buf).await {
+                Ok(()) => break,                   // Done!
+                Err(EndpointError::Disabled) => {} // Got disabled again, wait again.
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Write one frame, ending it with a short packet or ZLP.
+async fn write_frame<E: EndpointIn>(ep: &mut E, data: &[u8]) -> Result<(), EndpointError> {
+    // The host doesn't expect frames longer than announced in the Ethernet functional descriptor.
+    if data.len() > MAX_SEGMENT_SIZE {
+        return Err(EndpointError::BufferOverflow);
+    }
+
+    let max_packet_size = ep.info().max_packet_size as usize;
+
+    for chunk in data.chunks(max_packet_size) {
+        ep.write(chunk).await?;
+    }
+
+    // A frame ends with a short packet, so send a ZLP if the last one was full.
+    if data.len() % max_packet_size == 0 {
+        ep.write(&[]).await?;
+    }
+
+    Ok(())
+}
+
+// Read the packets of one frame, up to and including the terminating short packet or ZLP.
+async fn read_frame<E: EndpointOut>(ep: &mut E, buf: &mut [u8]) -> Result<usize, EndpointError> {
+    let max_packet_size = ep.info().max_packet_size as usize;
+    let mut scratch = [0; MAX_BULK_PACKET_SIZE];
+
+    let mut pos = 0;
+    let mut overflow = false;
+    loop {
+        let space = buf.len() - pos;
+        let n = if space >= max_packet_size {
+            let n = ep.read(&mut buf[pos..]).await?;
+            pos += n;
+            n
+        } else {
+            // Read packets that may not fit into `buf` into a scratch buffer instead, so the rest of
+            // an oversized frame is drained and the next read starts at a frame boundary.
+            let n = ep.read(&mut scratch[..max_packet_size]).await?;
+            let fit = n.min(space);
+            buf[pos..pos + fit].copy_from_slice(&scratch[..fit]);
+            pos += fit;
+            overflow |= fit < n;
+            n
+        };
+        if n < max_packet_size {
+            break;
+        }
+    }
+
+    match overflow {
+        true => Err(EndpointError::BufferOverflow),
+        false => Ok(pos),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::driver::{Direction, EndpointAddress, EndpointInfo, EndpointType};
+
+    /// OUT endpoint that receives the given packets.
+    struct MockEndpointOut<'a> {
+        info: EndpointInfo,
+        packets: &'a [&'a [u8]],
+    }
+
+    impl<'a> MockEndpointOut<'a> {
+        fn new(max_packet_size: u16, packets: &'a [&'a [u8]]) -> Self {
+            Self {
+                info: EndpointInfo {
+                    addr: EndpointAddress::from_parts(1, Direction::Out),
+                    ep_type: EndpointType::Bulk,
+                    max_packet_size,
+                    interval_ms: 0,
+                },
+                packets,
+            }
+        }
+    }
+
+    impl Endpoint for MockEndpointOut<'_> {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointOut for MockEndpointOut<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+            let (packet, rest) = self.packets.split_first().ok_or(EndpointError::Disabled)?;
+            self.packets = rest;
+            if packet.len() > buf.len() {
+                return Err(EndpointError::BufferOverflow);
+            }
+            buf[..packet.len()].copy_from_slice(packet);
+            Ok(packet.len())
+        }
+    }
+
+    /// IN endpoint that records the lengths of the packets it sends.
+    struct MockEndpointIn {
+        info: EndpointInfo,
+        sent: heapless::Vec<usize, 32>,
+    }
+
+    impl MockEndpointIn {
+        fn new(max_packet_size: u16) -> Self {
+            Self {
+                info: EndpointInfo {
+                    addr: EndpointAddress::from_parts(1, Direction::In),
+                    ep_type: EndpointType::Bulk,
+                    max_packet_size,
+                    interval_ms: 0,
+                },
+                sent: heapless::Vec::new(),
+            }
+        }
+    }
+
+    impl Endpoint for MockEndpointIn {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointIn for MockEndpointIn {
+        async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+            self.sent.push(buf.len()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_max_segment_size_frame() {
+        let mut ep = MockEndpointIn::new(64);
+        let frame = [0; MAX_SEGMENT_SIZE];
+
+        assert_eq!(block_on(write_frame(&mut ep, &frame)), Ok(()));
+        assert_eq!(ep.sent.len(), 24);
+        assert_eq!(ep.sent[23], MAX_SEGMENT_SIZE % 64);
+    }
+
+    #[test]
+    fn write_oversize_frame_fails() {
+        let mut ep = MockEndpointIn::new(64);
+        let frame = [0; MAX_SEGMENT_SIZE + 1];
+
+        assert_eq!(
+            block_on(write_frame(&mut ep, &frame)),
+            Err(EndpointError::BufferOverflow)
+        );
+        assert!(ep.sent.is_empty());
+    }
+
+    #[test]
+    fn exact_fit_frame() {
+        let mut ep = MockEndpointOut::new(4, &[&[1, 2, 3, 4], &[5, 6], &[7]]);
+        let mut buf = [0; 6];
+
+        assert_eq!(block_on(read_frame(&mut ep, &mut buf)), Ok(6));
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(ep.packets, [&[7]]);
+    }
+
+    #[test]
+    fn max_packet_size_multiple_frame() {
+        let mut ep = MockEndpointOut::new(4, &[&[1, 2, 3, 4], &[5, 6, 7, 8], &[], &[9]]);
+        let mut buf = [0; 8];
+
+        assert_eq!(block_on(read_frame(&mut ep, &mut buf)), Ok(8));
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+        // The terminating ZLP is consumed with the frame.
+        assert_eq!(ep.packets, [&[9]]);
+    }
+
+    #[test]
+    fn oversize_frame_is_drained() {
+        let mut ep = MockEndpointOut::new(4, &[&[1, 2, 3, 4], &[5, 6, 7, 8], &[9, 10, 11, 12], &[13], &[14]]);
+        let mut buf = [0; 6];
+
+        assert_eq!(
+            block_on(read_frame(&mut ep, &mut buf)),
+            Err(EndpointError::BufferOverflow)
+        );
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+
+        let mut buf = [0; 6];
+        assert_eq!(block_on(read_frame(&mut ep, &mut buf)), Ok(1));
+        assert_eq!(buf[..1], [14]);
+    }
+}
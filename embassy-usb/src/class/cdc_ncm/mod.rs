@@ -16,6 +16,7 @@
 
 use core::mem::{size_of, MaybeUninit};
 use core::ptr::{addr_of, copy_nonoverlapping};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use crate::control::{self, InResponse, OutResponse, Recipient, Request, RequestType};
 use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
@@ -61,7 +62,8 @@ const REQ_SET_NTB_INPUT_SIZE: u8 = 0x86;
 //const NOTIF_MAX_PACKET_SIZE: u16 = 8;
 //const NOTIF_POLL_INTERVAL: u8 = 20;
 
-const NTB_MAX_SIZE: usize = 2048;
+/// Default maximum NTB size in both directions, see [`State`].
+pub const NTB_MAX_SIZE: usize = 2048;
 const SIG_NTH: u32 = 0x484d_434e;
 const SIG_NDP_NO_FCS: u32 = 0x304d_434e;
 const SIG_NDP_WITH_FCS: u32 = 0x314d_434e;
@@ -69,26 +71,9 @@ const SIG_NDP_WITH_FCS: u32 = 0x314d_434e;
 const ALTERNATE_SETTING_DISABLED: u8 = 0x00;
 const ALTERNATE_SETTING_ENABLED: u8 = 0x01;
 
-/// Simple NTB header (NTH+NDP all in one) for sending packets
-#[repr(packed)]
-#[allow(unused)]
-struct NtbOutHeader {
-    // NTH
-    nth_sig: u32,
-    nth_len: u16,
-    nth_seq: u16,
-    nth_total_len: u16,
-    nth_first_index: u16,
-
-    // NDP
-    ndp_sig: u32,
-    ndp_len: u16,
-    ndp_next_index: u16,
-    ndp_datagram_index: u16,
-    ndp_datagram_len: u16,
-    ndp_term1: u16,
-    ndp_term2: u16,
-}
+const NTH_LEN: usize = 12;
+const NDP_HEADER_LEN: usize = 8;
+const NDP_ENTRY_LEN: usize = 4;
 
 #[repr(packed)]
 #[allow(unused)]
@@ -116,31 +101,46 @@ fn byteify<T>(buf: &mut [u8], data: T) -> &[u8] {
 }
 
 /// Internal state for the CDC-NCM class.
-pub struct State<'a> {
+///
+/// `NTB_IN` and `NTB_OUT` are the maximum NTB (NCM Transfer Block) sizes announced to the host as
+/// `dwNtbInMaxSize` and `dwNtbOutMaxSize`, and the size of the buffers that hold one NTB. Larger
+/// NTBs aggregate more datagrams per transfer on the IN path, which raises throughput. Use
+/// [`CdcNcmClass::new_with_ntb_sizes`] for sizes other than the default [`NTB_MAX_SIZE`].
+///
+/// Both NTB buffers are part of the `State`, so it is at least `NTB_IN + NTB_OUT` bytes large,
+/// 4 KiB with the default sizes. Place it in a `static`, e.g. with `StaticCell`, rather than on
+/// the stack of a task.
+pub struct State<'a, const NTB_IN: usize = NTB_MAX_SIZE, const NTB_OUT: usize = NTB_MAX_SIZE> {
     control: MaybeUninit<Control<'a>>,
     shared: ControlShared,
+    ntb_in: [u8; NTB_IN],
+    ntb_out: [u8; NTB_OUT],
 }
 
-impl<'a> Default for State<'a> {
+impl<'a, const NTB_IN: usize, const NTB_OUT: usize> Default for State<'a, NTB_IN, NTB_OUT> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<'a> State<'a> {
+impl<'a, const NTB_IN: usize, const NTB_OUT: usize> State<'a, NTB_IN, NTB_OUT> {
     /// Create a new `State`.
     pub fn new() -> Self {
         Self {
             control: MaybeUninit::uninit(),
             shared: ControlShared::default(),
+            ntb_in: [0; NTB_IN],
+            ntb_out: [0; NTB_OUT],
         }
     }
 }
 
-/// Shared data between Control and `CdcAcmClass`
+/// Shared data between Control and `CdcNcmClass`
 #[derive(Default)]
 struct ControlShared {
     mac_addr: [u8; 6],
+    /// IN NTB size selected by the host with `SET_NTB_INPUT_SIZE`.
+    ntb_in_size: AtomicU32,
 }
 
 struct Control<'a> {
@@ -149,6 +149,8 @@ struct Control<'a> {
     mac_addr_str: [u8; 12],
     comm_if: InterfaceNumber,
     data_if: InterfaceNumber,
+    ntb_in_max_size: u32,
+    ntb_out_max_size: u32,
 }
 
 impl<'d> Handler for Control<'d> {
@@ -159,12 +161,16 @@ impl<'d> Handler for Control<'d> {
 
         match alternate_setting {
             ALTERNATE_SETTING_ENABLED => info!("ncm: interface enabled"),
-            ALTERNATE_SETTING_DISABLED => info!("ncm: interface disabled"),
+            ALTERNATE_SETTING_DISABLED => {
+                info!("ncm: interface disabled");
+                // The IN NTB size selected by the host only lasts until the data interface is reset.
+                self.shared.ntb_in_size.store(self.ntb_in_max_size, Ordering::Relaxed);
+            }
             _ => unreachable!(),
         }
     }
 
-    fn control_out(&mut self, req: control::Request, _data: &[u8]) -> Option<OutResponse> {
+    fn control_out(&mut self, req: control::Request, data: &[u8]) -> Option<OutResponse> {
         if (req.request_type, req.recipient, req.index)
             != (RequestType::Class, Recipient::Interface, self.comm_if.0 as u16)
         {
@@ -178,7 +184,15 @@ impl<'d> Handler for Control<'d> {
                 Some(OutResponse::Accepted)
             }
             REQ_SET_NTB_INPUT_SIZE => {
-                // TODO
+                // dwNtbInMaxSize, optionally followed by wNtbInMaxDatagrams which we don't limit.
+                let Some(size) = data.get(..4) else {
+                    return Some(OutResponse::Rejected);
+                };
+                let size = u32::from_le_bytes(size.try_into().unwrap());
+                if size < (NTH_LEN + NDP_HEADER_LEN + 2 * NDP_ENTRY_LEN) as u32 || size > self.ntb_in_max_size {
+                    return Some(OutResponse::Rejected);
+                }
+                self.shared.ntb_in_size.store(size, Ordering::Relaxed);
                 Some(OutResponse::Accepted)
             }
             _ => Some(OutResponse::Rejected),
@@ -198,14 +212,14 @@ impl<'d> Handler for Control<'d> {
                     length: size_of::<NtbParameters>() as _,
                     formats_supported: 1, // only 16bit,
                     in_params: NtbParametersDir {
-                        max_size: NTB_MAX_SIZE as _,
+                        max_size: self.ntb_in_max_size,
                         divisor: 4,
                         payload_remainder: 0,
                         out_alignment: 4,
                         max_datagram_count: 0, // not used
                     },
                     out_params: NtbParametersDir {
-                        max_size: NTB_MAX_SIZE as _,
+                        max_size: self.ntb_out_max_size,
                         divisor: 4,
                         payload_remainder: 0,
                         out_alignment: 4,
@@ -248,20 +262,41 @@ pub struct CdcNcmClass<'d, D: Driver<'d>> {
     read_ep: D::EndpointOut,
     write_ep: D::EndpointIn,
 
-    _control: &'d ControlShared,
+    control: &'d ControlShared,
+    ntb_in: &'d mut [u8],
+    ntb_out: &'d mut [u8],
 
     max_packet_size: usize,
 }
 
 impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
     /// Create a new CDC NCM class.
+    ///
+    /// Uses NTBs of the default [`NTB_MAX_SIZE`], whose buffers make `state` 4 KiB large, see
+    /// [`State`].
     pub fn new(
         builder: &mut Builder<'d, D>,
         state: &'d mut State<'d>,
         mac_address: [u8; 6],
         max_packet_size: u16,
     ) -> Self {
+        Self::new_with_ntb_sizes(builder, state, mac_address, max_packet_size)
+    }
+
+    /// Create a new CDC NCM class with the maximum NTB sizes given by the `State` type.
+    ///
+    /// The host picks the IN NTB size it uses, up to `NTB_IN`, see [`Sender::ntb_in_size`].
+    /// NTB16 lengths are 16 bit, so both sizes must be at most 65535 bytes.
+    pub fn new_with_ntb_sizes<const NTB_IN: usize, const NTB_OUT: usize>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d, NTB_IN, NTB_OUT>,
+        mac_address: [u8; 6],
+        max_packet_size: u16,
+    ) -> Self {
+        const { ::core::assert!(NTB_IN <= u16::MAX as usize && NTB_OUT <= u16::MAX as usize) };
+
         state.shared.mac_addr = mac_address;
+        state.shared.ntb_in_size.store(NTB_IN as u32, Ordering::Relaxed);
 
         let mut func = builder.function(USB_CLASS_CDC, CDC_SUBCLASS_NCM, CDC_PROTOCOL_NONE);
 
@@ -331,6 +366,8 @@ impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
             mac_addr_str: [0; 12],
             comm_if,
             data_if,
+            ntb_in_max_size: NTB_IN as u32,
+            ntb_out_max_size: NTB_OUT as u32,
         });
         builder.handler(control);
 
@@ -340,7 +377,9 @@ impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
             data_if,
             read_ep,
             write_ep,
-            _control: &state.shared,
+            control: &state.shared,
+            ntb_in: &mut state.ntb_in,
+            ntb_out: &mut state.ntb_out,
             max_packet_size: max_packet_size as usize,
         }
     }
@@ -354,11 +393,14 @@ impl<'d, D: Driver<'d>> CdcNcmClass<'d, D> {
                 write_ep: self.write_ep,
                 seq: 0,
                 max_packet_size: self.max_packet_size,
+                control: self.control,
+                ntb: self.ntb_in,
             },
             Receiver {
                 data_if: self.data_if,
                 comm_ep: self.comm_ep,
                 read_ep: self.read_ep,
+                ntb: self.ntb_out,
             },
         )
     }
@@ -371,6 +413,8 @@ pub struct Sender<'d, D: Driver<'d>> {
     write_ep: D::EndpointIn,
     seq: u16,
     max_packet_size: usize,
+    control: &'d ControlShared,
+    ntb: &'d mut [u8],
 }
 
 impl<'d, D: Driver<'d>> Sender<'d, D> {
@@ -378,56 +422,99 @@ impl<'d, D: Driver<'d>> Sender<'d, D> {
     ///
     /// This waits until the packet is successfully stored in the CDC-NCM endpoint buffers.
     pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
-        const OUT_HEADER_LEN: usize = 28;
-        const ABS_MAX_PACKET_SIZE: usize = 512;
-
-        let seq = self.seq;
-        self.seq = self.seq.wrapping_add(1);
-
-        let header = NtbOutHeader {
-            nth_sig: SIG_NTH,
-            nth_len: 0x0c,
-            nth_seq: seq,
-            nth_total_len: (data.len() + OUT_HEADER_LEN) as u16,
-            nth_first_index: 0x0c,
-
-            ndp_sig: SIG_NDP_NO_FCS,
-            ndp_len: 0x10,
-            ndp_next_index: 0x00,
-            ndp_datagram_index: OUT_HEADER_LEN as u16,
-            ndp_datagram_len: data.len() as u16,
-            ndp_term1: 0x00,
-            ndp_term2: 0x00,
-        };
-
-        // Build first packet on a buffer, send next packets straight from `data`.
-        let mut buf = [0; ABS_MAX_PACKET_SIZE];
-        let n = byteify(&mut buf, header);
-        assert_eq!(n.len(), OUT_HEADER_LEN);
-
-        if OUT_HEADER_LEN + data.len() < self.max_packet_size {
-            // First packet is not full, just send it.
-            // No need to send ZLP because it's short for sure.
-            buf[OUT_HEADER_LEN..][..data.len()].copy_from_slice(data);
-            self.write_ep.write(&buf[..OUT_HEADER_LEN + data.len()]).await?;
-        } else {
-            let (d1, d2) = data.split_at(self.max_packet_size - OUT_HEADER_LEN);
+        self.write_packets(&[data]).await
+    }
 
-            buf[OUT_HEADER_LEN..self.max_packet_size].copy_from_slice(d1);
-            self.write_ep.write(&buf[..self.max_packet_size]).await?;
+    /// Write several packets, aggregating as many of them as fit into each NTB.
+    ///
+    /// Sending several datagrams in one transfer is considerably faster than sending them one by
+    /// one. This waits until all packets are stored in the CDC-NCM endpoint buffers. Fails with
+    /// [`EndpointError::BufferOverflow`] if a packet doesn't fit into an NTB on its own, packets
+    /// before it have been sent then.
+    pub async fn write_packets(&mut self, packets: &[&[u8]]) -> Result<(), EndpointError> {
+        let mut packets = packets;
+        while !packets.is_empty() {
+            let size = self.ntb_in_size().min(self.ntb.len());
+            let (len, count) = build_ntb(self.seq, packets, &mut self.ntb[..size]);
+            if count == 0 {
+                return Err(EndpointError::BufferOverflow);
+            }
+            self.seq = self.seq.wrapping_add(1);
+            packets = &packets[count..];
 
-            for chunk in d2.chunks(self.max_packet_size) {
+            let ntb = &self.ntb[..len];
+            for chunk in ntb.chunks(self.max_packet_size) {
                 self.write_ep.write(chunk).await?;
             }
-
             // Send ZLP if needed.
-            if d2.len() % self.max_packet_size == 0 {
+            if len % self.max_packet_size == 0 {
                 self.write_ep.write(&[]).await?;
             }
         }
 
         Ok(())
     }
+
+    /// Maximum IN NTB size, as selected by the host with `SET_NTB_INPUT_SIZE`.
+    ///
+    /// This is the NTB size of the `State` until the host selects a different one.
+    pub fn ntb_in_size(&self) -> usize {
+        self.control.ntb_in_size.load(Ordering::Relaxed) as usize
+    }
+}
+
+/// Build an NTB16 holding as many of `datagrams` as fit into `ntb`, each aligned to 4 bytes.
+///
+/// Returns the length of the NTB and the number of datagrams in it.
+fn build_ntb(seq: u16, datagrams: &[&[u8]], ntb: &mut [u8]) -> (usize, usize) {
+    const fn align4(n: usize) -> usize {
+        (n + 3) & !3
+    }
+
+    let ndp_len = |count: usize| NDP_HEADER_LEN + NDP_ENTRY_LEN * (count + 1);
+    let ntb_len = |count: usize| {
+        datagrams[..count]
+            .iter()
+            .fold(NTH_LEN + ndp_len(count), |len, d| align4(len) + d.len())
+    };
+    let count = (1..=datagrams.len())
+        .take_while(|&count| ntb_len(count) <= ntb.len())
+        .last()
+        .unwrap_or(0);
+    if count == 0 {
+        return (0, 0);
+    }
+    let len = ntb_len(count);
+
+    // NTH
+    ntb[0..4].copy_from_slice(&SIG_NTH.to_le_bytes());
+    ntb[4..6].copy_from_slice(&(NTH_LEN as u16).to_le_bytes());
+    ntb[6..8].copy_from_slice(&seq.to_le_bytes());
+    ntb[8..10].copy_from_slice(&(len as u16).to_le_bytes());
+    ntb[10..12].copy_from_slice(&(NTH_LEN as u16).to_le_bytes());
+
+    // NDP, right after the NTH
+    let ndp = &mut ntb[NTH_LEN..];
+    ndp[0..4].copy_from_slice(&SIG_NDP_NO_FCS.to_le_bytes());
+    ndp[4..6].copy_from_slice(&(ndp_len(count) as u16).to_le_bytes());
+    ndp[6..8].copy_from_slice(&0u16.to_le_bytes());
+
+    // Datagrams, followed by a zero entry terminating the NDP
+    let mut pos = NTH_LEN + ndp_len(count);
+    for (i, d) in datagrams[..count].iter().enumerate() {
+        let start = align4(pos);
+        ntb[pos..start].fill(0);
+        ntb[start..start + d.len()].copy_from_slice(d);
+        pos = start + d.len();
+
+        let entry = NTH_LEN + NDP_HEADER_LEN + NDP_ENTRY_LEN * i;
+        ntb[entry..entry + 2].copy_from_slice(&(start as u16).to_le_bytes());
+        ntb[entry + 2..entry + 4].copy_from_slice(&(d.len() as u16).to_le_bytes());
+    }
+    let term = NTH_LEN + NDP_HEADER_LEN + NDP_ENTRY_LEN * count;
+    ntb[term..term + NDP_ENTRY_LEN].fill(0);
+
+    (len, count)
 }
 
 /// CDC NCM class packet receiver.
@@ -437,6 +524,7 @@ pub struct Receiver<'d, D: Driver<'d>> {
     data_if: InterfaceNumber,
     comm_ep: D::EndpointIn,
     read_ep: D::EndpointOut,
+    ntb: &'d mut [u8],
 }
 
 impl<'d, D: Driver<'d>> Receiver<'d, D> {
@@ -447,12 +535,12 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
         // Retry loop
         loop {
             // read NTB
-            let mut ntb = [0u8; NTB_MAX_SIZE];
+            let ntb = &mut *self.ntb;
             let mut pos = 0;
             loop {
                 let n = self.read_ep.read(&mut ntb[pos..]).await?;
                 pos += n;
-                if n < self.read_ep.info().max_packet_size as usize || pos == NTB_MAX_SIZE {
+                if n < self.read_ep.info().max_packet_size as usize || pos == ntb.len() {
                     break;
                 }
             }
@@ -500,6 +588,11 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
         }
     }
 
+    /// Maximum OUT NTB size announced to the host, `NTB_OUT` of the `State`.
+    pub fn ntb_out_max_size(&self) -> usize {
+        self.ntb.len()
+    }
+
     /// Waits for the USB host to enable this interface
     pub async fn wait_connection(&mut self) -> Result<(), EndpointError> {
         loop {
@@ -526,3 +619,72 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::Direction;
+
+    #[test]
+    fn ntb_input_size_resets_with_interface() {
+        let shared = ControlShared::default();
+        let mut control = Control {
+            mac_addr_string: StringIndex::new(4),
+            shared: &shared,
+            mac_addr_str: [0; 12],
+            comm_if: InterfaceNumber::new(0),
+            data_if: InterfaceNumber::new(1),
+            ntb_in_max_size: 4096,
+            ntb_out_max_size: 2048,
+        };
+        let req = Request {
+            direction: Direction::Out,
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: REQ_SET_NTB_INPUT_SIZE,
+            value: 0,
+            index: 0,
+            length: 4,
+        };
+
+        control.set_alternate_setting(InterfaceNumber::new(1), ALTERNATE_SETTING_ENABLED);
+        let res = control.control_out(req, &1024u32.to_le_bytes());
+        assert!(matches!(res, Some(OutResponse::Accepted)));
+        assert_eq!(shared.ntb_in_size.load(Ordering::Relaxed), 1024);
+
+        // Other interfaces don't reset it
+        control.set_alternate_setting(InterfaceNumber::new(0), ALTERNATE_SETTING_DISABLED);
+        assert_eq!(shared.ntb_in_size.load(Ordering::Relaxed), 1024);
+        control.set_alternate_setting(InterfaceNumber::new(1), ALTERNATE_SETTING_DISABLED);
+        assert_eq!(shared.ntb_in_size.load(Ordering::Relaxed), 4096);
+    }
+
+    #[test]
+    fn ntb_with_two_datagrams() {
+        let mut ntb = [0xaa; 64];
+        assert_eq!(build_ntb(7, &[&[1, 2, 3], &[4, 5, 6, 7, 8]], &mut ntb), (41, 2));
+
+        #[rustfmt::skip]
+        let expected: [u8; 41] = [
+            // NTH16: signature, header length, sequence, block length, NDP index
+            b'N', b'C', b'M', b'H', 12, 0, 7, 0, 41, 0, 12, 0,
+            // NDP16: signature, length, next NDP index
+            b'N', b'C', b'M', b'0', 20, 0, 0, 0,
+            // Datagram index and length entries, then the terminating zero entry
+            32, 0, 3, 0,
+            36, 0, 5, 0,
+            0, 0, 0, 0,
+            // Datagrams, each starting on a 4 byte boundary
+            1, 2, 3, 0,
+            4, 5, 6, 7, 8,
+        ];
+        assert_eq!(ntb[..41], expected);
+    }
+
+    #[test]
+    fn ntb_holds_only_datagrams_that_fit() {
+        let mut ntb = [0; 36];
+        assert_eq!(build_ntb(0, &[&[1, 2, 3], &[4, 5, 6, 7, 8]], &mut ntb), (31, 1));
+        assert_eq!(build_ntb(0, &[&[0; 64]], &mut ntb), (0, 0));
+    }
+}
@@ -34,6 +34,8 @@ const HID_REQ_SET_PROTOCOL: u8 = 0x0b;
 /// Configuration for the HID class.
 pub struct Config<'d> {
     /// HID report descriptor.
+    ///
+    /// See [`ReportDescriptorBuilder`] to build one.
     pub report_descriptor: &'d [u8],
 
     /// Handler for control requests.
@@ -550,3 +552,376 @@ impl<'d> Handler for Control<'d> {
         }
     }
 }
+
+/// Flags of an Input, Output or Feature main item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ItemFlags(pub u8);
+
+impl ItemFlags {
+    /// Data, array, absolute: the default, used for key code arrays.
+    pub const DATA_ARRAY_ABS: Self = Self(0x00);
+    /// Data, variable, absolute: one field per usage, used for buttons and axes.
+    pub const DATA_VAR_ABS: Self = Self(0x02);
+    /// Data, variable, relative: used for mouse movement.
+    pub const DATA_VAR_REL: Self = Self(0x06);
+    /// Constant: padding bits.
+    pub const CONSTANT: Self = Self(0x01);
+
+    /// Combine with the flags in `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Collection type of a Collection main item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CollectionType {
+    /// Group of axes.
+    Physical = 0x00,
+    /// Top level collection, like a mouse or keyboard.
+    Application = 0x01,
+    /// Group of related fields.
+    Logical = 0x02,
+    /// Report.
+    Report = 0x03,
+    /// Named array.
+    NamedArray = 0x04,
+    /// Usage switch.
+    UsageSwitch = 0x05,
+    /// Usage modifier.
+    UsageModifier = 0x06,
+}
+
+// Item types, bits 3:2 of the item prefix.
+const ITEM_MAIN: u8 = 0x00;
+const ITEM_GLOBAL: u8 = 0x04;
+const ITEM_LOCAL: u8 = 0x08;
+
+/// Builder for HID report descriptors, usable in const context.
+///
+/// Each method appends one short item, with the item prefix and the smallest data size that holds
+/// the value. Logical and physical extents are signed, so e.g. a logical maximum of 255 takes two
+/// bytes. The descriptor is built into a buffer of `N` bytes; building a descriptor that doesn't
+/// fit panics, which is a compile error when done in a `const`.
+///
+/// Build it into a `static`, so the descriptor lives as long as the [`Config`] that borrows it:
+///
+/// ```
+/// use embassy_usb::class::hid::{Config, ReportDescriptorBuilder};
+///
+/// static KEYBOARD: ReportDescriptorBuilder<64> = ReportDescriptorBuilder::keyboard();
+///
+/// fn hid_config() -> Config<'static> {
+///     Config {
+///         report_descriptor: KEYBOARD.as_bytes(),
+///         request_handler: None,
+///         poll_ms: 10,
+///         max_packet_size: 8,
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ReportDescriptorBuilder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for ReportDescriptorBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ReportDescriptorBuilder<N> {
+    /// Create an empty descriptor.
+    pub const fn new() -> Self {
+        Self { buf: [0; N], len: 0 }
+    }
+
+    /// The descriptor built so far, for [`Config::report_descriptor`].
+    pub const fn as_bytes(&self) -> &[u8] {
+        self.buf.split_at(self.len).0
+    }
+
+    const fn item(mut self, prefix: u8, data: u32, size: usize) -> Self {
+        ::core::assert!(self.len + 1 + size <= N, "HID report descriptor buffer too small");
+        let size_code = match size {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            _ => 3,
+        };
+        self.buf[self.len] = prefix | size_code;
+        let mut i = 0;
+        while i < size {
+            self.buf[self.len + 1 + i] = (data >> (8 * i)) as u8;
+            i += 1;
+        }
+        self.len += 1 + size;
+        self
+    }
+
+    const fn unsigned(self, prefix: u8, value: u32) -> Self {
+        let size = if value <= 0xFF {
+            1
+        } else if value <= 0xFFFF {
+            2
+        } else {
+            4
+        };
+        self.item(prefix, value, size)
+    }
+
+    const fn signed(self, prefix: u8, value: i32) -> Self {
+        let size = if value >= i8::MIN as i32 && value <= i8::MAX as i32 {
+            1
+        } else if value >= i16::MIN as i32 && value <= i16::MAX as i32 {
+            2
+        } else {
+            4
+        };
+        self.item(prefix, value as u32, size)
+    }
+
+    /// Usage Page global item.
+    pub const fn usage_page(self, page: u16) -> Self {
+        self.unsigned(ITEM_GLOBAL, page as u32)
+    }
+
+    /// Logical Minimum global item.
+    pub const fn logical_min(self, min: i32) -> Self {
+        self.signed(ITEM_GLOBAL | 0x10, min)
+    }
+
+    /// Logical Maximum global item.
+    pub const fn logical_max(self, max: i32) -> Self {
+        self.signed(ITEM_GLOBAL | 0x20, max)
+    }
+
+    /// Physical Minimum global item.
+    pub const fn physical_min(self, min: i32) -> Self {
+        self.signed(ITEM_GLOBAL | 0x30, min)
+    }
+
+    /// Physical Maximum global item.
+    pub const fn physical_max(self, max: i32) -> Self {
+        self.signed(ITEM_GLOBAL | 0x40, max)
+    }
+
+    /// Report Size global item, the size of a field in bits.
+    pub const fn report_size(self, bits: u32) -> Self {
+        self.unsigned(ITEM_GLOBAL | 0x70, bits)
+    }
+
+    /// Report ID global item. All reports of a descriptor using report IDs start with their ID.
+    pub const fn report_id(self, id: u8) -> Self {
+        self.unsigned(ITEM_GLOBAL | 0x80, id as u32)
+    }
+
+    /// Report Count global item, the number of fields.
+    pub const fn report_count(self, count: u32) -> Self {
+        self.unsigned(ITEM_GLOBAL | 0x90, count)
+    }
+
+    /// Usage local item.
+    pub const fn usage(self, usage: u16) -> Self {
+        self.unsigned(ITEM_LOCAL, usage as u32)
+    }
+
+    /// Usage Minimum local item.
+    pub const fn usage_min(self, usage: u16) -> Self {
+        self.unsigned(ITEM_LOCAL | 0x10, usage as u32)
+    }
+
+    /// Usage Maximum local item.
+    pub const fn usage_max(self, usage: u16) -> Self {
+        self.unsigned(ITEM_LOCAL | 0x20, usage as u32)
+    }
+
+    /// Input main item.
+    pub const fn input(self, flags: ItemFlags) -> Self {
+        self.unsigned(ITEM_MAIN | 0x80, flags.0 as u32)
+    }
+
+    /// Output main item.
+    pub const fn output(self, flags: ItemFlags) -> Self {
+        self.unsigned(ITEM_MAIN | 0x90, flags.0 as u32)
+    }
+
+    /// Feature main item.
+    pub const fn feature(self, flags: ItemFlags) -> Self {
+        self.unsigned(ITEM_MAIN | 0xB0, flags.0 as u32)
+    }
+
+    /// Collection main item. Must be closed with [`end_collection`](Self::end_collection).
+    pub const fn collection(self, kind: CollectionType) -> Self {
+        self.unsigned(ITEM_MAIN | 0xA0, kind as u32)
+    }
+
+    /// End Collection main item.
+    pub const fn end_collection(self) -> Self {
+        self.item(ITEM_MAIN | 0xC0, 0, 0)
+    }
+
+    /// Boot protocol keyboard, as in appendix B.1 of the HID specification. Needs 63 bytes.
+    ///
+    /// Input reports are 8 bytes: a modifier bitmap, a reserved byte and up to 6 key codes. Output
+    /// reports are 1 byte with the 5 LED states.
+    pub const fn keyboard() -> Self {
+        Self::new()
+            .usage_page(0x01) // Generic Desktop
+            .usage(0x06) // Keyboard
+            .collection(CollectionType::Application)
+            // Modifier keys
+            .usage_page(0x07) // Keyboard/Keypad
+            .usage_min(0xE0)
+            .usage_max(0xE7)
+            .logical_min(0)
+            .logical_max(1)
+            .report_size(1)
+            .report_count(8)
+            .input(ItemFlags::DATA_VAR_ABS)
+            // Reserved byte
+            .report_count(1)
+            .report_size(8)
+            .input(ItemFlags::CONSTANT)
+            // LEDs
+            .report_count(5)
+            .report_size(1)
+            .usage_page(0x08) // LEDs
+            .usage_min(0x01)
+            .usage_max(0x05)
+            .output(ItemFlags::DATA_VAR_ABS)
+            .report_count(1)
+            .report_size(3)
+            .output(ItemFlags::CONSTANT)
+            // Key codes
+            .report_count(6)
+            .report_size(8)
+            .logical_min(0)
+            .logical_max(0x65)
+            .usage_page(0x07) // Keyboard/Keypad
+            .usage_min(0x00)
+            .usage_max(0x65)
+            .input(ItemFlags::DATA_ARRAY_ABS)
+            .end_collection()
+    }
+
+    /// Boot protocol mouse, as in appendix B.2 of the HID specification. Needs 50 bytes.
+    ///
+    /// Input reports are 3 bytes: a bitmap of 3 buttons, then the signed X and Y movement.
+    pub const fn mouse() -> Self {
+        Self::new()
+            .usage_page(0x01) // Generic Desktop
+            .usage(0x02) // Mouse
+            .collection(CollectionType::Application)
+            .usage(0x01) // Pointer
+            .collection(CollectionType::Physical)
+            // Buttons
+            .usage_page(0x09) // Button
+            .usage_min(0x01)
+            .usage_max(0x03)
+            .logical_min(0)
+            .logical_max(1)
+            .report_count(3)
+            .report_size(1)
+            .input(ItemFlags::DATA_VAR_ABS)
+            .report_count(1)
+            .report_size(5)
+            .input(ItemFlags::CONSTANT)
+            // Movement
+            .usage_page(0x01) // Generic Desktop
+            .usage(0x30) // X
+            .usage(0x31) // Y
+            .logical_min(-127)
+            .logical_max(127)
+            .report_size(8)
+            .report_count(2)
+            .input(ItemFlags::DATA_VAR_REL)
+            .end_collection()
+            .end_collection()
+    }
+
+    /// Gamepad with 16 buttons and 4 axes. Needs 43 bytes.
+    ///
+    /// Input reports are 6 bytes: a little endian bitmap of the 16 buttons, then the signed X, Y, Z
+    /// and Rz axes.
+    pub const fn gamepad() -> Self {
+        Self::new()
+            .usage_page(0x01) // Generic Desktop
+            .usage(0x05) // Gamepad
+            .collection(CollectionType::Application)
+            // Buttons
+            .usage_page(0x09) // Button
+            .usage_min(0x01)
+            .usage_max(0x10)
+            .logical_min(0)
+            .logical_max(1)
+            .report_size(1)
+            .report_count(16)
+            .input(ItemFlags::DATA_VAR_ABS)
+            // Axes
+            .usage_page(0x01) // Generic Desktop
+            .usage(0x30) // X
+            .usage(0x31) // Y
+            .usage(0x32) // Z
+            .usage(0x35) // Rz
+            .logical_min(-127)
+            .logical_max(127)
+            .report_size(8)
+            .report_count(4)
+            .input(ItemFlags::DATA_VAR_ABS)
+            .end_collection()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_keyboard_descriptor() {
+        // HID 1.11 appendix B.1
+        #[rustfmt::skip]
+        const EXPECTED: [u8; 63] = [
+            0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25, 0x01,
+            0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x05, 0x75, 0x01,
+            0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91, 0x01, 0x95, 0x06,
+            0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, 0xC0,
+        ];
+        const KEYBOARD: ReportDescriptorBuilder<63> = ReportDescriptorBuilder::keyboard();
+
+        assert_eq!(KEYBOARD.as_bytes(), EXPECTED);
+    }
+
+    #[test]
+    fn boot_mouse_descriptor() {
+        // HID 1.11 appendix B.2
+        #[rustfmt::skip]
+        const EXPECTED: [u8; 50] = [
+            0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x09, 0x01, 0xA1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29, 0x03,
+            0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05, 0x81, 0x01,
+            0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7F, 0x75, 0x08, 0x95, 0x02, 0x81, 0x06,
+            0xC0, 0xC0,
+        ];
+        const MOUSE: ReportDescriptorBuilder<50> = ReportDescriptorBuilder::mouse();
+
+        assert_eq!(MOUSE.as_bytes(), EXPECTED);
+    }
+
+    #[test]
+    fn signed_extents_use_smallest_size() {
+        let desc = ReportDescriptorBuilder::<16>::new()
+            .logical_min(-1)
+            .logical_max(255)
+            .physical_min(-32768)
+            .physical_max(70000);
+        assert_eq!(
+            desc.as_bytes(),
+            [0x15, 0xFF, 0x26, 0xFF, 0x00, 0x36, 0x00, 0x80, 0x47, 0x70, 0x11, 0x01, 0x00]
+        );
+    }
+}
@@ -21,6 +21,397 @@ const PROTOCOL_NONE: u8 = 0x00;
 const MIDI_IN_SIZE: u8 = 0x06;
 const MIDI_OUT_SIZE: u8 = 0x09;
 
+// Code index numbers, the low nibble of the first byte of a USB-MIDI event packet.
+const CIN_SYSTEM_COMMON_2: u8 = 0x2;
+const CIN_SYSTEM_COMMON_3: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SYSEX_END_3: u8 = 0x7;
+const CIN_SINGLE_BYTE: u8 = 0xF;
+
+// Event packets are buffered up to this size before being written to the endpoint.
+const MAX_WRITE_SIZE: usize = 64;
+
+/// A MIDI message, as carried in a USB-MIDI event packet.
+///
+/// Channels are `0..16` and data bytes are 7 bits; higher bits are ignored when encoding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MidiMessage {
+    /// Note off.
+    NoteOff {
+        /// MIDI channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Release velocity.
+        velocity: u8,
+    },
+    /// Note on. A note on with velocity 0 is commonly used as note off.
+    NoteOn {
+        /// MIDI channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Velocity.
+        velocity: u8,
+    },
+    /// Polyphonic key pressure (aftertouch).
+    PolyPressure {
+        /// MIDI channel.
+        channel: u8,
+        /// Note number.
+        note: u8,
+        /// Pressure.
+        pressure: u8,
+    },
+    /// Control change.
+    ControlChange {
+        /// MIDI channel.
+        channel: u8,
+        /// Controller number.
+        control: u8,
+        /// Controller value.
+        value: u8,
+    },
+    /// Program change.
+    ProgramChange {
+        /// MIDI channel.
+        channel: u8,
+        /// Program number.
+        program: u8,
+    },
+    /// Channel pressure (aftertouch).
+    ChannelPressure {
+        /// MIDI channel.
+        channel: u8,
+        /// Pressure.
+        pressure: u8,
+    },
+    /// Pitch bend.
+    PitchBend {
+        /// MIDI channel.
+        channel: u8,
+        /// 14 bit value, `0x2000` is the center.
+        value: u16,
+    },
+    /// Up to 3 bytes of a system exclusive message.
+    ///
+    /// A SysEx message is split over several packets: the first part starts with `0xF0` and the
+    /// last part ends with `0xF7`.
+    SysEx {
+        /// Message bytes, only the first `len` are valid.
+        data: [u8; 3],
+        /// Number of valid bytes in `data`.
+        len: u8,
+    },
+    /// System common or system real-time message.
+    System {
+        /// Message bytes, starting with the status byte. Only the first `len` are valid.
+        data: [u8; 3],
+        /// Number of valid bytes in `data`.
+        len: u8,
+    },
+}
+
+impl MidiMessage {
+    /// Encode the message as a USB-MIDI event packet for virtual cable `cable`.
+    pub fn to_packet(&self, cable: u8) -> [u8; 4] {
+        let channel_packet = |status: u8, channel: u8, data1: u8, data2: u8| {
+            [
+                (cable << 4) | (status >> 4),
+                status | (channel & 0x0F),
+                data1 & 0x7F,
+                data2 & 0x7F,
+            ]
+        };
+
+        match *self {
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => channel_packet(0x80, channel, note, velocity),
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => channel_packet(0x90, channel, note, velocity),
+            MidiMessage::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => channel_packet(0xA0, channel, note, pressure),
+            MidiMessage::ControlChange {
+                channel,
+                control,
+                value,
+            } => channel_packet(0xB0, channel, control, value),
+            MidiMessage::ProgramChange { channel, program } => channel_packet(0xC0, channel, program, 0),
+            MidiMessage::ChannelPressure { channel, pressure } => channel_packet(0xD0, channel, pressure, 0),
+            MidiMessage::PitchBend { channel, value } => channel_packet(0xE0, channel, value as u8, (value >> 7) as u8),
+            MidiMessage::SysEx { data, len } => {
+                let len = len.clamp(1, 3);
+                let cin = if data[len as usize - 1] == 0xF7 {
+                    CIN_SYSEX + len
+                } else {
+                    CIN_SYSEX
+                };
+                packet(cable, cin, data, len)
+            }
+            MidiMessage::System { data, len } => {
+                let cin = match len {
+                    2 => CIN_SYSTEM_COMMON_2,
+                    3 => CIN_SYSTEM_COMMON_3,
+                    _ if data[0] >= 0xF8 => CIN_SINGLE_BYTE,
+                    _ => CIN_SYSEX_END_1,
+                };
+                packet(cable, cin, data, len.clamp(1, 3))
+            }
+        }
+    }
+
+    /// Decode a USB-MIDI event packet, returning its virtual cable number and message.
+    ///
+    /// Returns `None` for packets with a reserved code index number, such as the all-zero padding
+    /// some hosts send, and for channel messages whose status byte doesn't match the packet.
+    pub fn from_packet(packet: [u8; 4]) -> Option<(u8, Self)> {
+        let cable = packet[0] >> 4;
+        let cin = packet[0] & 0x0F;
+        let data = [packet[1], packet[2], packet[3]];
+        let channel = packet[1] & 0x0F;
+        let (data1, data2) = (packet[2] & 0x7F, packet[3] & 0x7F);
+
+        let message = match cin {
+            CIN_SYSTEM_COMMON_2 => MidiMessage::System { data, len: 2 },
+            CIN_SYSTEM_COMMON_3 => MidiMessage::System { data, len: 3 },
+            CIN_SYSEX => MidiMessage::SysEx { data, len: 3 },
+            CIN_SYSEX_END_1 if packet[1] == 0xF7 => MidiMessage::SysEx { data, len: 1 },
+            CIN_SYSEX_END_1 | CIN_SINGLE_BYTE => MidiMessage::System { data, len: 1 },
+            0x6 | CIN_SYSEX_END_3 => MidiMessage::SysEx {
+                data,
+                len: cin - CIN_SYSEX,
+            },
+            0x8..=0xE if packet[1] >> 4 == cin => match cin {
+                0x8 => MidiMessage::NoteOff {
+                    channel,
+                    note: data1,
+                    velocity: data2,
+                },
+                0x9 => MidiMessage::NoteOn {
+                    channel,
+                    note: data1,
+                    velocity: data2,
+                },
+                0xA => MidiMessage::PolyPressure {
+                    channel,
+                    note: data1,
+                    pressure: data2,
+                },
+                0xB => MidiMessage::ControlChange {
+                    channel,
+                    control: data1,
+                    value: data2,
+                },
+                0xC => MidiMessage::ProgramChange {
+                    channel,
+                    program: data1,
+                },
+                0xD => MidiMessage::ChannelPressure {
+                    channel,
+                    pressure: data1,
+                },
+                _ => MidiMessage::PitchBend {
+                    channel,
+                    value: u16::from(data1) | u16::from(data2) << 7,
+                },
+            },
+            _ => return None,
+        };
+        Some((cable, message))
+    }
+}
+
+/// Decode the USB-MIDI event packets in `data`, as read with `read_packet`.
+///
+/// Yields the virtual cable number and message of every packet, skipping packets that
+/// [`MidiMessage::from_packet`] can't decode.
+pub fn parse_packets(data: &[u8]) -> impl Iterator<Item = (u8, MidiMessage)> + '_ {
+    data.chunks_exact(4)
+        .filter_map(|p| MidiMessage::from_packet([p[0], p[1], p[2], p[3]]))
+}
+
+fn packet(cable: u8, cin: u8, data: [u8; 3], len: u8) -> [u8; 4] {
+    let mut packet = [(cable << 4) | cin, 0, 0, 0];
+    packet[1..1 + len as usize].copy_from_slice(&data[..len as usize]);
+    packet
+}
+
+// Number of bytes of the channel message starting with `status`.
+fn channel_message_len(status: u8) -> u8 {
+    match status >> 4 {
+        0xC | 0xD => 2,
+        _ => 3,
+    }
+}
+
+/// Splits a MIDI byte stream into USB-MIDI event packets.
+///
+/// Handles running status, SysEx messages of any length and real-time messages interleaved with
+/// other messages. Data bytes without a status byte and incomplete messages are dropped, except
+/// SysEx: a SysEx message cut off by another status byte or by the end of the stream is ended with
+/// `0xF7`, so the receiver never waits for the end of an aborted SysEx.
+struct PacketEncoder<'a> {
+    cable: u8,
+    data: core::slice::Iter<'a, u8>,
+    // Status byte that ended a SysEx, to be encoded after the SysEx end packet.
+    pending: Option<u8>,
+    running_status: Option<u8>,
+    msg: [u8; 3],
+    len: u8,
+    expected: u8,
+    sysex: bool,
+}
+
+impl<'a> PacketEncoder<'a> {
+    fn new(cable: u8, data: &'a [u8]) -> Self {
+        Self {
+            cable,
+            data: data.iter(),
+            pending: None,
+            running_status: None,
+            msg: [0; 3],
+            len: 0,
+            expected: 0,
+            sysex: false,
+        }
+    }
+
+    fn start(&mut self, status: u8, expected: u8) {
+        self.msg = [status, 0, 0];
+        self.len = 1;
+        self.expected = expected;
+    }
+
+    fn flush(&mut self, cin: u8) -> [u8; 4] {
+        let packet = packet(self.cable, cin, self.msg, self.len);
+        self.len = 0;
+        packet
+    }
+
+    // End an unterminated SysEx message with the bytes buffered so far and `0xF7`.
+    fn end_sysex(&mut self) -> Option<[u8; 4]> {
+        if !self.sysex {
+            return None;
+        }
+        self.sysex = false;
+        self.msg[self.len as usize] = 0xF7;
+        self.len += 1;
+        Some(self.flush(CIN_SYSEX + self.len))
+    }
+}
+
+impl Iterator for PacketEncoder<'_> {
+    type Item = [u8; 4];
+
+    fn next(&mut self) -> Option<[u8; 4]> {
+        loop {
+            let Some(b) = self.pending.take().or_else(|| self.data.next().copied()) else {
+                return self.end_sysex();
+            };
+
+            // Real-time messages may appear anywhere, even in the middle of another message.
+            if b >= 0xF8 {
+                return Some(packet(self.cable, CIN_SINGLE_BYTE, [b, 0, 0], 1));
+            }
+
+            if self.sysex {
+                if b < 0x80 || b == 0xF7 {
+                    self.msg[self.len as usize] = b;
+                    self.len += 1;
+                    if b == 0xF7 {
+                        self.sysex = false;
+                        return Some(self.flush(CIN_SYSEX + self.len));
+                    }
+                    if self.len == 3 {
+                        return Some(self.flush(CIN_SYSEX));
+                    }
+                    continue;
+                }
+                // Any other status byte aborts the SysEx message.
+                self.pending = Some(b);
+                return self.end_sysex();
+            }
+
+            match b {
+                0xF0 => {
+                    self.running_status = None;
+                    self.sysex = true;
+                    self.start(b, 0);
+                }
+                0xF1..=0xF7 => {
+                    self.running_status = None;
+                    self.len = 0;
+                    match b {
+                        0xF1 | 0xF3 => self.start(b, 2),
+                        0xF2 => self.start(b, 3),
+                        0xF6 => {
+                            self.start(b, 1);
+                            return Some(self.flush(CIN_SYSEX_END_1));
+                        }
+                        // Undefined, or an end of SysEx without a start.
+                        _ => {}
+                    }
+                }
+                0x80..=0xEF => {
+                    self.running_status = Some(b);
+                    self.start(b, channel_message_len(b));
+                }
+                _ => {
+                    if self.len == 0 {
+                        match self.running_status {
+                            Some(status) => self.start(status, channel_message_len(status)),
+                            None => continue,
+                        }
+                    }
+                    self.msg[self.len as usize] = b;
+                    self.len += 1;
+                    if self.len == self.expected {
+                        let cin = match self.msg[0] {
+                            0xF0.. => self.len,
+                            status => status >> 4,
+                        };
+                        return Some(self.flush(cin));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Write event packets, filling each USB packet and ending with a short packet.
+async fn write_events<E: EndpointIn>(ep: &mut E, packets: impl Iterator<Item = [u8; 4]>) -> Result<(), EndpointError> {
+    let size = usize::from(ep.info().max_packet_size).min(MAX_WRITE_SIZE) & !3;
+    let mut buf = [0; MAX_WRITE_SIZE];
+    let mut len = 0;
+    let mut written = false;
+
+    for packet in packets {
+        buf[len..len + 4].copy_from_slice(&packet);
+        len += 4;
+        if len == size {
+            ep.write(&buf[..len]).await?;
+            len = 0;
+            written = true;
+        }
+    }
+
+    if len > 0 || written {
+        ep.write(&buf[..len]).await?;
+    }
+    Ok(())
+}
+
 /// Packet level implementation of a USB MIDI device.
 ///
 /// This class can be used directly and it has the least overhead due to directly reading and
@@ -35,7 +426,7 @@ const MIDI_OUT_SIZE: u8 = 0x09;
 ///   terminated with a short packet, even if the bulk endpoint is used for stream-like data.
 pub struct MidiClass<'d, D: Driver<'d>> {
     read_ep: D::EndpointOut,
-    write_ep: D::EndpointIn,
+    sender: Sender<'d, D>,
 }
 
 impl<'d, D: Driver<'d>> MidiClass<'d, D> {
@@ -139,7 +530,10 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
         let write_ep = alt.endpoint_bulk_in(None, max_packet_size);
         alt.descriptor(CS_ENDPOINT, &endpoint_data[0..2 + n_in_jacks as usize]);
 
-        MidiClass { read_ep, write_ep }
+        MidiClass {
+            read_ep,
+            sender: Sender { write_ep },
+        }
     }
 
     /// Gets the maximum packet size in bytes.
@@ -150,10 +544,37 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
 
     /// Writes a single packet into the IN endpoint.
     pub async fn write_packet(&mut self, data: &[u8]) -> Result<(), EndpointError> {
-        self.write_ep.write(data).await
+        self.sender.write_packet(data).await
+    }
+
+    /// Sends a note on message on virtual cable 0.
+    pub async fn send_note_on(&mut self, channel: u8, note: u8, velocity: u8) -> Result<(), EndpointError> {
+        self.sender.send_note_on(channel, note, velocity).await
+    }
+
+    /// Sends a note off message on virtual cable 0.
+    pub async fn send_note_off(&mut self, channel: u8, note: u8, velocity: u8) -> Result<(), EndpointError> {
+        self.sender.send_note_off(channel, note, velocity).await
+    }
+
+    /// Sends a control change message on virtual cable 0.
+    pub async fn send_control_change(&mut self, channel: u8, control: u8, value: u8) -> Result<(), EndpointError> {
+        self.sender.send_control_change(channel, control, value).await
+    }
+
+    /// Sends a single message on virtual cable 0.
+    pub async fn send_message(&mut self, message: MidiMessage) -> Result<(), EndpointError> {
+        self.sender.send_message(message).await
+    }
+
+    /// Sends a raw MIDI byte stream on virtual cable 0, see [`Sender::send_raw`].
+    pub async fn send_raw(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        self.sender.send_raw(data).await
     }
 
     /// Reads a single packet from the OUT endpoint.
+    ///
+    /// The packet holds USB-MIDI event packets, which can be decoded with [`parse_packets`].
     pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
         self.read_ep.read(data).await
     }
@@ -167,12 +588,7 @@ impl<'d, D: Driver<'d>> MidiClass<'d, D> {
     ///
     /// This allows concurrently sending and receiving packets from separate tasks.
     pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
-        (
-            Sender {
-                write_ep: self.write_ep,
-            },
-            Receiver { read_ep: self.read_ep },
-        )
+        (self.sender, Receiver { read_ep: self.read_ep })
     }
 }
 
@@ -195,6 +611,52 @@ impl<'d, D: Driver<'d>> Sender<'d, D> {
         self.write_ep.write(data).await
     }
 
+    /// Sends a note on message on virtual cable 0.
+    pub async fn send_note_on(&mut self, channel: u8, note: u8, velocity: u8) -> Result<(), EndpointError> {
+        self.send_message(MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity,
+        })
+        .await
+    }
+
+    /// Sends a note off message on virtual cable 0.
+    pub async fn send_note_off(&mut self, channel: u8, note: u8, velocity: u8) -> Result<(), EndpointError> {
+        self.send_message(MidiMessage::NoteOff {
+            channel,
+            note,
+            velocity,
+        })
+        .await
+    }
+
+    /// Sends a control change message on virtual cable 0.
+    pub async fn send_control_change(&mut self, channel: u8, control: u8, value: u8) -> Result<(), EndpointError> {
+        self.send_message(MidiMessage::ControlChange {
+            channel,
+            control,
+            value,
+        })
+        .await
+    }
+
+    /// Sends a single message on virtual cable 0.
+    pub async fn send_message(&mut self, message: MidiMessage) -> Result<(), EndpointError> {
+        write_events(&mut self.write_ep, core::iter::once(message.to_packet(0))).await
+    }
+
+    /// Sends a raw MIDI byte stream on virtual cable 0.
+    ///
+    /// The stream is split into USB-MIDI event packets, so it can use running status and contain
+    /// SysEx messages of any length. Running status doesn't carry over between calls, and
+    /// incomplete messages at the end of `data` are dropped. A SysEx message must be sent in one
+    /// call: one that is unterminated at the end of `data`, or cut off by another status byte, is
+    /// ended with `0xF7`.
+    pub async fn send_raw(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        write_events(&mut self.write_ep, PacketEncoder::new(0, data)).await
+    }
+
     /// Waits for the USB host to enable this interface
     pub async fn wait_connection(&mut self) {
         self.write_ep.wait_enabled().await;
@@ -216,6 +678,8 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
     }
 
     /// Reads a single packet.
+    ///
+    /// The packet holds USB-MIDI event packets, which can be decoded with [`parse_packets`].
     pub async fn read_packet(&mut self, data: &mut [u8]) -> Result<usize, EndpointError> {
         self.read_ep.read(data).await
     }
@@ -225,3 +689,82 @@ impl<'d, D: Driver<'d>> Receiver<'d, D> {
         self.read_ep.wait_enabled().await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(cable: u8, data: &[u8]) -> heapless::Vec<[u8; 4], 8> {
+        PacketEncoder::new(cable, data).collect()
+    }
+
+    #[test]
+    fn note_on_packet() {
+        let note_on = MidiMessage::NoteOn {
+            channel: 2,
+            note: 60,
+            velocity: 100,
+        };
+        assert_eq!(note_on.to_packet(1), [0x19, 0x92, 60, 100]);
+        assert_eq!(MidiMessage::from_packet([0x19, 0x92, 60, 100]), Some((1, note_on)));
+    }
+
+    #[test]
+    fn running_status() {
+        assert_eq!(
+            encode(0, &[0x90, 60, 100, 62, 0, 0xF8, 64, 90]),
+            [
+                [0x09, 0x90, 60, 100],
+                [0x09, 0x90, 62, 0],
+                [0x0F, 0xF8, 0, 0],
+                [0x09, 0x90, 64, 90]
+            ]
+        );
+    }
+
+    #[test]
+    fn sysex_chunks() {
+        assert_eq!(
+            encode(0, &[0xF0, 1, 2, 3, 4, 0xF7]),
+            [[0x04, 0xF0, 1, 2], [0x07, 3, 4, 0xF7]]
+        );
+        assert_eq!(encode(0, &[0xF0, 1, 2, 0xF7]), [[0x04, 0xF0, 1, 2], [0x05, 0xF7, 0, 0]]);
+        assert_eq!(encode(0, &[0xF0, 1, 0xF7]), [[0x07, 0xF0, 1, 0xF7]]);
+    }
+
+    #[test]
+    fn aborted_sysex_is_terminated() {
+        assert_eq!(
+            encode(0, &[0xF0, 1, 2, 3, 0x90, 60, 100]),
+            [[0x04, 0xF0, 1, 2], [0x06, 3, 0xF7, 0], [0x09, 0x90, 60, 100]]
+        );
+        assert_eq!(encode(0, &[0xF0, 1]), [[0x07, 0xF0, 1, 0xF7]]);
+    }
+
+    #[test]
+    fn parse_sysex_packets() {
+        let packets = [0x04, 0xF0, 1, 2, 0x06, 3, 0xF7, 0, 0, 0, 0, 0];
+        let mut messages = parse_packets(&packets);
+        assert_eq!(
+            messages.next(),
+            Some((
+                0,
+                MidiMessage::SysEx {
+                    data: [0xF0, 1, 2],
+                    len: 3
+                }
+            ))
+        );
+        assert_eq!(
+            messages.next(),
+            Some((
+                0,
+                MidiMessage::SysEx {
+                    data: [3, 0xF7, 0],
+                    len: 2
+                }
+            ))
+        );
+        assert_eq!(messages.next(), None);
+    }
+}
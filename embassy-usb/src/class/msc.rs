@@ -0,0 +1,1068 @@
+//! USB Mass Storage class implementation, using the Bulk-Only Transport (BOT) with the SCSI
+//! transparent command set.
+//!
+//! The device exposes a single logical unit (LUN 0) backed by a [`BlockDevice`]. The
+//! `Get Max LUN` request is answered with 0 accordingly, and commands addressed to another LUN fail.
+//!
+//! Supported SCSI commands: TEST UNIT READY, REQUEST SENSE, INQUIRY, MODE SENSE(6),
+//! PREVENT ALLOW MEDIUM REMOVAL, READ CAPACITY(10), READ(10) and WRITE(10). Other commands fail
+//! with ILLEGAL REQUEST sense data.
+//!
+//! An invalid CBW starts the reset recovery of BOT 6.6.1: both bulk endpoints are stalled and no
+//! further commands are read until the host sends a Bulk-Only Mass Storage Reset. This needs a
+//! driver that supports [`Endpoint::set_stalled`]; with other drivers the endpoints just stop
+//! responding until the reset.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::WakerRegistration;
+
+use crate::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use crate::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use crate::types::InterfaceNumber;
+use crate::{Builder, Handler};
+
+/// This should be used as `device_class` when building the `UsbDevice`.
+pub const USB_CLASS_MSC: u8 = 0x08;
+
+const MSC_SUBCLASS_SCSI: u8 = 0x06;
+const MSC_PROTOCOL_BOT: u8 = 0x50;
+
+const REQ_GET_MAX_LUN: u8 = 0xFE;
+const REQ_BULK_ONLY_RESET: u8 = 0xFF;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_LEN: usize = 31;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+
+const CSW_LEN: usize = 13;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1A;
+const SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1E;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2A;
+
+const SENSE_KEY_NO_SENSE: u8 = 0x00;
+const SENSE_KEY_NOT_READY: u8 = 0x02;
+const SENSE_KEY_MEDIUM_ERROR: u8 = 0x03;
+const SENSE_KEY_ILLEGAL_REQUEST: u8 = 0x05;
+
+const ASC_NONE: u8 = 0x00;
+const ASC_WRITE_ERROR: u8 = 0x0C;
+const ASC_UNRECOVERED_READ_ERROR: u8 = 0x11;
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD_IN_CDB: u8 = 0x24;
+const ASC_MEDIUM_NOT_PRESENT: u8 = 0x3A;
+
+/// Block storage exposed by [`MscClass`].
+#[allow(async_fn_in_trait)]
+pub trait BlockDevice {
+    /// Error returned by block accesses. It is reported to the host as a medium error.
+    type Error;
+
+    /// Size of a block in bytes, usually 512.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks on the device, 0 if no medium is present.
+    fn block_count(&self) -> u32;
+
+    /// Read block `lba` into `buf`, which is [`block_size`](Self::block_size) bytes long.
+    async fn read_block(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write `buf`, which is [`block_size`](Self::block_size) bytes long, to block `lba`.
+    async fn write_block(&mut self, lba: u32, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Identification returned in the SCSI INQUIRY response.
+#[derive(Copy, Clone)]
+pub struct Inquiry {
+    /// Vendor identification, up to 8 ASCII characters.
+    pub vendor: &'static str,
+    /// Product identification, up to 16 ASCII characters.
+    pub product: &'static str,
+    /// Product revision, up to 4 ASCII characters.
+    pub revision: &'static str,
+}
+
+impl Default for Inquiry {
+    fn default() -> Self {
+        Self {
+            vendor: "Embassy",
+            product: "Mass Storage",
+            revision: "1.0",
+        }
+    }
+}
+
+/// Internal state for the Mass Storage class.
+pub struct State<'a> {
+    control: MaybeUninit<Control<'a>>,
+    shared: ControlShared,
+}
+
+impl<'a> Default for State<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> State<'a> {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            shared: ControlShared::new(),
+        }
+    }
+}
+
+/// Shared data between Control and the class.
+struct ControlShared {
+    // Set by a Bulk-Only Mass Storage Reset.
+    reset: AtomicBool,
+    waker: RefCell<WakerRegistration>,
+}
+
+impl ControlShared {
+    const fn new() -> Self {
+        Self {
+            reset: AtomicBool::new(false),
+            waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+}
+
+struct Control<'a> {
+    if_num: InterfaceNumber,
+    shared: &'a ControlShared,
+}
+
+impl<'a> Handler for Control<'a> {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.if_num.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_BULK_ONLY_RESET => {
+                // Every command is handled to completion, so there is no transfer state to reset.
+                // The reset only ends a reset recovery.
+                self.shared.reset.store(true, Ordering::Relaxed);
+                self.shared.waker.borrow_mut().wake();
+                Some(OutResponse::Accepted)
+            }
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+
+    fn control_in<'b>(&'b mut self, req: Request, buf: &'b mut [u8]) -> Option<InResponse<'b>> {
+        if (req.request_type, req.recipient, req.index)
+            != (RequestType::Class, Recipient::Interface, self.if_num.0 as u16)
+        {
+            return None;
+        }
+
+        match req.request {
+            REQ_GET_MAX_LUN => {
+                // Only LUN 0.
+                buf[0] = 0;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+/// Command Block Wrapper, sent by the host to start a command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cbw {
+    /// Tag to return in the [`Csw`] of the command.
+    pub tag: u32,
+    /// Number of bytes the host expects to transfer in the data phase.
+    pub data_len: u32,
+    /// Direction of the data phase, `true` for device to host.
+    pub dir_in: bool,
+    /// Logical unit the command is addressed to.
+    pub lun: u8,
+    /// SCSI command block, padded with zeros.
+    pub cb: [u8; 16],
+}
+
+impl Cbw {
+    /// Decode a CBW, returning `None` if it isn't valid and meaningful as defined in BOT 6.2.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() != CBW_LEN || u32::from_le_bytes(buf[0..4].try_into().unwrap()) != CBW_SIGNATURE {
+            return None;
+        }
+        if !(1..=16).contains(&(buf[14] & 0x1F)) {
+            return None;
+        }
+        Some(Self {
+            tag: u32::from_le_bytes(buf[4..8].try_into().unwrap()),
+            data_len: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            dir_in: buf[12] & 0x80 != 0,
+            lun: buf[13] & 0x0F,
+            cb: buf[15..31].try_into().unwrap(),
+        })
+    }
+}
+
+/// Status of a command, reported in the [`Csw`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CswStatus {
+    /// The command completed successfully.
+    Passed = 0x00,
+    /// The command failed, the host reads the reason with REQUEST SENSE.
+    Failed = 0x01,
+    /// The host and device disagree about the command phases, the host does a reset recovery.
+    PhaseError = 0x02,
+}
+
+/// Command Status Wrapper, sent by the device to end a command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Csw {
+    /// Tag of the [`Cbw`] of the command.
+    pub tag: u32,
+    /// Number of bytes of the data phase that were not transferred.
+    pub residue: u32,
+    /// Status of the command.
+    pub status: CswStatus,
+}
+
+impl Csw {
+    /// Status of the command of `cbw`, after `done` of the expected bytes were transferred.
+    pub fn new(cbw: &Cbw, done: u32, status: CswStatus) -> Self {
+        Self {
+            tag: cbw.tag,
+            residue: cbw.data_len.saturating_sub(done),
+            status,
+        }
+    }
+
+    /// Encode the CSW as sent on the bulk IN endpoint.
+    pub fn to_bytes(&self) -> [u8; CSW_LEN] {
+        let mut csw = [0; CSW_LEN];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        csw[8..12].copy_from_slice(&self.residue.to_le_bytes());
+        csw[12] = self.status as u8;
+        csw
+    }
+}
+
+/// USB Mass Storage class (Bulk-Only Transport).
+pub struct MscClass<'d, D: Driver<'d>> {
+    sender: Sender<'d, D>,
+    receiver: Receiver<'d, D>,
+    scsi: Scsi,
+}
+
+impl<'d, D: Driver<'d>> MscClass<'d, D> {
+    /// Create a new Mass Storage class.
+    ///
+    /// For full-speed devices, `max_packet_size` has to be 64.
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, max_packet_size: u16, inquiry: Inquiry) -> Self {
+        let mut func = builder.function(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BOT);
+
+        let mut iface = func.interface();
+        let if_num = iface.interface_number();
+        let mut alt = iface.alt_setting(USB_CLASS_MSC, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BOT, None);
+        let read_ep = alt.endpoint_bulk_out(None, max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(None, max_packet_size);
+
+        drop(func);
+
+        let control = state.control.write(Control {
+            if_num,
+            shared: &state.shared,
+        });
+        builder.handler(control);
+
+        MscClass {
+            sender: Sender { write_ep },
+            receiver: Receiver {
+                read_ep,
+                control: &state.shared,
+            },
+            scsi: Scsi::new(inquiry),
+        }
+    }
+
+    /// Split the class into a sender and receiver, to handle the commands without [`run`](Self::run).
+    pub fn split(self) -> (Sender<'d, D>, Receiver<'d, D>) {
+        (self.sender, self.receiver)
+    }
+
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.receiver.wait_connection().await;
+    }
+
+    /// Serve commands from the host, reading and writing blocks of `device`.
+    ///
+    /// `buf` holds one block and must be at least [`BlockDevice::block_size`] bytes long. Returns
+    /// when the endpoints report an error, e.g. [`EndpointError::Disabled`] when the host disconnects.
+    pub async fn run<B: BlockDevice>(&mut self, device: &mut B, buf: &mut [u8]) -> Result<(), EndpointError> {
+        let Self { sender, receiver, scsi } = self;
+        let (write_ep, read_ep) = (&mut sender.write_ep, &mut receiver.read_ep);
+        scsi.serve(write_ep, read_ep, receiver.control, device, buf).await
+    }
+}
+
+// SCSI state of the logical unit. The command handling only needs the bulk endpoints, not the
+// driver.
+struct Scsi {
+    inquiry: Inquiry,
+    sense: (u8, u8),
+}
+
+impl Scsi {
+    const fn new(inquiry: Inquiry) -> Self {
+        Self {
+            inquiry,
+            sense: (SENSE_KEY_NO_SENSE, ASC_NONE),
+        }
+    }
+
+    async fn serve<I: EndpointIn, O: EndpointOut, B: BlockDevice>(
+        &mut self,
+        write_ep: &mut I,
+        read_ep: &mut O,
+        control: &ControlShared,
+        device: &mut B,
+        buf: &mut [u8],
+    ) -> Result<(), EndpointError> {
+        assert!(buf.len() >= device.block_size());
+
+        loop {
+            let Some(cbw) = read_cbw(read_ep, control).await? else {
+                warn!("msc: invalid CBW, waiting for reset recovery");
+                stall_in(write_ep);
+                wait_reset(control).await;
+                continue;
+            };
+
+            let csw = self.handle_command(write_ep, read_ep, &cbw, device, buf).await?;
+            let done = cbw.data_len - csw.residue;
+            match cbw.dir_in {
+                true => finish_data_in(write_ep, &cbw, done).await?,
+                false => finish_data_out(read_ep, &cbw, done).await?,
+            }
+            write_ep.write(&csw.to_bytes()).await?;
+        }
+    }
+
+    // Run the data phase of a command, returning its status. The caller ends a data phase that was
+    // cut short.
+    async fn handle_command<I: EndpointIn, O: EndpointOut, B: BlockDevice>(
+        &mut self,
+        write_ep: &mut I,
+        read_ep: &mut O,
+        cbw: &Cbw,
+        device: &mut B,
+        buf: &mut [u8],
+    ) -> Result<Csw, EndpointError> {
+        if cbw.lun != 0 {
+            return Ok(self.fail(cbw, 0, SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB));
+        }
+
+        let cb = &cbw.cb;
+        let needs_medium = matches!(
+            cb[0],
+            SCSI_TEST_UNIT_READY | SCSI_READ_CAPACITY_10 | SCSI_READ_10 | SCSI_WRITE_10
+        );
+        if needs_medium && device.block_count() == 0 {
+            return Ok(self.fail(cbw, 0, SENSE_KEY_NOT_READY, ASC_MEDIUM_NOT_PRESENT));
+        }
+
+        match cb[0] {
+            SCSI_TEST_UNIT_READY | SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL => Ok(Csw::new(cbw, 0, CswStatus::Passed)),
+            SCSI_REQUEST_SENSE => {
+                let mut data = [0; 18];
+                data[0] = 0x70; // current error, fixed format
+                data[2] = self.sense.0;
+                data[7] = 10; // additional sense length
+                data[12] = self.sense.1;
+                self.sense = (SENSE_KEY_NO_SENSE, ASC_NONE);
+                self.respond(write_ep, cbw, &data).await
+            }
+            SCSI_INQUIRY => {
+                let mut data = [b' '; 36];
+                data[0] = 0x00; // direct access block device
+                data[1] = 0x80; // removable
+                data[2] = 0x04; // SPC-2
+                data[3] = 0x02; // response data format
+                data[4] = 31; // additional length
+                data[5..8].fill(0);
+                copy_ascii(&mut data[8..16], self.inquiry.vendor);
+                copy_ascii(&mut data[16..32], self.inquiry.product);
+                copy_ascii(&mut data[32..36], self.inquiry.revision);
+                self.respond(write_ep, cbw, &data).await
+            }
+            SCSI_MODE_SENSE_6 => {
+                // Mode parameter header only: no write protection, no block descriptors.
+                self.respond(write_ep, cbw, &[3, 0, 0, 0]).await
+            }
+            SCSI_READ_CAPACITY_10 => {
+                let last_lba = device.block_count() - 1;
+                let mut data = [0; 8];
+                data[0..4].copy_from_slice(&last_lba.to_be_bytes());
+                data[4..8].copy_from_slice(&(device.block_size() as u32).to_be_bytes());
+                self.respond(write_ep, cbw, &data).await
+            }
+            SCSI_READ_10 | SCSI_WRITE_10 => {
+                let lba = u32::from_be_bytes(cb[2..6].try_into().unwrap());
+                let count = u16::from_be_bytes(cb[7..9].try_into().unwrap()) as u32;
+                let block_size = device.block_size();
+
+                if !matches!(lba.checked_add(count), Some(end) if end <= device.block_count()) {
+                    return Ok(self.fail(cbw, 0, SENSE_KEY_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE));
+                }
+                if count as usize * block_size != cbw.data_len as usize || cbw.dir_in != (cb[0] == SCSI_READ_10) {
+                    return Ok(self.fail(cbw, 0, SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB));
+                }
+
+                let block = &mut buf[..block_size];
+                let mut done = 0;
+                for lba in lba..lba + count {
+                    if cb[0] == SCSI_READ_10 {
+                        if device.read_block(lba, block).await.is_err() {
+                            return Ok(self.fail(cbw, done, SENSE_KEY_MEDIUM_ERROR, ASC_UNRECOVERED_READ_ERROR));
+                        }
+                        write_data(write_ep, block).await?;
+                    } else {
+                        read_data(read_ep, block).await?;
+                        if device.write_block(lba, block).await.is_err() {
+                            done += block_size as u32;
+                            return Ok(self.fail(cbw, done, SENSE_KEY_MEDIUM_ERROR, ASC_WRITE_ERROR));
+                        }
+                    }
+                    done += block_size as u32;
+                }
+                Ok(Csw::new(cbw, done, CswStatus::Passed))
+            }
+            _ => {
+                debug!("msc: unsupported SCSI command {:02x}", cb[0]);
+                Ok(self.fail(cbw, 0, SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_COMMAND))
+            }
+        }
+    }
+
+    // Send `data`, truncated to the length the host asked for.
+    async fn respond<I: EndpointIn>(&mut self, write_ep: &mut I, cbw: &Cbw, data: &[u8]) -> Result<Csw, EndpointError> {
+        if !cbw.dir_in {
+            return Ok(self.fail(cbw, 0, SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_FIELD_IN_CDB));
+        }
+        let len = data.len().min(cbw.data_len as usize);
+        write_data(write_ep, &data[..len]).await?;
+        Ok(Csw::new(cbw, len as u32, CswStatus::Passed))
+    }
+
+    fn fail(&mut self, cbw: &Cbw, done: u32, key: u8, asc: u8) -> Csw {
+        self.sense = (key, asc);
+        Csw::new(cbw, done, CswStatus::Failed)
+    }
+}
+
+/// Mass Storage class sender, for the data phase of IN commands and the CSW.
+///
+/// You can obtain a `Sender` with [`MscClass::split`]
+pub struct Sender<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> Sender<'d, D> {
+    /// Send data of an IN command.
+    pub async fn write_data(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        write_data(&mut self.write_ep, data).await
+    }
+
+    /// End the data phase of an IN command that sent `done` of the `cbw.data_len` bytes the host
+    /// expects, terminating a shorter transfer with a short packet.
+    pub async fn finish_data(&mut self, cbw: &Cbw, done: u32) -> Result<(), EndpointError> {
+        finish_data_in(&mut self.write_ep, cbw, done).await
+    }
+
+    /// Send the status of a command.
+    pub async fn write_csw(&mut self, csw: &Csw) -> Result<(), EndpointError> {
+        self.write_ep.write(&csw.to_bytes()).await
+    }
+
+    /// Stall the bulk IN endpoint for a reset recovery, see [`Receiver::read_cbw`].
+    pub fn stall(&mut self) {
+        stall_in(&mut self.write_ep);
+    }
+}
+
+/// Mass Storage class receiver, for the CBW and the data phase of OUT commands.
+///
+/// You can obtain a `Receiver` with [`MscClass::split`]
+pub struct Receiver<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> Receiver<'d, D> {
+    /// Waits for the USB host to enable this interface
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await;
+    }
+
+    /// Read the CBW of the next command.
+    ///
+    /// Returns `None` for an invalid CBW and stalls the bulk OUT endpoint, starting a reset
+    /// recovery. Stall the IN endpoint too with [`Sender::stall`], then wait for the host to
+    /// finish the recovery with [`wait_reset`](Self::wait_reset) before reading the next CBW.
+    pub async fn read_cbw(&mut self) -> Result<Option<Cbw>, EndpointError> {
+        read_cbw(&mut self.read_ep, self.control).await
+    }
+
+    /// Wait for the Bulk-Only Mass Storage Reset ending a reset recovery.
+    pub async fn wait_reset(&mut self) {
+        wait_reset(self.control).await
+    }
+
+    /// Receive data of an OUT command.
+    pub async fn read_data(&mut self, data: &mut [u8]) -> Result<(), EndpointError> {
+        read_data(&mut self.read_ep, data).await
+    }
+
+    /// End the data phase of an OUT command that received `done` of the `cbw.data_len` bytes the
+    /// host sends, reading and discarding the rest.
+    pub async fn finish_data(&mut self, cbw: &Cbw, done: u32) -> Result<(), EndpointError> {
+        finish_data_out(&mut self.read_ep, cbw, done).await
+    }
+}
+
+async fn write_data<E: EndpointIn>(ep: &mut E, data: &[u8]) -> Result<(), EndpointError> {
+    let max_packet_size = ep.info().max_packet_size as usize;
+    for chunk in data.chunks(max_packet_size) {
+        ep.write(chunk).await?;
+    }
+    Ok(())
+}
+
+async fn read_data<E: EndpointOut>(ep: &mut E, data: &mut [u8]) -> Result<(), EndpointError> {
+    let max_packet_size = ep.info().max_packet_size as usize;
+    for chunk in data.chunks_mut(max_packet_size) {
+        ep.read(chunk).await?;
+    }
+    Ok(())
+}
+
+async fn finish_data_in<E: EndpointIn>(ep: &mut E, cbw: &Cbw, done: u32) -> Result<(), EndpointError> {
+    let max_packet_size = ep.info().max_packet_size as u32;
+    if done < cbw.data_len && done % max_packet_size == 0 {
+        ep.write(&[]).await?;
+    }
+    Ok(())
+}
+
+async fn finish_data_out<E: EndpointOut>(ep: &mut E, cbw: &Cbw, done: u32) -> Result<(), EndpointError> {
+    let max_packet_size = ep.info().max_packet_size as usize;
+    let mut remaining = cbw.data_len.saturating_sub(done) as usize;
+    let mut discard = [0; 512];
+    while remaining > 0 {
+        let n = ep.read(&mut discard[..max_packet_size]).await?;
+        if n == 0 {
+            break;
+        }
+        remaining = remaining.saturating_sub(n);
+    }
+    Ok(())
+}
+
+async fn read_cbw<E: EndpointOut>(ep: &mut E, control: &ControlShared) -> Result<Option<Cbw>, EndpointError> {
+    let mut buf = [0; 64];
+    let cbw = match ep.read(&mut buf).await {
+        Ok(n) => Cbw::parse(&buf[..n]),
+        Err(EndpointError::BufferOverflow) => None,
+        Err(e) => return Err(e),
+    };
+    if cbw.is_none() {
+        // Only a reset after this CBW ends the recovery.
+        control.reset.store(false, Ordering::Relaxed);
+        if ep.set_stalled(true).is_err() {
+            warn!("msc: driver can't stall the OUT endpoint");
+        }
+    }
+    Ok(cbw)
+}
+
+fn stall_in<E: EndpointIn>(ep: &mut E) {
+    if ep.set_stalled(true).is_err() {
+        warn!("msc: driver can't stall the IN endpoint");
+    }
+}
+
+async fn wait_reset(control: &ControlShared) {
+    poll_fn(|cx| {
+        if control.reset.swap(false, Ordering::Relaxed) {
+            return Poll::Ready(());
+        }
+        control.waker.borrow_mut().register(cx.waker());
+        Poll::Pending
+    })
+    .await
+}
+
+fn copy_ascii(dst: &mut [u8], s: &str) {
+    let n = s.len().min(dst.len());
+    dst[..n].copy_from_slice(&s.as_bytes()[..n]);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_futures::join::join;
+    use embassy_futures::{block_on, yield_now};
+
+    use super::*;
+    use crate::driver::{Direction, EndpointAddress, EndpointInfo, EndpointType, Unsupported};
+
+    const MAX_PACKET_SIZE: usize = 64;
+    const BLOCK_SIZE: usize = 128;
+
+    /// State of the bulk endpoints, as seen by the host.
+    #[derive(Default)]
+    struct Bulk {
+        /// Packets sent by the device, in order.
+        sent: RefCell<heapless::Vec<heapless::Vec<u8, MAX_PACKET_SIZE>, 16>>,
+        out_stalled: Cell<bool>,
+        in_stalled: Cell<bool>,
+    }
+
+    /// OUT endpoint that receives the given packets, then reports the endpoint as disabled.
+    struct MockEndpointOut<'a> {
+        info: EndpointInfo,
+        packets: &'a [&'a [u8]],
+        bulk: &'a Bulk,
+    }
+
+    impl Endpoint for MockEndpointOut<'_> {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+
+        async fn wait_enabled(&mut self) {}
+
+        fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+            self.bulk.out_stalled.set(stalled);
+            Ok(())
+        }
+    }
+
+    impl EndpointOut for MockEndpointOut<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+            let (packet, rest) = self.packets.split_first().ok_or(EndpointError::Disabled)?;
+            self.packets = rest;
+            if packet.len() > buf.len() {
+                return Err(EndpointError::BufferOverflow);
+            }
+            buf[..packet.len()].copy_from_slice(packet);
+            Ok(packet.len())
+        }
+    }
+
+    /// IN endpoint that records the packets it sends.
+    struct MockEndpointIn<'a> {
+        info: EndpointInfo,
+        bulk: &'a Bulk,
+    }
+
+    impl Endpoint for MockEndpointIn<'_> {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+
+        async fn wait_enabled(&mut self) {}
+
+        fn set_stalled(&mut self, stalled: bool) -> Result<(), Unsupported> {
+            self.bulk.in_stalled.set(stalled);
+            Ok(())
+        }
+    }
+
+    impl EndpointIn for MockEndpointIn<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+            let packet = heapless::Vec::from_slice(buf).map_err(|_| EndpointError::BufferOverflow)?;
+            self.bulk.sent.borrow_mut().push(packet).unwrap();
+            Ok(())
+        }
+    }
+
+    fn endpoint_info(dir: Direction) -> EndpointInfo {
+        EndpointInfo {
+            addr: EndpointAddress::from_parts(1, dir),
+            ep_type: EndpointType::Bulk,
+            max_packet_size: MAX_PACKET_SIZE as u16,
+            interval_ms: 0,
+        }
+    }
+
+    fn endpoints<'a>(packets: &'a [&'a [u8]], bulk: &'a Bulk) -> (MockEndpointIn<'a>, MockEndpointOut<'a>) {
+        let write_ep = MockEndpointIn {
+            info: endpoint_info(Direction::In),
+            bulk,
+        };
+        let read_ep = MockEndpointOut {
+            info: endpoint_info(Direction::Out),
+            packets,
+            bulk,
+        };
+        (write_ep, read_ep)
+    }
+
+    /// Four blocks of RAM, failing accesses to `fail_lba`. Without a medium it has no blocks.
+    struct RamDisk {
+        blocks: [[u8; BLOCK_SIZE]; 4],
+        fail_lba: Option<u32>,
+        medium: bool,
+    }
+
+    impl RamDisk {
+        fn new() -> Self {
+            Self {
+                blocks: [[0; BLOCK_SIZE]; 4],
+                fail_lba: None,
+                medium: true,
+            }
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        type Error = ();
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn block_count(&self) -> u32 {
+            match self.medium {
+                true => self.blocks.len() as u32,
+                false => 0,
+            }
+        }
+
+        async fn read_block(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), ()> {
+            if self.fail_lba == Some(lba) {
+                return Err(());
+            }
+            buf.copy_from_slice(&self.blocks[lba as usize]);
+            Ok(())
+        }
+
+        async fn write_block(&mut self, lba: u32, buf: &[u8]) -> Result<(), ()> {
+            if self.fail_lba == Some(lba) {
+                return Err(());
+            }
+            self.blocks[lba as usize].copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    // Serve the commands in `packets` until the host has nothing left to send.
+    fn serve(packets: &[&[u8]], disk: &mut impl BlockDevice) -> Bulk {
+        let bulk = Bulk::default();
+        let shared = ControlShared::new();
+        let (mut write_ep, mut read_ep) = endpoints(packets, &bulk);
+        let mut scsi = Scsi::new(Inquiry::default());
+        let mut buf = [0; BLOCK_SIZE];
+        assert_eq!(
+            block_on(scsi.serve(&mut write_ep, &mut read_ep, &shared, disk, &mut buf)),
+            Err(EndpointError::Disabled)
+        );
+        bulk
+    }
+
+    fn csw(tag: u32, residue: u32, status: CswStatus) -> [u8; CSW_LEN] {
+        Csw { tag, residue, status }.to_bytes()
+    }
+
+    fn cbw_bytes(tag: u32, data_len: u32, flags: u8, lun: u8, cb: &[u8]) -> [u8; CBW_LEN] {
+        let mut buf = [0; CBW_LEN];
+        buf[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        buf[4..8].copy_from_slice(&tag.to_le_bytes());
+        buf[8..12].copy_from_slice(&data_len.to_le_bytes());
+        buf[12] = flags;
+        buf[13] = lun;
+        buf[14] = cb.len() as u8;
+        buf[15..15 + cb.len()].copy_from_slice(cb);
+        buf
+    }
+
+    #[test]
+    fn cbw_decode() {
+        let read_10 = [SCSI_READ_10, 0, 0, 0, 0, 8, 0, 0, 2, 0];
+        let cbw = Cbw::parse(&cbw_bytes(0x1234_5678, 1024, 0x80, 0, &read_10)).unwrap();
+        assert_eq!(cbw.tag, 0x1234_5678);
+        assert_eq!(cbw.data_len, 1024);
+        assert!(cbw.dir_in);
+        assert_eq!(cbw.lun, 0);
+        assert_eq!(cbw.cb[..10], read_10);
+        assert_eq!(cbw.cb[10..], [0; 6]);
+
+        let cbw = Cbw::parse(&cbw_bytes(1, 512, 0x00, 3, &[SCSI_WRITE_10; 10])).unwrap();
+        assert!(!cbw.dir_in);
+        assert_eq!(cbw.lun, 3);
+    }
+
+    #[test]
+    fn cbw_rejects_invalid() {
+        let valid = cbw_bytes(1, 0, 0, 0, &[SCSI_TEST_UNIT_READY; 6]);
+        assert!(Cbw::parse(&valid).is_some());
+
+        // Short or long transfer
+        assert_eq!(Cbw::parse(&valid[..CBW_LEN - 1]), None);
+        let mut long = [0; CBW_LEN + 1];
+        long[..CBW_LEN].copy_from_slice(&valid);
+        assert_eq!(Cbw::parse(&long), None);
+
+        // Wrong signature
+        let mut bad = valid;
+        bad[3] = 0;
+        assert_eq!(Cbw::parse(&bad), None);
+
+        // Command block length out of range
+        let mut bad = valid;
+        bad[14] = 0;
+        assert_eq!(Cbw::parse(&bad), None);
+        bad[14] = 17;
+        assert_eq!(Cbw::parse(&bad), None);
+    }
+
+    #[test]
+    fn csw_encode_with_residue() {
+        let cbw = Cbw::parse(&cbw_bytes(0xaabb_ccdd, 4096, 0x80, 0, &[SCSI_READ_10; 10])).unwrap();
+
+        let csw = Csw::new(&cbw, 1024, CswStatus::Failed);
+        assert_eq!(csw.residue, 3072);
+        assert_eq!(
+            csw.to_bytes(),
+            [0x55, 0x53, 0x42, 0x53, 0xdd, 0xcc, 0xbb, 0xaa, 0x00, 0x0c, 0x00, 0x00, 0x01]
+        );
+
+        let csw = Csw::new(&cbw, 4096, CswStatus::Passed);
+        assert_eq!(csw.residue, 0);
+        assert_eq!(csw.to_bytes()[8..], [0, 0, 0, 0, 0]);
+
+        // More data than expected never underflows the residue.
+        assert_eq!(Csw::new(&cbw, 8192, CswStatus::PhaseError).residue, 0);
+    }
+
+    #[test]
+    fn write_10_then_read_10() {
+        let data: [u8; 2 * BLOCK_SIZE] = core::array::from_fn(|i| i as u8);
+        let mut chunks = data.chunks(MAX_PACKET_SIZE);
+        let mut chunk = || chunks.next().unwrap();
+        // Blocks 1 and 2
+        let write = cbw_bytes(1, 256, 0x00, 0, &[SCSI_WRITE_10, 0, 0, 0, 0, 1, 0, 0, 2, 0]);
+        let read = cbw_bytes(2, 256, 0x80, 0, &[SCSI_READ_10, 0, 0, 0, 0, 1, 0, 0, 2, 0]);
+        let packets: [&[u8]; 6] = [&write, chunk(), chunk(), chunk(), chunk(), &read];
+
+        let mut disk = RamDisk::new();
+        let bulk = serve(&packets, &mut disk);
+        assert_eq!(disk.blocks[0], [0; BLOCK_SIZE]);
+        assert_eq!(disk.blocks[1], data[..BLOCK_SIZE]);
+        assert_eq!(disk.blocks[2], data[BLOCK_SIZE..]);
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 6);
+        assert_eq!(sent[0][..], csw(1, 0, CswStatus::Passed));
+        for (packet, expected) in sent[1..5].iter().zip(data.chunks(MAX_PACKET_SIZE)) {
+            assert_eq!(packet[..], *expected);
+        }
+        // A complete data phase needs no zero-length packet
+        assert_eq!(sent[5][..], csw(2, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn failed_read_reports_residue_and_sense() {
+        let read = cbw_bytes(3, 256, 0x80, 0, &[SCSI_READ_10, 0, 0, 0, 0, 0, 0, 0, 2, 0]);
+        let sense = cbw_bytes(4, 18, 0x80, 0, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        let packets: [&[u8]; 3] = [&read, &sense, &sense];
+
+        let mut disk = RamDisk::new();
+        disk.blocks[0] = [0x5a; BLOCK_SIZE];
+        disk.fail_lba = Some(1);
+        let bulk = serve(&packets, &mut disk);
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 8);
+        assert_eq!(sent[0][..], [0x5a; MAX_PACKET_SIZE]);
+        assert_eq!(sent[1][..], [0x5a; MAX_PACKET_SIZE]);
+        // The data phase ends short on a packet boundary, so a zero-length packet terminates it
+        assert!(sent[2].is_empty());
+        assert_eq!(sent[3][..], csw(3, 128, CswStatus::Failed));
+
+        // The sense data reports the failure once
+        assert_eq!(sent[4].len(), 18);
+        assert_eq!(
+            (sent[4][2], sent[4][12]),
+            (SENSE_KEY_MEDIUM_ERROR, ASC_UNRECOVERED_READ_ERROR)
+        );
+        assert_eq!(sent[5][..], csw(4, 0, CswStatus::Passed));
+        assert_eq!((sent[6][2], sent[6][12]), (SENSE_KEY_NO_SENSE, ASC_NONE));
+        assert_eq!(sent[7][..], csw(4, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn short_responses() {
+        let inquiry = cbw_bytes(5, 96, 0x80, 0, &[SCSI_INQUIRY, 0, 0, 0, 96, 0]);
+        let unsupported = cbw_bytes(6, 64, 0x80, 0, &[0xff, 0, 0, 0, 0, 0]);
+        let sense = cbw_bytes(7, 18, 0x80, 0, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        let packets: [&[u8]; 3] = [&inquiry, &unsupported, &sense];
+
+        let bulk = serve(&packets, &mut RamDisk::new());
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 6);
+        // A short packet ends the data phase by itself
+        assert_eq!(sent[0].len(), 36);
+        assert_eq!(sent[0][8..16], *b"Embassy ");
+        assert_eq!(sent[1][..], csw(5, 60, CswStatus::Passed));
+        // No data at all needs a zero-length packet
+        assert!(sent[2].is_empty());
+        assert_eq!(sent[3][..], csw(6, 64, CswStatus::Failed));
+        assert_eq!(
+            (sent[4][2], sent[4][12]),
+            (SENSE_KEY_ILLEGAL_REQUEST, ASC_INVALID_COMMAND)
+        );
+        assert_eq!(sent[5][..], csw(7, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn read_capacity() {
+        let read_capacity = cbw_bytes(12, 8, 0x80, 0, &[SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let packets: [&[u8]; 1] = [&read_capacity];
+
+        let bulk = serve(&packets, &mut RamDisk::new());
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0][..], [0, 0, 0, 3, 0, 0, 0, BLOCK_SIZE as u8]);
+        assert_eq!(sent[1][..], csw(12, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn no_medium() {
+        let test_unit_ready = cbw_bytes(13, 0, 0x00, 0, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        let sense = cbw_bytes(14, 18, 0x80, 0, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        let read_capacity = cbw_bytes(15, 8, 0x80, 0, &[SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let read = cbw_bytes(16, 128, 0x80, 0, &[SCSI_READ_10, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        let prevent = cbw_bytes(17, 0, 0x00, 0, &[SCSI_PREVENT_ALLOW_MEDIUM_REMOVAL, 0, 0, 0, 1, 0]);
+        let packets: [&[u8]; 5] = [&test_unit_ready, &sense, &read_capacity, &read, &prevent];
+
+        let mut disk = RamDisk::new();
+        disk.medium = false;
+        let bulk = serve(&packets, &mut disk);
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 8);
+        assert_eq!(sent[0][..], csw(13, 0, CswStatus::Failed));
+        assert_eq!((sent[1][2], sent[1][12]), (SENSE_KEY_NOT_READY, ASC_MEDIUM_NOT_PRESENT));
+        assert_eq!(sent[2][..], csw(14, 0, CswStatus::Passed));
+        // No capacity is reported, not a last LBA of 0xFFFFFFFF
+        assert!(sent[3].is_empty());
+        assert_eq!(sent[4][..], csw(15, 8, CswStatus::Failed));
+        // The read fails as NOT READY rather than as an LBA out of range
+        assert!(sent[5].is_empty());
+        assert_eq!(sent[6][..], csw(16, 128, CswStatus::Failed));
+        assert_eq!(sent[7][..], csw(17, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn rejected_writes_discard_data() {
+        let data = [0xa5; MAX_PACKET_SIZE];
+        // Blocks 3 and 4, past the end of the disk
+        let out_of_range = cbw_bytes(8, 256, 0x00, 0, &[SCSI_WRITE_10, 0, 0, 0, 0, 3, 0, 0, 2, 0]);
+        // Blocks 0 and 1, failing on block 0
+        let failing = cbw_bytes(9, 256, 0x00, 0, &[SCSI_WRITE_10, 0, 0, 0, 0, 0, 0, 0, 2, 0]);
+        let test_unit_ready = cbw_bytes(10, 0, 0x00, 0, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        let packets: [&[u8]; 11] = [
+            &out_of_range,
+            &data,
+            &data,
+            &data,
+            &data,
+            &failing,
+            &data,
+            &data,
+            &data,
+            &data,
+            &test_unit_ready,
+        ];
+
+        let mut disk = RamDisk::new();
+        disk.fail_lba = Some(0);
+        let bulk = serve(&packets, &mut disk);
+        assert_eq!(disk.blocks, [[0; BLOCK_SIZE]; 4]);
+
+        // The data of both writes is discarded, so the next CBW is read correctly
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0][..], csw(8, 256, CswStatus::Failed));
+        assert_eq!(sent[1][..], csw(9, 128, CswStatus::Failed));
+        assert_eq!(sent[2][..], csw(10, 0, CswStatus::Passed));
+    }
+
+    #[test]
+    fn invalid_cbw_stalls_until_reset() {
+        let test_unit_ready = cbw_bytes(11, 0, 0x00, 0, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        let packets: [&[u8]; 2] = [&[0; CBW_LEN], &test_unit_ready];
+        let bulk = Bulk::default();
+        let shared = ControlShared::new();
+        let (mut write_ep, mut read_ep) = endpoints(&packets, &bulk);
+        let mut scsi = Scsi::new(Inquiry::default());
+        let mut control = Control {
+            if_num: InterfaceNumber::new(0),
+            shared: &shared,
+        };
+        let reset = Request {
+            direction: Direction::Out,
+            request_type: RequestType::Class,
+            recipient: Recipient::Interface,
+            request: REQ_BULK_ONLY_RESET,
+            value: 0,
+            index: 0,
+            length: 0,
+        };
+
+        // A reset before the invalid CBW doesn't end the recovery
+        assert!(matches!(control.control_out(reset, &[]), Some(OutResponse::Accepted)));
+
+        let host = async {
+            while !bulk.out_stalled.get() {
+                yield_now().await;
+            }
+            assert!(bulk.in_stalled.get());
+            assert!(bulk.sent.borrow().is_empty());
+            assert!(matches!(control.control_out(reset, &[]), Some(OutResponse::Accepted)));
+        };
+        let mut disk = RamDisk::new();
+        let mut buf = [0; BLOCK_SIZE];
+        let serve = scsi.serve(&mut write_ep, &mut read_ep, &shared, &mut disk, &mut buf);
+        let (res, ()) = block_on(join(serve, host));
+        assert_eq!(res, Err(EndpointError::Disabled));
+
+        let sent = bulk.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0][..], csw(11, 0, CswStatus::Passed));
+    }
+}